@@ -69,7 +69,7 @@ in
     };
 
     postInstall = ''
-      # Install assets (only the fonts)
+      # Install assets (the data files are fetched separately, see `just fetch-data`)
       mkdir -p $out/bin/assets
       cp -r ./assets/fonts $out/bin/assets/
       # Rename original binary (from cargo)
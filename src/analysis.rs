@@ -0,0 +1,239 @@
+use chrono::{DateTime, NaiveDateTime, Utc};
+
+use crate::archive::ElementHistory;
+use crate::config::Config;
+
+/// A jump between two consecutive element sets that drag alone doesn't explain
+#[derive(Debug, Clone)]
+pub struct ManeuverEvent {
+    pub epoch: NaiveDateTime,
+    pub delta_mean_motion: f64, // rev/day
+    pub expected_delta_mean_motion: f64, // rev/day, from the mean motion derivative
+    pub delta_eccentricity: f64,
+    pub delta_inclination: f64, // deg
+}
+
+#[derive(Debug, Clone)]
+pub struct DecayWarning {
+    pub epoch: NaiveDateTime,
+    pub mean_motion_dot: f64, // rev/day², as published (first derivative / 2)
+    pub growth_factor: f64, // over the decay window
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AnalysisReport {
+    pub sets: usize,
+    pub first_epoch: Option<NaiveDateTime>,
+    pub last_epoch: Option<NaiveDateTime>,
+    pub maneuvers: Vec<ManeuverEvent>,
+    pub decay: Option<DecayWarning>,
+}
+
+impl AnalysisReport {
+    /// Short markers shown next to the satellite name
+    pub fn badges(&self, now: DateTime<Utc>, config: &Config) -> Vec<&'static str> {
+        let mut badges = Vec::new();
+        let recent = |epoch: NaiveDateTime| {
+            let age = now.naive_utc().signed_duration_since(epoch).num_seconds() as f64 / 86400.0;
+            // Events after `now` (simulated time in the past) haven't happened yet
+            (0.0..=config.analysis_badge_recent_days).contains(&age)
+        };
+        if self.maneuvers.iter().any(|m| recent(m.epoch)) {
+            badges.push("MNV");
+        }
+        if self.decay.as_ref().is_some_and(|decay| recent(decay.epoch)) {
+            badges.push("DECAY");
+        }
+        badges
+    }
+}
+
+pub fn analyze(history: &ElementHistory, config: &Config) -> AnalysisReport {
+    let mut report = AnalysisReport {
+        sets: history.sets.len(),
+        first_epoch: history.sets.first().map(|set| set.elements.datetime),
+        last_epoch: history.sets.last().map(|set| set.elements.datetime),
+        ..Default::default()
+    };
+    if history.is_empty() {
+        return report;
+    }
+
+    for pair in history.sets.windows(2) {
+        let (a, b) = (&pair[0].elements, &pair[1].elements);
+        let dt = b.datetime.signed_duration_since(a.datetime).num_seconds() as f64 / 86400.0;
+        if dt <= 0.0 || dt > config.analysis_max_gap_days {
+            continue;
+        }
+        // Mean motion grows at 2*(ndot/2) under drag, use the average of both sets
+        let expected = (a.mean_motion_dot + b.mean_motion_dot) * dt;
+        let delta_mean_motion = b.mean_motion - a.mean_motion;
+        let delta_eccentricity = b.eccentricity - a.eccentricity;
+        let delta_inclination = b.inclination - a.inclination;
+        if (delta_mean_motion - expected).abs() > config.analysis_mean_motion_tolerance
+            || delta_eccentricity.abs() > config.analysis_eccentricity_tolerance
+            || delta_inclination.abs() > config.analysis_inclination_tolerance_deg
+        {
+            report.maneuvers.push(ManeuverEvent {
+                epoch: b.datetime,
+                delta_mean_motion,
+                expected_delta_mean_motion: expected,
+                delta_eccentricity,
+                delta_inclination,
+            });
+        }
+    }
+
+    let latest = &history.sets[history.sets.len() - 1].elements;
+    let window_start = latest.datetime - chrono::Duration::seconds((config.analysis_decay_window_days * 86400.0) as i64);
+    let oldest_in_window = history.sets.iter().find(|set| set.elements.datetime >= window_start).map(|set| &set.elements).unwrap_or(latest);
+    let growth_factor = if oldest_in_window.mean_motion_dot > 0.0 {
+        latest.mean_motion_dot / oldest_in_window.mean_motion_dot
+    } else {
+        1.0
+    };
+    if latest.mean_motion_dot > config.analysis_decay_mean_motion_dot
+        || (latest.mean_motion_dot > config.analysis_decay_mean_motion_dot / 10.0 && growth_factor > config.analysis_decay_growth_factor)
+    {
+        report.decay = Some(DecayWarning {
+            epoch: latest.datetime,
+            mean_motion_dot: latest.mean_motion_dot,
+            growth_factor,
+        });
+    }
+    report
+}
+
+pub fn print_report(name: &str, norad_id: u64, report: &AnalysisReport) {
+    println!("{} (NORAD {})", name, norad_id);
+    match (report.first_epoch, report.last_epoch) {
+        (Some(first), Some(last)) => println!("{} element sets from {} to {}", report.sets, first, last),
+        _ => {
+            println!("No element sets archived");
+            return;
+        }
+    }
+    if report.maneuvers.is_empty() {
+        println!("No maneuvers detected");
+    }
+    for maneuver in &report.maneuvers {
+        println!(
+            "Possible maneuver at {}: dn = {:+.6} rev/day (drag expects {:+.6}), de = {:+.6}, di = {:+.4} deg",
+            maneuver.epoch, maneuver.delta_mean_motion, maneuver.expected_delta_mean_motion, maneuver.delta_eccentricity, maneuver.delta_inclination
+        );
+    }
+    match &report.decay {
+        Some(decay) => println!(
+            "Decaying: ndot/2 = {:.6} rev/day² at {} (x{:.1} over the decay window), reentry may be imminent",
+            decay.mean_motion_dot, decay.epoch, decay.growth_factor
+        ),
+        None => println!("No signs of imminent reentry"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ISS_LINE1: &str = "1 25544U 98067A   20194.88612269 -.00002218  00000-0 -31515-4 0  9992";
+    const ISS_LINE2: &str = "2 25544  51.6461 221.2784 0001413  89.1723 280.4612 15.49507896236008";
+
+    /// Daily element sets starting at the ISS epoch, each changed by `modify(day, elements)`
+    fn history(days: &[i64], modify: impl Fn(i64, &mut sgp4::Elements)) -> ElementHistory {
+        let base = sgp4::Elements::from_tle(None, ISS_LINE1.as_bytes(), ISS_LINE2.as_bytes()).unwrap();
+        let elements = days
+            .iter()
+            .map(|day| {
+                let mut elements = base.clone();
+                elements.datetime += chrono::Duration::days(*day);
+                modify(*day, &mut elements);
+                elements
+            })
+            .collect();
+        ElementHistory::from_elements(elements)
+    }
+
+    fn maneuver_at(epoch: &str) -> ManeuverEvent {
+        ManeuverEvent {
+            epoch: epoch.parse().unwrap(),
+            delta_mean_motion: 0.01,
+            expected_delta_mean_motion: 0.0,
+            delta_eccentricity: 0.0,
+            delta_inclination: 0.0,
+        }
+    }
+
+    #[test]
+    fn steady_drag_is_not_a_maneuver() {
+        let report = analyze(&history(&[0, 1, 2, 3], |_, _| ()), &Config::default());
+        assert_eq!(report.sets, 4);
+        assert!(report.maneuvers.is_empty());
+        assert!(report.decay.is_none());
+    }
+
+    #[test]
+    fn detects_mean_motion_jump() {
+        let report = analyze(&history(&[0, 1, 2, 3], |day, elements| if day >= 2 { elements.mean_motion += 0.01 }), &Config::default());
+        assert_eq!(report.maneuvers.len(), 1);
+        assert_eq!(report.maneuvers[0].epoch, report.first_epoch.unwrap() + chrono::Duration::days(2));
+        assert!((report.maneuvers[0].delta_mean_motion - 0.01).abs() < 1e-9);
+    }
+
+    #[test]
+    fn detects_plane_change() {
+        let report = analyze(&history(&[0, 1], |day, elements| if day == 1 { elements.inclination += 0.05 }), &Config::default());
+        assert_eq!(report.maneuvers.len(), 1);
+    }
+
+    #[test]
+    fn ignores_element_sets_too_far_apart() {
+        let config = Config::default();
+        let gap = config.analysis_max_gap_days as i64 + 1;
+        let report = analyze(&history(&[0, gap], |day, elements| if day > 0 { elements.mean_motion += 0.01 }), &config);
+        assert!(report.maneuvers.is_empty());
+    }
+
+    #[test]
+    fn detects_decay_from_high_or_growing_drag() {
+        let config = Config::default();
+        let report = analyze(&history(&[0, 1], |_, elements| elements.mean_motion_dot = 0.003), &config);
+        assert!(report.decay.is_some());
+        // Below the threshold but more than tripled over the window
+        let report = analyze(
+            &history(&[0, 1, 2], |day, elements| elements.mean_motion_dot = if day == 2 { 0.001 } else { 0.0003 }),
+            &config,
+        );
+        assert!((report.decay.unwrap().growth_factor - 0.001 / 0.0003).abs() < 1e-9);
+    }
+
+    #[test]
+    fn empty_history_has_no_findings() {
+        let report = analyze(&ElementHistory::default(), &Config::default());
+        assert_eq!(report.sets, 0);
+        assert!(report.first_epoch.is_none());
+        assert!(report.decay.is_none());
+    }
+
+    #[test]
+    fn maneuver_badge_only_for_recent_past_maneuvers() {
+        let config = Config::default();
+        let now: DateTime<Utc> = "2024-03-15T00:00:00Z".parse().unwrap();
+        let badges = |epoch: &str| AnalysisReport { maneuvers: vec![maneuver_at(epoch)], ..Default::default() }.badges(now, &config);
+        assert_eq!(badges("2024-03-10T00:00:00"), vec!["MNV"]);
+        assert!(badges("2024-02-01T00:00:00").is_empty());
+        assert!(badges("2024-03-20T00:00:00").is_empty());
+    }
+
+    #[test]
+    fn decay_badge_only_for_recent_past_warnings() {
+        let config = Config::default();
+        let now: DateTime<Utc> = "2024-03-15T00:00:00Z".parse().unwrap();
+        let badges = |epoch: &str| {
+            let decay = DecayWarning { epoch: epoch.parse().unwrap(), mean_motion_dot: 0.003, growth_factor: 1.0 };
+            AnalysisReport { decay: Some(decay), ..Default::default() }.badges(now, &config)
+        };
+        assert_eq!(badges("2024-03-10T00:00:00"), vec!["DECAY"]);
+        assert!(badges("2024-02-01T00:00:00").is_empty());
+        assert!(badges("2024-03-20T00:00:00").is_empty());
+    }
+}
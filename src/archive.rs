@@ -0,0 +1,186 @@
+use chrono::{DateTime, Utc};
+use homedir::my_home;
+use std::collections::HashSet;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+
+use crate::tle;
+
+/// An element set together with its initialized SGP4 constants
+#[derive(Clone)]
+pub struct ElementSet {
+    pub elements: sgp4::Elements,
+    pub constants: sgp4::Constants,
+}
+
+/// Every known element set of one object, sorted by epoch
+#[derive(Clone, Default)]
+pub struct ElementHistory {
+    pub sets: Vec<ElementSet>,
+}
+
+impl ElementHistory {
+    pub fn from_elements(mut elements: Vec<sgp4::Elements>) -> Self {
+        elements.sort_by_key(|e| e.datetime);
+        elements.dedup_by_key(|e| e.datetime);
+        let sets = elements
+            .into_iter()
+            .filter_map(|elements| {
+                let constants = sgp4::Constants::from_elements_afspc_compatibility_mode(&elements).ok()?;
+                Some(ElementSet { elements, constants })
+            })
+            .collect();
+        Self { sets }
+    }
+
+    /// Index of the element set whose epoch is closest to `date`
+    pub fn closest_index(&self, date: DateTime<Utc>) -> usize {
+        let date = date.naive_utc();
+        let i = self.sets.partition_point(|set| set.elements.datetime < date);
+        if i == 0 {
+            return 0;
+        }
+        if i >= self.sets.len() {
+            return self.sets.len() - 1;
+        }
+        let before = date.signed_duration_since(self.sets[i - 1].elements.datetime);
+        let after = self.sets[i].elements.datetime.signed_duration_since(date);
+        if before <= after {
+            i - 1
+        } else {
+            i
+        }
+    }
+
+    /// Element set whose epoch is closest to `date`, None when the history is empty
+    pub fn closest(&self, date: DateTime<Utc>) -> Option<&ElementSet> {
+        self.sets.get(self.closest_index(date))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sets.is_empty()
+    }
+}
+
+pub fn archive_dir() -> PathBuf {
+    let mut path = my_home().unwrap().expect("couldn't get home directory");
+    path.push(".config/ontake/tasogare/archive");
+    path
+}
+
+fn archive_path(norad_id: u64) -> PathBuf {
+    let mut path = archive_dir();
+    path.push(format!("{}.tle", norad_id));
+    path
+}
+
+/// Append every element set of a fetched listing to the per-object archive files,
+/// skipping sets that are already archived
+pub fn archive_tle(tle_data: &str) {
+    if let Err(err) = fs::create_dir_all(archive_dir()) {
+        eprintln!("Failed to create TLE archive directory: {}", err);
+        return;
+    }
+    for (name, line1, line2) in tle::split_tle(tle_data) {
+        let norad_id = match sgp4::Elements::from_tle(None, line1.as_bytes(), line2.as_bytes()) {
+            Ok(elements) => elements.norad_id,
+            Err(_) => continue,
+        };
+        let path = archive_path(norad_id);
+        let known: HashSet<String> = match fs::read_to_string(&path) {
+            Ok(contents) => tle::split_tle(&contents).into_iter().map(|(_, line1, _)| line1).collect(),
+            Err(_) => HashSet::new(),
+        };
+        if known.contains(&line1) {
+            continue;
+        }
+        let written = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .and_then(|mut file| file.write_all(format!("{}\n{}\n{}\n", name, line1, line2).as_bytes()));
+        if written.is_err() {
+            eprintln!("Failed to archive TLE for {}", name);
+        }
+    }
+}
+
+/// Every archived element set of one object
+pub fn load_history(norad_id: u64) -> Vec<sgp4::Elements> {
+    match fs::read_to_string(archive_path(norad_id)) {
+        Ok(contents) => tle::parse_tle(&contents),
+        Err(_) => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// ISS elements with the epoch replaced, e.g. "20192.00000000" for 2020-07-10T00:00:00Z
+    fn elements_at(epoch: &str) -> sgp4::Elements {
+        let line1 = format!("1 25544U 98067A   {} -.00002218  00000-0 -31515-4 0  999", epoch);
+        let checksum = line1.chars().map(|c| c.to_digit(10).unwrap_or((c == '-') as u32)).sum::<u32>() % 10;
+        let line1 = format!("{}{}", line1, checksum);
+        let line2 = "2 25544  51.6461 221.2784 0001413  89.1723 280.4612 15.49507896236008";
+        sgp4::Elements::from_tle(None, line1.as_bytes(), line2.as_bytes()).unwrap()
+    }
+
+    fn history() -> ElementHistory {
+        // Out of order and with a duplicate epoch
+        ElementHistory::from_elements(vec![
+            elements_at("20196.00000000"),
+            elements_at("20190.00000000"),
+            elements_at("20192.00000000"),
+            elements_at("20190.00000000"),
+        ])
+    }
+
+    fn date(date: &str) -> DateTime<Utc> {
+        date.parse().unwrap()
+    }
+
+    #[test]
+    fn history_is_sorted_and_deduplicated() {
+        let history = history();
+        assert_eq!(history.sets.len(), 3);
+        assert!(history.sets.windows(2).all(|pair| pair[0].elements.datetime < pair[1].elements.datetime));
+    }
+
+    #[test]
+    fn closest_index_clamps_to_the_ends() {
+        let history = history();
+        assert_eq!(history.closest_index(date("2020-01-01T00:00:00Z")), 0);
+        assert_eq!(history.closest_index(date("2021-01-01T00:00:00Z")), 2);
+    }
+
+    #[test]
+    fn closest_index_picks_the_nearest_epoch() {
+        let history = history();
+        // Epochs on 2020-07-08, 2020-07-10 and 2020-07-14
+        assert_eq!(history.closest_index(date("2020-07-08T00:00:00Z")), 0);
+        assert_eq!(history.closest_index(date("2020-07-08T23:00:00Z")), 0);
+        assert_eq!(history.closest_index(date("2020-07-09T01:00:00Z")), 1);
+        assert_eq!(history.closest_index(date("2020-07-13T00:00:00Z")), 2);
+        assert_eq!(history.closest(date("2020-07-14T00:00:00Z")).unwrap().elements.datetime, date("2020-07-14T00:00:00Z").naive_utc());
+    }
+
+    #[test]
+    fn closest_index_prefers_the_earlier_epoch_on_ties() {
+        assert_eq!(history().closest_index(date("2020-07-12T00:00:00Z")), 1);
+    }
+
+    #[test]
+    fn single_set_is_always_closest() {
+        let history = ElementHistory::from_elements(vec![elements_at("20192.00000000")]);
+        assert_eq!(history.closest_index(date("2020-01-01T00:00:00Z")), 0);
+        assert_eq!(history.closest_index(date("2021-01-01T00:00:00Z")), 0);
+    }
+
+    #[test]
+    fn empty_history_has_no_closest_set() {
+        let history = ElementHistory::from_elements(Vec::new());
+        assert!(history.closest(date("2020-07-10T00:00:00Z")).is_none());
+    }
+}
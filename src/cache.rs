@@ -0,0 +1,72 @@
+use chrono::{DateTime, Utc};
+use homedir::my_home;
+use std::fs;
+use std::path::PathBuf;
+
+pub fn is_url(source: &str) -> bool {
+    source.starts_with("http://") || source.starts_with("https://")
+}
+
+/// Downloaded copy of a source, in ~/.config/ontake/tasogare
+pub fn cache_path(name: &str) -> PathBuf {
+    let mut path = my_home().unwrap().expect("couldn't get home directory");
+    path.push(".config/ontake/tasogare");
+    path.push(name);
+    path
+}
+
+/// File to read a source from: the cache for URLs, the source itself for local paths
+pub fn source_path(source: &str, name: &str) -> PathBuf {
+    if is_url(source) {
+        cache_path(name)
+    } else {
+        PathBuf::from(source)
+    }
+}
+
+/// Download a URL source to the cache if the cache is older than `max_age_seconds`. The cache is only
+/// replaced when `valid` accepts the body, so a failed download keeps the previous copy
+pub async fn refresh(source: &str, name: &str, max_age_seconds: i64, valid: impl Fn(&str) -> bool) {
+    if !is_url(source) {
+        return;
+    }
+    let path = cache_path(name);
+    if let Ok(modified) = fs::metadata(&path).and_then(|metadata| metadata.modified()) {
+        let modified: DateTime<Utc> = modified.into();
+        if Utc::now().signed_duration_since(modified).num_seconds() < max_age_seconds {
+            return;
+        }
+    }
+    println!("Fetching {} from {}", name, source);
+    let body = match reqwest::get(source).await.and_then(|response| response.error_for_status()) {
+        Ok(response) => response.text().await,
+        Err(err) => Err(err),
+    };
+    match body {
+        Ok(body) if !valid(&body) => eprintln!("Couldn't parse {} from {}, keeping the cached copy", name, source),
+        Ok(body) => {
+            if fs::write(&path, body).is_err() {
+                eprintln!("Failed to write {} to {}", name, path.display());
+            }
+        }
+        Err(err) => eprintln!("Failed to fetch {}: {}", name, err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognizes_urls() {
+        assert!(is_url("https://celestrak.org/pub/satcat.csv"));
+        assert!(is_url("http://localhost:8000/transmitters.json"));
+        assert!(!is_url("/home/user/satcat.csv"));
+        assert!(!is_url("satcat.csv"));
+    }
+
+    #[test]
+    fn local_sources_are_read_in_place() {
+        assert_eq!(source_path("/data/satcat.csv", "SATCAT"), PathBuf::from("/data/satcat.csv"));
+    }
+}
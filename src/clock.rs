@@ -0,0 +1,44 @@
+use bevy::prelude::*;
+use chrono::{DateTime, Duration, Utc};
+
+/// Simulated time used for every prediction, so that past passes can be replayed
+#[derive(Resource, Clone, Debug)]
+pub struct SimClock {
+    pub origin_real: DateTime<Utc>,
+    pub origin_sim: DateTime<Utc>,
+    pub rate: f64,
+}
+
+impl SimClock {
+    pub fn realtime() -> Self {
+        Self::starting_at(Utc::now())
+    }
+
+    pub fn starting_at(date: DateTime<Utc>) -> Self {
+        Self {
+            origin_real: Utc::now(),
+            origin_sim: date,
+            rate: 1.0,
+        }
+    }
+
+    pub fn now(&self) -> DateTime<Utc> {
+        let elapsed = Utc::now().signed_duration_since(self.origin_real);
+        self.origin_sim + Duration::microseconds((elapsed.num_microseconds().unwrap_or(0) as f64 * self.rate) as i64)
+    }
+
+    pub fn jump_to(&mut self, date: DateTime<Utc>) {
+        self.origin_real = Utc::now();
+        self.origin_sim = date;
+    }
+
+    pub fn set_rate(&mut self, rate: f64) {
+        let now = self.now();
+        self.jump_to(now);
+        self.rate = rate;
+    }
+}
+
+/// Bumped whenever cached satellite tracks must be recomputed (e.g. after a time jump)
+#[derive(Resource, Default, Debug)]
+pub struct TrackGeneration(pub u64);
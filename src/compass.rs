@@ -0,0 +1,125 @@
+use bevy::{prelude::*, sprite::Anchor};
+use std::f32::consts::PI;
+
+use crate::{config, hexstr2color, view};
+
+const CARDINALS: [&str; 8] = ["N", "NE", "E", "SE", "S", "SW", "W", "NW"];
+
+/// Anchor putting a label just outside the dome in the direction of an azimuth (radians)
+fn outward_anchor(azimuth: f32) -> Anchor {
+    let direction = Vec2::new(azimuth.sin(), azimuth.cos());
+    Anchor::Custom(-direction / direction.x.abs().max(direction.y.abs()) * 0.5)
+}
+
+/// Azimuths (degrees) of the degree labels every `step` degrees, at least 5°, leaving the directions that have a letter to the letter
+fn azimuth_label_degrees(step: f32, letters: usize) -> Vec<f32> {
+    let step = step.max(5.0);
+    let mut labels = Vec::new();
+    let mut degrees = step;
+    while degrees < 360.0 {
+        let offset = (degrees / (360.0 / letters as f32)).fract();
+        if offset > 1e-3 && offset < 1.0 - 1e-3 {
+            labels.push(degrees);
+        }
+        degrees += step;
+    }
+    labels
+}
+
+/// Compass letters and azimuth degrees around the horizon, and elevations along the altitude rings
+pub fn setup_compass(mut commands: Commands, asset_server: Res<AssetServer>, loaded_config: Res<config::Config>) {
+    let text_font = TextFont {
+        font: asset_server.load("fonts/FiraMono-Bold.ttf"),
+        font_size: loaded_config.sat_name_font_size,
+        ..Default::default()
+    };
+    let rotation = loaded_config.user_azimuth.to_radians();
+    let mut spawn_label = |text: String, color: &str, azimuth: f32, elevation: f32, anchor: Anchor| {
+        let (x, y) = loaded_config.sky_projection.project((azimuth - rotation) as f64, elevation as f64, loaded_config.scene_radius);
+        commands.spawn((
+            Text2d::new(text),
+            text_font.clone(),
+            TextColor(hexstr2color(&color.to_string())),
+            anchor,
+            Transform::from_xyz(x, y, -60.).with_rotation(Quat::from_rotation_z(rotation)),
+            view::DomeLabel { azimuth, elevation, scene_radius: loaded_config.scene_radius },
+        ));
+    };
+
+    let letters = if loaded_config.compass_intercardinals { 8 } else { 4 };
+    for (i, letter) in CARDINALS.iter().enumerate().step_by(8 / letters) {
+        let azimuth = i as f32 * PI / 4.0;
+        let color = if i == 0 { &loaded_config.north_color } else { &loaded_config.compass_color };
+        spawn_label(letter.to_string(), color, azimuth, 0.0, outward_anchor(azimuth));
+    }
+
+    if loaded_config.azimuth_label_degrees > 0.0 {
+        for degrees in azimuth_label_degrees(loaded_config.azimuth_label_degrees, letters) {
+            let azimuth = degrees.to_radians();
+            spawn_label(format!("{:.0}°", degrees), &loaded_config.grid_label_color, azimuth, 0.0, outward_anchor(azimuth));
+        }
+    }
+
+    if loaded_config.elevation_labels {
+        for m in 1..loaded_config.altitude_angle_steps {
+            let elevation = m as f32 / loaded_config.altitude_angle_steps as f32 * PI / 2.0;
+            spawn_label(
+                format!("{:.0}°", elevation.to_degrees()),
+                &loaded_config.grid_label_color,
+                loaded_config.elevation_labels_azimuth.to_radians(),
+                elevation,
+                Anchor::BottomLeft,
+            );
+        }
+    }
+}
+
+/// Azimuth ticks pointing inwards from the horizon, a constant length on screen
+pub fn draw_azimuth_ticks(view: Res<view::ViewState>, mut gizmos: Gizmos) {
+    let loaded_config = &view.loaded_config;
+    if !view.show_grid || loaded_config.azimuth_tick_degrees <= 0.0 {
+        return;
+    }
+    let length = loaded_config.azimuth_tick_length * view::readable_scale(&view);
+    let color = hexstr2color(&loaded_config.azimuth_angle_lines_color);
+    let step = loaded_config.azimuth_tick_degrees.max(1.0);
+    let mut degrees = 0.0;
+    while degrees < 360.0 {
+        let outer = view::sky_to_scene((degrees as f64).to_radians(), 0.0, loaded_config.scene_radius, &view);
+        gizmos.line_2d(outer, outer - outer.normalize_or_zero() * length, color);
+        degrees += step;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn anchor_at(azimuth_degrees: f32) -> Vec2 {
+        outward_anchor(azimuth_degrees.to_radians()).as_vec()
+    }
+
+    #[test]
+    fn anchors_labels_on_the_side_facing_the_dome() {
+        // The anchor is the point of the label on the horizon, so a label at the north sits on its bottom edge
+        assert!(anchor_at(0.0).abs_diff_eq(Vec2::new(0.0, -0.5), 1e-6));
+        assert!(anchor_at(90.0).abs_diff_eq(Vec2::new(-0.5, 0.0), 1e-6));
+        assert!(anchor_at(180.0).abs_diff_eq(Vec2::new(0.0, 0.5), 1e-6));
+        assert!(anchor_at(270.0).abs_diff_eq(Vec2::new(0.5, 0.0), 1e-6));
+        assert!(anchor_at(45.0).abs_diff_eq(Vec2::new(-0.5, -0.5), 1e-6));
+        assert!(anchor_at(30.0).abs_diff_eq(Vec2::new(-0.5 * 30f32.to_radians().tan(), -0.5), 1e-6));
+    }
+
+    #[test]
+    fn skips_the_labels_of_lettered_directions() {
+        assert_eq!(azimuth_label_degrees(30.0, 4), vec![30.0, 60.0, 120.0, 150.0, 210.0, 240.0, 300.0, 330.0]);
+        assert_eq!(azimuth_label_degrees(45.0, 4), vec![45.0, 135.0, 225.0, 315.0]);
+        assert!(azimuth_label_degrees(45.0, 8).is_empty());
+        assert_eq!(azimuth_label_degrees(100.0, 8), vec![100.0, 200.0, 300.0]);
+    }
+
+    #[test]
+    fn label_steps_are_at_least_five_degrees() {
+        assert_eq!(azimuth_label_degrees(0.01, 8).len(), 72 - 8);
+    }
+}
@@ -1,15 +1,197 @@
+use bevy::prelude::Resource;
 use chrono::Utc;
 use serde::{Deserialize, Serialize};
 use reqwest::Error;
 use homedir::my_home;
 use std::fs::File;
 use std::fs;
+use std::collections::HashMap;
 use std::io::Write;
+use std::path::PathBuf;
+use std::sync::OnceLock;
 use chrono::DateTime;
 
+use crate::archive;
+use crate::groups;
+use crate::locator;
+use crate::observers;
+use crate::projection::{MapProjection, SkyProjection};
+use crate::spacetrack;
+use crate::terrain::{self, AltitudeReference};
+use crate::tle;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TleSourceKind {
+    Celestrak,
+    SpaceTrack,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TleSource {
+    pub kind: TleSourceKind,
+    #[serde(default)]
+    pub names: Vec<String>, // Celestrak only
+    #[serde(default)]
+    pub norad_ids: Vec<u64>,
+    #[serde(default)]
+    pub celestrak_groups: Vec<String>, // Celestrak only, e.g. "weather", "amateur", "stations", "starlink"
+    #[serde(default)]
+    pub epoch_start: String, // Space-Track only, leave empty for the latest element sets
+    #[serde(default)]
+    pub epoch_end: String,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SatAlias {
+    pub norad_id: u64,
+    pub name: String,
+}
+
+/// A named place on the ground, altitude in meters above the `altitude_reference`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Location {
+    pub name: String,
+    pub latitude: f64,
+    pub longitude: f64,
+    #[serde(default)]
+    pub altitude: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SatFrequency {
+    pub norad_id: u64,
+    pub downlink_hz: u64,
+}
+
+/// Display overrides, unset fields keep the value inherited from the groups or global settings
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct SatStyle {
+    pub color: Option<String>,
+    pub trail_color: Option<String>,
+    pub size: Option<f32>,
+    pub label: Option<bool>,
+    pub display_name: Option<String>,
+    pub trail_length_seconds: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SatStyleOverride {
+    pub norad_id: u64,
+    #[serde(default)]
+    pub style: SatStyle,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SatGroup {
+    pub name: String,
+    #[serde(default)]
+    pub norad_ids: Vec<u64>,
+    #[serde(default)]
+    pub object_types: Vec<String>, // SATCAT object types (PAY, R/B, DEB, UNK)
+    #[serde(default)]
+    pub sources: Vec<TleSource>, // every object fetched from these sources belongs to the group
+    #[serde(default)]
+    pub hidden: bool, // initial visibility, toggled at runtime with the keymap toggle_group_N keys
+    #[serde(default)]
+    pub style: SatStyle,
+}
+
+impl SatGroup {
+    pub fn contains(&self, norad_id: u64, object_type: Option<&str>) -> bool {
+        self.norad_ids.contains(&norad_id)
+            || object_type.is_some_and(|object_type| self.object_types.iter().any(|t| t.eq_ignore_ascii_case(object_type)))
+    }
+}
+
+/// Key names are bevy `KeyCode` names ("KeyS", "ArrowLeft", "F1") or shorthands ("S", "1", "=", "[")
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Keymap {
+    pub toggle_stars: String,
+    pub toggle_grid: String,
+    pub toggle_trails: String,
+    pub toggle_labels: String,
+    pub rotate_left: String,
+    pub rotate_right: String,
+    pub zoom_in: String,
+    pub zoom_out: String,
+    pub next_satellite: String,
+    pub previous_satellite: String,
+    pub time_faster: String,
+    pub time_slower: String,
+    pub time_pause: String,
+    pub time_reset: String,
+    pub screenshot: String,
+    pub export_passes: String,
+    pub toggle_help: String,
+    pub follow_selected: String,
+    pub reset_view: String,
+    pub toggle_map: String,
+    pub toggle_globe_frame: String,
+    pub export_footprints: String,
+    pub next_observer: String,
+    pub toggle_schedule: String,
+    pub toggle_group_1: String, // groups in the order of sat_groups
+    pub toggle_group_2: String,
+    pub toggle_group_3: String,
+    pub toggle_group_4: String,
+    pub toggle_group_5: String,
+    pub toggle_group_6: String,
+    pub toggle_group_7: String,
+    pub toggle_group_8: String,
+    pub toggle_group_9: String,
+}
+
+impl ::std::default::Default for Keymap {
+    fn default() -> Self {
+        Self {
+            toggle_stars: "S".to_string(),
+            toggle_grid: "G".to_string(),
+            toggle_trails: "T".to_string(),
+            toggle_labels: "L".to_string(),
+            rotate_left: "ArrowLeft".to_string(),
+            rotate_right: "ArrowRight".to_string(),
+            zoom_in: "=".to_string(),
+            zoom_out: "-".to_string(),
+            next_satellite: "Tab".to_string(),
+            previous_satellite: "Backquote".to_string(),
+            time_faster: "]".to_string(),
+            time_slower: "[".to_string(),
+            time_pause: "Space".to_string(),
+            time_reset: "R".to_string(),
+            screenshot: "F12".to_string(),
+            export_passes: "E".to_string(),
+            toggle_help: "H".to_string(),
+            follow_selected: "F".to_string(),
+            reset_view: "Home".to_string(),
+            toggle_map: "M".to_string(),
+            toggle_globe_frame: "I".to_string(),
+            export_footprints: "P".to_string(),
+            next_observer: "O".to_string(),
+            toggle_schedule: "C".to_string(),
+            toggle_group_1: "1".to_string(),
+            toggle_group_2: "2".to_string(),
+            toggle_group_3: "3".to_string(),
+            toggle_group_4: "4".to_string(),
+            toggle_group_5: "5".to_string(),
+            toggle_group_6: "6".to_string(),
+            toggle_group_7: "7".to_string(),
+            toggle_group_8: "8".to_string(),
+            toggle_group_9: "9".to_string(),
+        }
+    }
+}
+
+/// Loaded once at startup and inserted as a resource, fields missing from config.toml keep their defaults
+#[derive(Resource, Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
 pub struct Config {
     pub scene_radius: f32,
+    pub sky_projection: SkyProjection, // Orthographic, AzimuthalEquidistant, Stereographic or LambertEqualArea
+    pub scene_margin: f32, // logical pixels kept free around the dome for labels
+    pub ui_scale: f32, // multiplies font and marker sizes on top of the window's scale factor
     pub azimuth_lines_radius: f32,
     pub azimuth_lines_thickness: f32,
     pub altitude_lines_thickness: f32,
@@ -18,7 +200,13 @@ pub struct Config {
     pub star_radius: f32,
     pub user_latitude: f64,
     pub user_longitude: f64,
-    pub user_altitude: f64,
+    pub user_altitude: f64, // meters above the altitude_reference
+    pub altitude_reference: AltitudeReference, // MeanSeaLevel or Ellipsoid (WGS84)
+    pub geoid_path: String, // NGA EGM96 15' WW15MGH.DAC grid, not shipped, converts between mean sea level and ellipsoidal heights
+    pub terrain_tiles_path: String, // directory of SRTM .hgt tiles (GeoTIFF isn't supported, `gdal_translate -of SRTMHGT` converts it), replaces the altitudes of the places they cover when set
+    pub height_above_terrain_meters: f64, // antenna height added to the terrain
+    pub observers: Vec<Location>, // named stations, the active one replaces the user_* location
+    pub active_observer: String, // name of the station used at startup, the first one if empty
     pub max_star_magnitude: f32,
     pub star_magnitude_scale_comp: f32, // make radius adjust with magnitude more tame
     pub shape_resolution: u32,
@@ -28,9 +216,10 @@ pub struct Config {
     pub trail_sim_step_seconds: i64,
     pub trail_max_length_seconds: i64,
     pub trail_max_forecast_seconds: i64,
-    pub sat_name_font_size: f32,
-    pub sat_name_font_width: f32,
+    pub sat_name_font_size: f32, // logical pixels, the label background is sized from the rendered text
     pub tle_fetch_sats: Vec<String>,
+    pub tle_fetch_norad_ids: Vec<u64>,
+    pub sat_aliases: Vec<SatAlias>,
     pub altitude_angle_lines_color: String,
     pub azimuth_angle_lines_color: String,
     pub sat_trails_color: String,
@@ -39,13 +228,116 @@ pub struct Config {
     pub sat_name_bg_color: String,
     pub star_color: String,
     pub north_color: String,
+    pub compass_color: String, // the other compass letters
+    pub compass_intercardinals: bool, // NE, SE, SW and NW too
+    pub azimuth_tick_degrees: f32, // 0 to hide the ticks, at least 1
+    pub azimuth_tick_length: f32,
+    pub azimuth_label_degrees: f32, // 0 to hide the azimuth values, at least 5
+    pub elevation_labels: bool,
+    pub elevation_labels_azimuth: f32, // degrees, where the elevation values run along the rings
+    pub grid_label_color: String,
+    pub equatorial_grid: bool,
+    pub equatorial_grid_color: String,
+    pub equatorial_grid_ra_hours: f64, // between hour circles
+    pub equatorial_grid_dec_degrees: f64, // between declination parallels
+    pub celestial_equator: bool,
+    pub celestial_equator_color: String,
+    pub ecliptic: bool,
+    pub ecliptic_color: String,
+    pub galactic_plane: bool,
+    pub galactic_plane_color: String,
+    pub sky_overlay_points: usize, // per full circle
     pub tle_update_interval_seconds: i64,
+    pub tle_epoch_stale_age_days: f64,
+    pub tle_epoch_expired_age_days: f64,
+    pub tle_refuse_stale_predictions: bool,
+    pub tle_max_prediction_age_days: f64,
+    pub sat_name_stale_color: String,
+    pub sat_name_expired_color: String,
+    pub tle_sources: Vec<TleSource>,
+    pub spacetrack_base_url: String,
+    pub spacetrack_identity: String,
+    pub spacetrack_password: String,
+    pub spacetrack_credentials_file: String, // relative to ~/.config/ontake/tasogare
+    pub tle_archive_enabled: bool, // keep every fetched element set in ~/.config/ontake/tasogare/archive
+    pub analysis_mean_motion_tolerance: f64, // rev/day beyond the drag-induced change
+    pub analysis_eccentricity_tolerance: f64,
+    pub analysis_inclination_tolerance_deg: f64,
+    pub analysis_max_gap_days: f64, // don't compare element sets further apart than this
+    pub analysis_decay_mean_motion_dot: f64, // rev/day², ndot/2 as published in the TLE
+    pub analysis_decay_growth_factor: f64,
+    pub analysis_decay_window_days: f64,
+    pub analysis_badge_recent_days: f64, // MNV and DECAY badges for events this many days before the simulated time
+    pub satcat_source: String, // URL or local path of the Celestrak SATCAT CSV, empty to disable
+    pub satcat_update_interval_seconds: i64,
+    pub satcat_filter_object_types: Vec<String>, // PAY, R/B, DEB, UNK (empty = everything)
+    pub satcat_filter_owners: Vec<String>,
+    pub satcat_filter_status_codes: Vec<String>, // +, -, P, B, S, X, D, ?
+    pub satcat_filter_rcs_sizes: Vec<String>, // SMALL, MEDIUM, LARGE, UNKNOWN
+    pub satcat_filter_launched_after: String, // YYYY-MM-DD
+    pub info_panel_enabled: bool,
+    pub info_panel_font_size: f32,
+    pub transmitters_source: String, // URL or local path of the SatNOGS DB transmitters JSON, empty to disable
+    pub transmitters_update_interval_seconds: i64,
+    pub sat_downlink_frequencies: Vec<SatFrequency>, // overrides the SatNOGS downlinks
+    pub sat_groups: Vec<SatGroup>,
+    pub sat_styles: Vec<SatStyleOverride>, // applied after the groups
+    pub pass_list_length: usize,
+    pub pick_radius: f32, // pixels around a satellite that count as hovering it
+    pub selection_color: String,
+    pub keymap: Keymap,
+    pub rotate_speed_degrees: f32, // per second while the rotate key is held
+    pub zoom_speed: f32, // zoom factor per second while the zoom key is held
+    pub min_zoom: f32,
+    pub max_zoom: f32,
+    pub zoom_wheel_step: f32, // zoom factor per mouse wheel notch
+    pub view_smoothing: f32, // how fast zoom and follow catch up, per second
+    pub map_projection: MapProjection, // Equirectangular or Mercator
+    pub map_coastlines_path: String, // coastline GeoJSON (e.g. a finer Natural Earth one) found by resolve_data_path, empty for the bundled 1:110m ones
+    pub map_coastline_color: String,
+    pub map_graticule_color: String,
+    pub map_terminator_color: String,
+    pub map_observer_color: String,
+    pub map_track_past_minutes: i64,
+    pub map_track_future_minutes: i64,
+    pub map_track_step_seconds: i64,
+    pub map_max_tracks: usize, // above this many shown satellites, only the selected one gets a ground track
+    pub globe_texture: String, // equirectangular Earth image under assets/, empty for a plain sphere with coastlines
+    pub globe_color: String,
+    pub globe_cone_range_km: f64, // length of the observer's horizon cone
+    pub globe_orbit_points: usize,
+    pub globe_position_step_seconds: f64, // simulated time between propagations of the satellites on the globe
+    pub footprint_min_elevation_degrees: f64,
+    pub footprint_points: usize,
+    pub footprint_color: String,
+    pub partner_location: Option<Location>, // outlined on the dome: where a satellite is heard by both stations
+    pub partner_color: String,
+    pub footprint_locations: Vec<Location>, // reported when a footprint covers them
+    pub footprint_location_color: String,
+    pub schedule_hours: i64,
+    pub schedule_step_seconds: i64,
+    pub schedule_min_elevation_degrees: f64,
+    pub schedule_panel_length: usize,
+    pub gps_source: String, // "gpsd", "gpsd://host:port", "tcp://host:port" or an NMEA device/file path, empty to disable. With observers listed, only the station named "gps" follows it
+    pub gps_move_threshold_meters: f64,
+    pub user_location: String, // Maidenhead locator (e.g. "JN18eu") or place name (e.g. "Lyon, FR"), replaces user_latitude and user_longitude when set
+    pub gazetteer_path: String, // GeoNames cities15000.txt (`just fetch-data` downloads it) or any file in that format, else a smaller bundled table is used
+    pub hud_enabled: bool,
+    pub hud_items: Vec<String>, // lines of the HUD, among "observer", "clock", "sidereal", "sun" and "next_pass"
+    #[serde(alias = "hud_utc_offset")]
+    pub hud_time_zone: String, // local time zone of the HUD as an IANA name ("Asia/Tokyo") or an offset ("+09:00"), the system's when empty
+    pub hud_locator_length: usize,
+    #[serde(skip)]
+    pub(crate) observer_height: OnceLock<f64>, // of the user_* location, cleared by move_observer
 }
 
 impl ::std::default::Default for Config {
     fn default() -> Self {
         Self {
             scene_radius: 600.0,
+            sky_projection: SkyProjection::Orthographic,
+            scene_margin: 40.0,
+            ui_scale: 1.0,
             azimuth_lines_radius: 600.0,
             azimuth_lines_thickness: 1.0,
             altitude_lines_thickness: 1.0,
@@ -55,6 +347,12 @@ impl ::std::default::Default for Config {
             user_latitude: 48.8,
             user_longitude: 2.3,
             user_altitude: 0.0,
+            altitude_reference: AltitudeReference::MeanSeaLevel,
+            geoid_path: "assets/data/WW15MGH.DAC".to_string(),
+            terrain_tiles_path: "".to_string(),
+            height_above_terrain_meters: 0.0,
+            observers: Vec::new(),
+            active_observer: "".to_string(),
             max_star_magnitude: 5.0,
             star_magnitude_scale_comp: 3.0,
             shape_resolution: 128,
@@ -65,8 +363,9 @@ impl ::std::default::Default for Config {
             trail_max_length_seconds: 3600,
             trail_max_forecast_seconds: 3600 * 24,
             sat_name_font_size: 15.0,
-            sat_name_font_width: 9.4,
             tle_fetch_sats: vec!["NOAA 15".to_string(), "NOAA 18".to_string(), "NOAA 19".to_string(), "NOAA 20".to_string(), "NOAA 21".to_string(),"METEOR-M 1".to_string(),"METEOR-M 2".to_string(),"METEOR-M2 3".to_string(),"METEOR-M2 4".to_string(),"METOP-A".to_string(),"METOP-B".to_string(),"METOP-C".to_string()],
+            tle_fetch_norad_ids: Vec::new(),
+            sat_aliases: Vec::new(),
             altitude_angle_lines_color: "#FFFFFF77".to_string(),
             azimuth_angle_lines_color: "#FFFFFF77".to_string(),
             sat_trails_color: "#FFA500DD".to_string(),
@@ -75,20 +374,173 @@ impl ::std::default::Default for Config {
             sat_name_bg_color: "#000000FF".to_string(),
             star_color: "#FFFFFFDD".to_string(),
             north_color: "#FF0000FF".to_string(),
+            compass_color: "#FFFFFFFF".to_string(),
+            compass_intercardinals: true,
+            azimuth_tick_degrees: 10.0,
+            azimuth_tick_length: 8.0,
+            azimuth_label_degrees: 30.0,
+            elevation_labels: true,
+            elevation_labels_azimuth: 22.5,
+            grid_label_color: "#FFFFFF99".to_string(),
+            equatorial_grid: false,
+            equatorial_grid_color: "#4488FF55".to_string(),
+            equatorial_grid_ra_hours: 2.0,
+            equatorial_grid_dec_degrees: 30.0,
+            celestial_equator: false,
+            celestial_equator_color: "#4488FFAA".to_string(),
+            ecliptic: false,
+            ecliptic_color: "#FFCC00AA".to_string(),
+            galactic_plane: false,
+            galactic_plane_color: "#CC88FFAA".to_string(),
+            sky_overlay_points: 180,
             tle_update_interval_seconds: 86400*2,
+            tle_epoch_stale_age_days: 3.0,
+            tle_epoch_expired_age_days: 7.0,
+            tle_refuse_stale_predictions: false,
+            tle_max_prediction_age_days: 14.0,
+            sat_name_stale_color: "#FFD700FF".to_string(),
+            sat_name_expired_color: "#FF4040FF".to_string(),
+            tle_sources: Vec::new(),
+            spacetrack_base_url: "https://www.space-track.org".to_string(),
+            spacetrack_identity: String::new(),
+            spacetrack_password: String::new(),
+            spacetrack_credentials_file: "spacetrack-credentials".to_string(),
+            tle_archive_enabled: true,
+            analysis_mean_motion_tolerance: 0.001,
+            analysis_eccentricity_tolerance: 0.0005,
+            analysis_inclination_tolerance_deg: 0.02,
+            analysis_max_gap_days: 10.0,
+            analysis_decay_mean_motion_dot: 0.002,
+            analysis_decay_growth_factor: 3.0,
+            analysis_decay_window_days: 7.0,
+            analysis_badge_recent_days: 14.0,
+            satcat_source: "https://celestrak.org/pub/satcat.csv".to_string(),
+            satcat_update_interval_seconds: 86400*7,
+            satcat_filter_object_types: Vec::new(),
+            satcat_filter_owners: Vec::new(),
+            satcat_filter_status_codes: Vec::new(),
+            satcat_filter_rcs_sizes: Vec::new(),
+            satcat_filter_launched_after: String::new(),
+            info_panel_enabled: true,
+            info_panel_font_size: 13.0,
+            transmitters_source: "https://db.satnogs.org/api/transmitters/?format=json".to_string(),
+            transmitters_update_interval_seconds: 86400*7,
+            sat_downlink_frequencies: Vec::new(),
+            sat_groups: Vec::new(),
+            sat_styles: Vec::new(),
+            pass_list_length: 5,
+            pick_radius: 12.0,
+            selection_color: "#00FFFFFF".to_string(),
+            keymap: Keymap::default(),
+            rotate_speed_degrees: 60.0,
+            zoom_speed: 2.0,
+            min_zoom: 0.5,
+            max_zoom: 20.0,
+            zoom_wheel_step: 1.15,
+            view_smoothing: 10.0,
+            map_projection: MapProjection::Equirectangular,
+            map_coastlines_path: String::new(),
+            map_coastline_color: "#88AACCFF".to_string(),
+            map_graticule_color: "#FFFFFF22".to_string(),
+            map_terminator_color: "#FFCC0088".to_string(),
+            map_observer_color: "#FF0000FF".to_string(),
+            map_track_past_minutes: 45,
+            map_track_future_minutes: 90,
+            map_track_step_seconds: 30,
+            map_max_tracks: 20,
+            globe_texture: "".to_string(),
+            globe_color: "#1B3550FF".to_string(),
+            globe_cone_range_km: 2500.0,
+            globe_orbit_points: 180,
+            globe_position_step_seconds: 1.0,
+            footprint_min_elevation_degrees: 0.0,
+            footprint_points: 120,
+            footprint_color: "#00FF88AA".to_string(),
+            partner_location: None,
+            partner_color: "#FF8800CC".to_string(),
+            footprint_locations: Vec::new(),
+            footprint_location_color: "#FFFFFFCC".to_string(),
+            schedule_hours: 24,
+            schedule_step_seconds: 30,
+            schedule_min_elevation_degrees: 0.0,
+            schedule_panel_length: 20,
+            gps_source: "".to_string(),
+            gps_move_threshold_meters: 100.0,
+            user_location: "".to_string(),
+            gazetteer_path: "assets/data/cities15000.txt".to_string(),
+            hud_enabled: true,
+            hud_items: vec!["observer".to_string(), "clock".to_string(), "sidereal".to_string(), "sun".to_string(), "next_pass".to_string()],
+            hud_time_zone: "".to_string(),
+            hud_locator_length: 6,
+            observer_height: OnceLock::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Height of the observer above the WGS84 ellipsoid (m), looked up in the terrain and geoid once per location
+    pub fn observer_height(&self) -> f64 {
+        *self.observer_height.get_or_init(|| terrain::ellipsoidal_height(self.user_latitude, self.user_longitude, self.user_altitude, self))
+    }
+
+    /// Move the observer, altitude in meters above the altitude_reference
+    pub fn move_observer(&mut self, latitude: f64, longitude: f64, altitude: f64) {
+        self.user_latitude = latitude;
+        self.user_longitude = longitude;
+        self.user_altitude = altitude;
+        self.observer_height = OnceLock::new();
+    }
+
+    /// Name shown for an object: the user alias if any, otherwise the catalog name
+    pub fn display_name(&self, norad_id: u64, object_name: &Option<String>) -> String {
+        match self.sat_aliases.iter().find(|alias| alias.norad_id == norad_id) {
+            Some(alias) => alias.name.clone(),
+            None => object_name.clone().unwrap_or_else(|| norad_id.to_string()),
         }
     }
 }
 
+/// Find a data file such as "assets/data/cities15000.txt": absolute paths as they are, relative
+/// ones in ~/.config/ontake/tasogare, then where bevy looks for assets (BEVY_ASSET_ROOT, CARGO_MANIFEST_DIR
+/// or next to the executable), then in the working directory
+pub fn resolve_data_path(path: &str) -> Option<PathBuf> {
+    let path = PathBuf::from(path);
+    if path.is_absolute() {
+        return path.exists().then_some(path);
+    }
+    let mut bases = Vec::new();
+    if let Ok(Some(mut home)) = my_home() {
+        home.push(".config/ontake/tasogare");
+        bases.push(home);
+    }
+    if let Some(root) = std::env::var_os("BEVY_ASSET_ROOT").or_else(|| std::env::var_os("CARGO_MANIFEST_DIR")) {
+        bases.push(PathBuf::from(root));
+    }
+    if let Some(dir) = std::env::current_exe().ok().and_then(|exe| exe.parent().map(PathBuf::from)) {
+        bases.push(dir);
+    }
+    bases.push(PathBuf::new());
+    bases.into_iter().map(|base| base.join(&path)).find(|candidate| candidate.exists())
+}
+
 pub fn init() -> Config {
-    let cfg: Config = match confy::load("ontake/tasogare", "config") {
-        Ok(config) => config,
-        Err(_) => Config::default(),
-    };
-    match confy::store("ontake/tasogare", "config", cfg.clone()) {
-        Ok(_) => (),
-        Err(_) => (), // Doesn't actually matter (this is only for reformatting anyways)
+    let mut cfg = match confy::load::<Config>("ontake/tasogare", "config") {
+        Ok(config) => {
+            // Doesn't actually matter if this fails (this is only for reformatting anyways)
+            let _ = confy::store("ontake/tasogare", "config", config.clone());
+            config
+        }
+        Err(err) => {
+            // Keep the file as it is so the user can fix it, rather than replacing it with the defaults
+            eprintln!("Couldn't load config, using the defaults: {}", err);
+            Config::default()
+        }
     };
+    groups::apply_membership(&mut cfg);
+    locator::apply_user_location(&mut cfg);
+    let active_observer = cfg.active_observer.clone();
+    observers::apply(&mut cfg, &active_observer);
+    locator::apply_override(&mut cfg);
     cfg
 }
 
@@ -101,6 +553,68 @@ async fn fetch_tle(sat_name: &str) -> Result<String, Error> {
     Ok(body)
 }
 
+async fn fetch_tle_by_norad_id(norad_id: u64) -> Result<String, Error> {
+    println!("Fetching TLE for NORAD ID {}", norad_id);
+    let url = format!("https://celestrak.org/NORAD/elements/gp.php?CATNR={}&FORMAT=TLE", norad_id);
+    let response = reqwest::get(&url).await?;
+    let body = response.text().await?;
+    Ok(body)
+}
+
+async fn fetch_tle_group(group: &str) -> Result<String, Error> {
+    println!("Fetching TLE for Celestrak group {}", group);
+    let url = format!("https://celestrak.org/NORAD/elements/gp.php?GROUP={}&FORMAT=TLE", group);
+    let response = reqwest::get(&url).await?;
+    let body = response.text().await?;
+    Ok(body)
+}
+
+/// Fetch every element set of one source, returning the listing and whether any request failed
+async fn fetch_source(spacetrack: &mut spacetrack::Session<'_>, source: &TleSource) -> (String, bool) {
+    let mut tle_data = String::new();
+    let mut failure = false;
+    match source.kind {
+        TleSourceKind::Celestrak => {
+            for sat in &source.names {
+                match fetch_tle(sat).await {
+                    Ok(tle) => tle_data.push_str(&tle),
+                    Err(_) => failure = true,
+                }
+            }
+            for norad_id in &source.norad_ids {
+                match fetch_tle_by_norad_id(*norad_id).await {
+                    Ok(tle) => tle_data.push_str(&tle),
+                    Err(_) => failure = true,
+                }
+            }
+            for group in &source.celestrak_groups {
+                match fetch_tle_group(group).await {
+                    Ok(tle) => tle_data.push_str(&tle),
+                    Err(_) => failure = true,
+                }
+            }
+        }
+        TleSourceKind::SpaceTrack => {
+            if source.norad_ids.is_empty() {
+                return (tle_data, failure);
+            }
+            match spacetrack.fetch_tle(&source.norad_ids, &source.epoch_start, &source.epoch_end).await {
+                Ok(tle) => {
+                    tle_data.push_str(&tle);
+                    if !tle.ends_with('\n') {
+                        tle_data.push('\n');
+                    }
+                }
+                Err(err) => {
+                    eprintln!("Failed to fetch TLE from Space-Track: {}", err);
+                    failure = true;
+                }
+            }
+        }
+    }
+    (tle_data, failure)
+}
+
 pub async fn update_tle(loaded_config: Config) {
     let mut pathtimestamp = my_home().unwrap().expect("couldn't get home directory");
     pathtimestamp.push(".config/ontake/tasogare/TLEDATA-DATE");
@@ -129,9 +643,42 @@ pub async fn update_tle(loaded_config: Config) {
         }
     }
 
+    for norad_id in &loaded_config.tle_fetch_norad_ids {
+        match fetch_tle_by_norad_id(*norad_id).await {
+            Ok(tle) => tle_data.push_str(&tle),
+            Err(_) => failure = true,
+        }
+    }
+
+    let mut spacetrack = spacetrack::Session::new(&loaded_config);
+    for source in &loaded_config.tle_sources {
+        let (tle, source_failure) = fetch_source(&mut spacetrack, source).await;
+        tle_data.push_str(&tle);
+        failure |= source_failure;
+    }
+
+    let mut membership: HashMap<String, Vec<u64>> = HashMap::new();
+    for group in &loaded_config.sat_groups {
+        let members = membership.entry(group.name.clone()).or_default();
+        for source in &group.sources {
+            let (tle, source_failure) = fetch_source(&mut spacetrack, source).await;
+            members.extend(tle::split_tle(&tle).iter().filter_map(|(_, line1, _)| tle::norad_id_from_line1(line1)));
+            tle_data.push_str(&tle);
+            failure |= source_failure;
+        }
+    }
+    spacetrack.logout().await;
+    if !failure {
+        groups::save_membership(&membership);
+    }
+
     let mut path = my_home().unwrap().expect("couldn't get home directory");
     path.push(".config/ontake/tasogare/TLEDATA");
 
+    if loaded_config.tle_archive_enabled {
+        archive::archive_tle(&tle_data);
+    }
+
     if !failure {
         match std::fs::write(&path, tle_data) {
             Ok(_) => {
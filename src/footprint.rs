@@ -0,0 +1,233 @@
+use bevy::prelude::*;
+use chrono::{DateTime, Utc};
+use homedir::my_home;
+use std::f64::consts::PI;
+
+use crate::config::{Config, Location};
+use crate::{archive, clock, eci_state, groups, gst_from_datetime, hexstr2color, keymap, selection, sub_satellite_point, terrain, view, world_map, Satellite};
+
+const EARTH_MEAN_RADIUS: f64 = 6_371_000.0; // m
+
+/// Earth central angle (radians) between the sub-satellite point and the edge of the area
+/// seeing a satellite at `altitude` (m) above `min_elevation` (radians)
+pub fn half_angle(altitude: f64, min_elevation: f64) -> f64 {
+    (EARTH_MEAN_RADIUS / (EARTH_MEAN_RADIUS + altitude) * min_elevation.cos()).clamp(-1.0, 1.0).acos() - min_elevation
+}
+
+/// The point at an angular distance and bearing (radians) from a latitude and longitude (radians)
+fn destination(lat: f64, lon: f64, distance: f64, bearing: f64) -> (f64, f64) {
+    let lat2 = (lat.sin() * distance.cos() + lat.cos() * distance.sin() * bearing.cos()).asin();
+    let lon2 = lon + (bearing.sin() * distance.sin() * lat.cos()).atan2(distance.cos() - lat.sin() * lat2.sin());
+    (lat2, (lon2 + PI).rem_euclid(2.0 * PI) - PI)
+}
+
+/// Small circle of the given angular radius around a point, as latitudes and longitudes (radians)
+pub fn circle(lat: f64, lon: f64, radius: f64, points: usize) -> Vec<(f64, f64)> {
+    (0..=points).map(|i| destination(lat, lon, radius, 2.0 * PI * i as f64 / points as f64)).collect()
+}
+
+/// Outline of the ground area seeing a satellite above the configured minimum elevation
+pub fn footprint(history: &archive::ElementHistory, date: DateTime<Utc>, loaded_config: &Config) -> Option<Vec<(f64, f64)>> {
+    let (lat, lon, alt) = sub_satellite_point(history, date)?;
+    let radius = half_angle(alt, loaded_config.footprint_min_elevation_degrees.to_radians());
+    Some(circle(lat, lon, radius, loaded_config.footprint_points.max(8)))
+}
+
+/// Elevation (radians) of a satellite seen from a location, its altitude above the ellipsoid
+pub fn elevation_from(history: &archive::ElementHistory, date: DateTime<Utc>, location: &Location) -> Option<f64> {
+    let (position, _) = eci_state(history, date)?;
+    let (_, el, _) = map_3d::eci2aer(gst_from_datetime(date), position[0], position[1], position[2], location.latitude.to_radians(), location.longitude.to_radians(), location.altitude, map_3d::Ellipsoid::WGS84);
+    Some(el)
+}
+
+/// The configured observer, partner and listed locations, with their heights above the ellipsoid
+pub fn all_locations(loaded_config: &Config) -> Vec<Location> {
+    let mut locations = vec![Location {
+        name: "here".to_string(),
+        latitude: loaded_config.user_latitude,
+        longitude: loaded_config.user_longitude,
+        altitude: loaded_config.observer_height(),
+    }];
+    let others = loaded_config.partner_location.iter().chain(loaded_config.footprint_locations.iter());
+    locations.extend(others.map(|location| Location {
+        altitude: terrain::ellipsoidal_height(location.latitude, location.longitude, location.altitude, loaded_config),
+        ..location.clone()
+    }));
+    locations
+}
+
+/// Locations inside the footprint of a satellite, with the satellite's elevation there (radians)
+pub fn locations_in_footprint(history: &archive::ElementHistory, date: DateTime<Utc>, loaded_config: &Config) -> Vec<(Location, f64)> {
+    let min_elevation = loaded_config.footprint_min_elevation_degrees.to_radians();
+    all_locations(loaded_config)
+        .into_iter()
+        .filter_map(|location| {
+            let el = elevation_from(history, date, &location)?;
+            (el >= min_elevation).then_some((location, el))
+        })
+        .collect()
+}
+
+/// Print which locations can hear a satellite at a given time
+pub fn print_coverage(name: &str, norad_id: u64, history: &archive::ElementHistory, date: DateTime<Utc>, loaded_config: &Config) {
+    println!("Coverage of {} ({}) at {}, minimum elevation {}°", name, norad_id, date.to_rfc3339(), loaded_config.footprint_min_elevation_degrees);
+    let min_elevation = loaded_config.footprint_min_elevation_degrees.to_radians();
+    for location in all_locations(loaded_config) {
+        match elevation_from(history, date, &location) {
+            Some(el) => println!("  {:<24} {:>7.2}°  {}", location.name, el.to_degrees(), if el >= min_elevation { "inside" } else { "outside" }),
+            None => println!("  {:<24} propagation failed", location.name),
+        }
+    }
+}
+
+/// Sky directions (azimuth, elevation in radians) from the observer of the edge of the partner's
+/// visibility at `altitude` (m): a satellite at that altitude inside this outline is heard by both stations
+pub fn partner_outline(partner: &Location, altitude: f64, loaded_config: &Config) -> Vec<Option<(f64, f64)>> {
+    let radius = half_angle(altitude, loaded_config.footprint_min_elevation_degrees.to_radians());
+    circle(partner.latitude.to_radians(), partner.longitude.to_radians(), radius, loaded_config.footprint_points.max(8))
+        .into_iter()
+        .map(|(lat, lon)| {
+            let (x, y, z) = map_3d::geodetic2ecef(lat, lon, altitude, map_3d::Ellipsoid::WGS84);
+            let (az, el, _) = map_3d::ecef2aer(x, y, z, loaded_config.user_latitude.to_radians(), loaded_config.user_longitude.to_radians(), loaded_config.observer_height(), map_3d::Ellipsoid::WGS84);
+            (el >= 0.0).then_some((az, el))
+        })
+        .collect()
+}
+
+/// Write the footprints of the shown satellites to ~/.config/ontake/tasogare/footprints.geojson
+pub fn export_footprints<'a>(sats: impl Iterator<Item = &'a Satellite>, date: DateTime<Utc>, visibility: &groups::GroupVisibility) {
+    let features: Vec<serde_json::Value> = sats
+        .filter(|sat| !groups::is_satellite_hidden(visibility, sat))
+        .filter_map(|sat| {
+            let outline = footprint(&sat.history, date, &sat.loaded_config)?;
+            let ring: Vec<[f64; 2]> = outline.iter().map(|(lat, lon)| [lon.to_degrees(), lat.to_degrees()]).collect();
+            let inside: Vec<String> = locations_in_footprint(&sat.history, date, &sat.loaded_config).into_iter().map(|(location, _)| location.name).collect();
+            Some(serde_json::json!({
+                "type": "Feature",
+                "properties": {
+                    "norad_id": sat.norad_id,
+                    "name": sat.name,
+                    "time": date.to_rfc3339(),
+                    "min_elevation": sat.loaded_config.footprint_min_elevation_degrees,
+                    "locations_inside": inside,
+                },
+                "geometry": { "type": "Polygon", "coordinates": [ring] },
+            }))
+        })
+        .collect();
+    let count = features.len();
+    let geojson = serde_json::json!({ "type": "FeatureCollection", "features": features });
+    let mut path = my_home().unwrap().expect("couldn't get home directory");
+    path.push(".config/ontake/tasogare/footprints.geojson");
+    match std::fs::write(&path, geojson.to_string()) {
+        Ok(_) => println!("Exported {} footprints to {}", count, path.display()),
+        Err(_) => eprintln!("Failed to export footprints"),
+    }
+}
+
+pub fn export_footprints_on_key(keys: Res<ButtonInput<KeyCode>>, bindings: Res<keymap::Keybindings>, sat_query: Query<&Satellite>, clock: Res<clock::SimClock>, visibility: Res<groups::GroupVisibility>) {
+    if bindings.just_pressed(&keys, keymap::Action::ExportFootprints) {
+        export_footprints(sat_query.iter(), clock.now(), &visibility);
+    }
+}
+
+/// Outline on the dome of where the selected satellite would be heard by the partner station too
+pub fn draw_dome_partner_outline(selected: Res<selection::SelectedSatellite>, sat_query: Query<&Satellite>, clock: Res<clock::SimClock>, view: Res<view::ViewState>, mut gizmos: Gizmos) {
+    let Some(Ok(sat)) = selected.0.map(|entity| sat_query.get(entity)) else { return };
+    let Some(partner) = &sat.loaded_config.partner_location else { return };
+    let Some((_, _, altitude)) = sub_satellite_point(&sat.history, clock.now()) else { return };
+    let color = hexstr2color(&sat.loaded_config.partner_color);
+    // Parts of the outline below our horizon split it into separate arcs
+    for arc in partner_outline(partner, altitude, &sat.loaded_config).split(|point| point.is_none()) {
+        gizmos.linestrip_2d(
+            arc.iter().flatten().map(|(az, el)| view::sky_to_scene(*az, *el, sat.loaded_config.scene_radius, &view)),
+            color,
+        );
+    }
+}
+
+/// Footprints of the selected and hovered satellites and the listed locations on the world map
+pub fn draw_map_footprints(
+    selected: Res<selection::SelectedSatellite>,
+    hovered: Res<selection::HoveredSatellite>,
+    sat_query: Query<&Satellite>,
+    clock: Res<clock::SimClock>,
+    view: Res<view::ViewState>,
+    mut gizmos: Gizmos,
+) {
+    let loaded_config = &view.loaded_config;
+    let scale = view::readable_scale(&view);
+    let now = clock.now();
+    for entity in [selected.0, hovered.0].into_iter().flatten() {
+        let Ok(sat) = sat_query.get(entity) else { continue };
+        let Some(outline) = footprint(&sat.history, now, &sat.loaded_config) else { continue };
+        let outline: Vec<Vec2> = outline.iter().map(|(lat, lon)| world_map::geo_to_scene(*lat, *lon, loaded_config)).collect();
+        for segment in world_map::split_at_antimeridian(&outline, loaded_config.scene_radius) {
+            gizmos.linestrip_2d(segment, hexstr2color(&loaded_config.footprint_color));
+        }
+    }
+    if let Some(partner) = &loaded_config.partner_location {
+        let position = world_map::geo_to_scene(partner.latitude.to_radians(), partner.longitude.to_radians(), loaded_config);
+        gizmos.circle_2d(Isometry2d::from_translation(position), 5.0 * scale, hexstr2color(&loaded_config.partner_color));
+    }
+    for location in &loaded_config.footprint_locations {
+        let position = world_map::geo_to_scene(location.latitude.to_radians(), location.longitude.to_radians(), loaded_config);
+        gizmos.circle_2d(Isometry2d::from_translation(position), 3.0 * scale, hexstr2color(&loaded_config.footprint_location_color));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Angular distance (radians) between two latitudes and longitudes (radians)
+    fn central_angle(a: (f64, f64), b: (f64, f64)) -> f64 {
+        let h = ((b.0 - a.0) / 2.0).sin().powi(2) + a.0.cos() * b.0.cos() * ((b.1 - a.1) / 2.0).sin().powi(2);
+        2.0 * h.sqrt().asin()
+    }
+
+    #[test]
+    fn half_angle_at_the_horizon() {
+        let iss = half_angle(420_000.0, 0.0);
+        assert!((iss - (EARTH_MEAN_RADIUS / (EARTH_MEAN_RADIUS + 420_000.0)).acos()).abs() < 1e-12);
+        assert!((iss.to_degrees() - 20.3).abs() < 0.1);
+        assert!((half_angle(35_786_000.0, 0.0).to_degrees() - 81.3).abs() < 0.1);
+        assert!(half_angle(0.0, 0.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn half_angle_shrinks_with_the_minimum_elevation() {
+        let angles: Vec<f64> = [0.0, 5.0, 10.0, 30.0, 60.0].iter().map(|el: &f64| half_angle(800_000.0, el.to_radians())).collect();
+        assert!(angles.windows(2).all(|pair| pair[1] < pair[0]));
+        assert!(half_angle(800_000.0, 90f64.to_radians()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn circle_points_are_at_the_radius() {
+        let center = (48.85f64.to_radians(), 2.35f64.to_radians());
+        let radius = 20f64.to_radians();
+        let points = circle(center.0, center.1, radius, 36);
+        assert_eq!(points.len(), 37);
+        assert!((points[0].0 - points[36].0).abs() < 1e-12 && (points[0].1 - points[36].1).abs() < 1e-12);
+        for point in &points {
+            assert!((central_angle(center, *point) - radius).abs() < 1e-9);
+        }
+        // Bearing 0 is due north
+        assert!((points[0].0 - (center.0 + radius)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn circle_wraps_longitudes_across_the_antimeridian() {
+        let points = circle(0.0, 175f64.to_radians(), 10f64.to_radians(), 36);
+        assert!(points.iter().all(|(_, lon)| (-PI..PI).contains(lon)));
+        assert!(points.iter().any(|(_, lon)| *lon < 0.0));
+    }
+
+    #[test]
+    fn circle_around_a_pole_stays_at_one_latitude() {
+        let radius = 15f64.to_radians();
+        for (lat, _) in circle(PI / 2.0, 0.0, radius, 24) {
+            assert!((lat - (PI / 2.0 - radius)).abs() < 1e-9);
+        }
+    }
+}
@@ -0,0 +1,363 @@
+use bevy::{
+    input::mouse::{MouseScrollUnit, MouseWheel},
+    math::DVec3,
+    prelude::*,
+    render::view::RenderLayers,
+    window::PrimaryWindow,
+};
+use chrono::{DateTime, Duration, Utc};
+use satkit::types::Vector3;
+
+use crate::{clock, config, eci_state, groups, gst_from_datetime, hexstr2color, selection, sun, view, world_map, Satellite};
+
+const EARTH_RADIUS_KM: f64 = 6378.137;
+/// Render layer of the globe meshes, lights, gizmos and camera, kept apart from the 2D scene
+const GLOBE_LAYER: usize = 1;
+
+/// Gizmos drawn only by the globe camera
+#[derive(Default, Reflect, GizmoConfigGroup)]
+pub struct GlobeGizmos;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GlobeFrame {
+    /// Orbits stay put while the Earth turns under them
+    Inertial,
+    /// The Earth stays put, orbits become paths over the ground
+    EarthFixed,
+}
+
+impl GlobeFrame {
+    pub fn other(&self) -> Self {
+        match self {
+            GlobeFrame::Inertial => GlobeFrame::EarthFixed,
+            GlobeFrame::EarthFixed => GlobeFrame::Inertial,
+        }
+    }
+}
+
+/// Orbit camera and cached orbits of the globe view
+#[derive(Resource)]
+pub struct GlobeState {
+    pub frame: GlobeFrame,
+    pub yaw: f32,
+    pub pitch: f32,
+    pub distance: f32, // Earth radii from the center
+    pub orbits_at: Option<DateTime<Utc>>,
+    pub orbits_frame: GlobeFrame,
+    pub orbits_selected: Option<Entity>,
+    pub orbits: Vec<(Entity, Vec<Vec3>)>,
+}
+
+impl GlobeState {
+    pub fn from_config(config: &config::Config) -> Self {
+        Self {
+            frame: GlobeFrame::Inertial,
+            yaw: config.user_longitude.to_radians() as f32 + std::f32::consts::FRAC_PI_2,
+            pitch: config.user_latitude.to_radians() as f32,
+            distance: 4.0,
+            orbits_at: None,
+            orbits_frame: GlobeFrame::Inertial,
+            orbits_selected: None,
+            orbits: Vec::new(),
+        }
+    }
+}
+
+/// A marker component for the globe camera
+#[derive(Component)]
+pub struct GlobeCamera;
+
+/// A marker component for the Earth sphere
+#[derive(Component)]
+pub struct Earth;
+
+/// A marker component for the light coming from the Sun
+#[derive(Component)]
+pub struct GlobeSun;
+
+/// Turn a vector around the Earth's axis
+fn rotate_z(v: DVec3, angle: f64) -> DVec3 {
+    DVec3::new(v.x * angle.cos() - v.y * angle.sin(), v.x * angle.sin() + v.y * angle.cos(), v.z)
+}
+
+/// Earth-centered coordinates (km, Z to the north pole) to globe scene coordinates (Earth radii, Y up)
+fn to_scene(v: DVec3) -> Vec3 {
+    Vec3::new((v.x / EARTH_RADIUS_KM) as f32, (v.z / EARTH_RADIUS_KM) as f32, (-v.y / EARTH_RADIUS_KM) as f32)
+}
+
+/// A point above a spherical Earth (km, Earth-fixed)
+fn geo_to_ecef(lat: f64, lon: f64, alt_km: f64) -> DVec3 {
+    DVec3::new(lat.cos() * lon.cos(), lat.cos() * lon.sin(), lat.sin()) * (EARTH_RADIUS_KM + alt_km)
+}
+
+/// Earth-fixed vectors to the frame the globe is drawn in
+fn ecef_to_frame(v: DVec3, gst: f64, frame: GlobeFrame) -> DVec3 {
+    match frame {
+        GlobeFrame::Inertial => rotate_z(v, gst),
+        GlobeFrame::EarthFixed => v,
+    }
+}
+
+/// GCRF vectors to the frame the globe is drawn in
+fn eci_to_frame(v: DVec3, gst: f64, frame: GlobeFrame) -> DVec3 {
+    match frame {
+        GlobeFrame::Inertial => v,
+        GlobeFrame::EarthFixed => rotate_z(v, -gst),
+    }
+}
+
+/// GCRF meters to the km the globe works in
+fn gcrf_km(position: &Vector3) -> DVec3 {
+    DVec3::new(position[0], position[1], position[2]) / 1000.0
+}
+
+/// The UV sphere has its poles on Z and longitude 180° on +X, turn it to Y up with Greenwich on +X
+fn earth_mesh_rotation() -> Quat {
+    Quat::from_mat3(&Mat3::from_cols(Vec3::NEG_X, Vec3::Z, Vec3::Y))
+}
+
+pub fn setup_globe(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut config_store: ResMut<GizmoConfigStore>,
+    asset_server: Res<AssetServer>,
+    loaded_config: Res<config::Config>,
+) {
+    let (gizmo_config, _) = config_store.config_mut::<GlobeGizmos>();
+    gizmo_config.render_layers = RenderLayers::layer(GLOBE_LAYER);
+    commands.spawn((
+        Mesh3d(meshes.add(Sphere::new(1.0).mesh().uv(72, 36))),
+        MeshMaterial3d(materials.add(StandardMaterial {
+            base_color: if loaded_config.globe_texture.is_empty() { hexstr2color(&loaded_config.globe_color) } else { Color::WHITE },
+            base_color_texture: (!loaded_config.globe_texture.is_empty()).then(|| asset_server.load(loaded_config.globe_texture.clone())),
+            perceptual_roughness: 1.0,
+            ..default()
+        })),
+        Transform::from_rotation(earth_mesh_rotation()),
+        RenderLayers::layer(GLOBE_LAYER),
+        Earth,
+    ));
+    commands.spawn((
+        DirectionalLight { illuminance: 8_000.0, ..default() },
+        Transform::default(),
+        RenderLayers::layer(GLOBE_LAYER),
+        GlobeSun,
+    ));
+    commands.spawn((
+        Camera3d::default(),
+        Camera {
+            // Drawn under the 2D camera, which keeps rendering the UI
+            order: -1,
+            is_active: false,
+            ..default()
+        },
+        Msaa::Sample4,
+        Transform::default(),
+        RenderLayers::layer(GLOBE_LAYER),
+        GlobeCamera,
+    ));
+}
+
+/// Switch the globe camera on and stop the 2D camera from clearing over it
+pub fn apply_view_mode(
+    mode: Res<view::ViewMode>,
+    mut camera_2d_query: Query<&mut Camera, (With<Camera2d>, Without<GlobeCamera>)>,
+    mut camera_3d_query: Query<&mut Camera, With<GlobeCamera>>,
+) {
+    if !mode.is_changed() {
+        return;
+    }
+    let globe = *mode == view::ViewMode::Globe;
+    for mut camera in &mut camera_2d_query {
+        camera.clear_color = if globe { ClearColorConfig::None } else { ClearColorConfig::Default };
+    }
+    for mut camera in &mut camera_3d_query {
+        camera.is_active = globe;
+    }
+}
+
+/// Drag to orbit around the Earth, scroll to get closer
+pub fn orbit_camera(
+    buttons: Res<ButtonInput<MouseButton>>,
+    mut wheel: EventReader<MouseWheel>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    mut globe: ResMut<GlobeState>,
+    mut camera_query: Query<&mut Transform, With<GlobeCamera>>,
+    mut last_cursor: Local<Option<Vec2>>,
+) {
+    let cursor = windows.get_single().ok().and_then(|window| window.cursor_position());
+    if buttons.pressed(MouseButton::Left) {
+        if let (Some(previous), Some(cursor)) = (*last_cursor, cursor) {
+            let delta = cursor - previous;
+            if delta != Vec2::ZERO {
+                globe.yaw -= delta.x * 0.005;
+                globe.pitch = (globe.pitch + delta.y * 0.005).clamp(-1.5, 1.5);
+            }
+        }
+        *last_cursor = cursor;
+    } else {
+        *last_cursor = None;
+    }
+    let mut notches = 0.0;
+    for event in wheel.read() {
+        notches += match event.unit {
+            MouseScrollUnit::Line => event.y,
+            MouseScrollUnit::Pixel => event.y / 40.0,
+        };
+    }
+    if notches != 0.0 {
+        globe.distance = (globe.distance * 0.9f32.powf(notches)).clamp(1.2, 50.0);
+    }
+    let eye = globe.distance * Vec3::new(globe.pitch.cos() * globe.yaw.sin(), globe.pitch.sin(), globe.pitch.cos() * globe.yaw.cos());
+    for mut transform in &mut camera_query {
+        let target = Transform::from_translation(eye).looking_at(Vec3::ZERO, Vec3::Y);
+        if *transform != target {
+            *transform = target;
+        }
+    }
+}
+
+/// Turn the Earth and the Sun for the simulated time and the chosen frame
+pub fn update_earth(
+    clock: Res<clock::SimClock>,
+    globe: Res<GlobeState>,
+    mut earth_query: Query<&mut Transform, (With<Earth>, Without<GlobeSun>)>,
+    mut sun_query: Query<&mut Transform, (With<GlobeSun>, Without<Earth>)>,
+) {
+    let now = clock.now();
+    let gst = gst_from_datetime(now);
+    let rotation = match globe.frame {
+        GlobeFrame::Inertial => Quat::from_rotation_y(gst as f32),
+        GlobeFrame::EarthFixed => Quat::IDENTITY,
+    };
+    for mut transform in &mut earth_query {
+        transform.rotation = rotation * earth_mesh_rotation();
+    }
+    let (ra, dec) = sun::equatorial_position(now);
+    let sun_direction = to_scene(eci_to_frame(DVec3::new(dec.cos() * ra.cos(), dec.cos() * ra.sin(), dec.sin()), gst, globe.frame)).normalize();
+    for mut transform in &mut sun_query {
+        *transform = Transform::default().looking_to(-sun_direction, Vec3::Y);
+    }
+}
+
+/// Sample one revolution of the tracked satellites, again when the frame or the selection changes
+pub fn update_orbits(
+    clock: Res<clock::SimClock>,
+    sat_query: Query<(Entity, &Satellite)>,
+    visibility: Res<groups::GroupVisibility>,
+    selected: Res<selection::SelectedSatellite>,
+    mut globe: ResMut<GlobeState>,
+) {
+    let now = clock.now();
+    let shown: Vec<(Entity, &Satellite)> = sat_query.iter().filter(|(_, sat)| !groups::is_satellite_hidden(&visibility, sat)).collect();
+    let Some((_, any_sat)) = shown.first() else {
+        globe.orbits.clear();
+        return;
+    };
+    let loaded_config = &any_sat.loaded_config;
+    let fresh = globe.orbits_at.is_some_and(|at| (now - at).num_seconds().abs() < loaded_config.map_track_step_seconds);
+    if fresh && globe.orbits_frame == globe.frame && globe.orbits_selected == selected.0 && !visibility.is_changed() {
+        return;
+    }
+    let tracked: Vec<&(Entity, &Satellite)> = if shown.len() <= loaded_config.map_max_tracks {
+        shown.iter().collect()
+    } else {
+        shown.iter().filter(|(entity, _)| selected.0 == Some(*entity)).collect()
+    };
+    let points = loaded_config.globe_orbit_points.max(8);
+    let frame = globe.frame;
+    globe.orbits = tracked
+        .iter()
+        .map(|(entity, sat)| {
+            let period_seconds = 86400.0 / sat.elements.mean_motion;
+            let orbit = (0..=points)
+                .filter_map(|i| {
+                    let date = now + Duration::milliseconds((period_seconds * 1000.0 * i as f64 / points as f64) as i64);
+                    let (position, _) = eci_state(&sat.history, date)?;
+                    Some(to_scene(eci_to_frame(gcrf_km(&position), gst_from_datetime(date), frame)))
+                })
+                .collect();
+            (*entity, orbit)
+        })
+        .collect();
+    globe.orbits_at = Some(now);
+    globe.orbits_frame = frame;
+    globe.orbits_selected = selected.0;
+}
+
+pub fn draw_globe(
+    clock: Res<clock::SimClock>,
+    globe: Res<GlobeState>,
+    view: Res<view::ViewState>,
+    coastlines: Res<world_map::Coastlines>,
+    sat_query: Query<(Entity, &Satellite)>,
+    visibility: Res<groups::GroupVisibility>,
+    selected: Res<selection::SelectedSatellite>,
+    mut gizmos: Gizmos<GlobeGizmos>,
+) {
+    let loaded_config = &view.loaded_config;
+    let now = clock.now();
+    let gst = gst_from_datetime(now);
+    let surface = |lat: f64, lon: f64| to_scene(ecef_to_frame(geo_to_ecef(lat, lon, 5.0), gst, globe.frame));
+
+    if view.show_grid {
+        let graticule_color = hexstr2color(&loaded_config.map_graticule_color);
+        for lon in (-180..180).step_by(30) {
+            gizmos.linestrip((-90..=90).step_by(5).map(|lat| surface((lat as f64).to_radians(), (lon as f64).to_radians())), graticule_color);
+        }
+        for lat in (-60..=60).step_by(30) {
+            gizmos.linestrip((-180..=180).step_by(5).map(|lon| surface((lat as f64).to_radians(), (lon as f64).to_radians())), graticule_color);
+        }
+    }
+    let coastline_color = hexstr2color(&loaded_config.map_coastline_color);
+    for line in &coastlines.lines {
+        gizmos.linestrip(line.iter().map(|(lat, lon)| surface(*lat, *lon)), coastline_color);
+    }
+
+    // Observer and its horizon cone, on a spherical Earth
+    let (lat, lon) = (loaded_config.user_latitude.to_radians(), loaded_config.user_longitude.to_radians());
+    let observer_ecef = geo_to_ecef(lat, lon, loaded_config.observer_height() / 1000.0);
+    let east = DVec3::new(-lon.sin(), lon.cos(), 0.0);
+    let north = DVec3::new(-lat.sin() * lon.cos(), -lat.sin() * lon.sin(), lat.cos());
+    let up = DVec3::new(lat.cos() * lon.cos(), lat.cos() * lon.sin(), lat.sin());
+    let observer = to_scene(ecef_to_frame(observer_ecef, gst, globe.frame));
+    let observer_color = hexstr2color(&loaded_config.map_observer_color);
+    gizmos.sphere(Isometry3d::from_translation(observer), 0.01, observer_color);
+    let rim: Vec<Vec3> = (0..=36)
+        .map(|i| {
+            let az = (i as f64 * 10.0).to_radians();
+            let direction = east * az.sin() + north * az.cos();
+            to_scene(ecef_to_frame(observer_ecef + direction * loaded_config.globe_cone_range_km, gst, globe.frame))
+        })
+        .collect();
+    for point in rim.iter().step_by(3) {
+        gizmos.line(observer, *point, observer_color.with_alpha(0.4));
+    }
+    gizmos.linestrip(rim, observer_color.with_alpha(0.4));
+
+    if view.show_trails {
+        for (entity, orbit) in &globe.orbits {
+            let Ok((_, sat)) = sat_query.get(*entity) else { continue };
+            let color = if selected.0 == Some(*entity) { hexstr2color(&sat.loaded_config.selection_color) } else { hexstr2color(&sat.style.trail_color) };
+            gizmos.linestrip(orbit.iter().copied(), color);
+        }
+    }
+
+    // Satellites, with a line of sight to the ones above the observer's horizon
+    let size = globe.distance * 0.002;
+    for (entity, sat) in &sat_query {
+        if groups::is_satellite_hidden(&visibility, sat) {
+            continue;
+        }
+        let Some(eci) = sat.gcrf_position.as_ref().map(gcrf_km) else { continue };
+        let position = to_scene(eci_to_frame(eci, gst, globe.frame));
+        let is_selected = selected.0 == Some(entity);
+        let color = if is_selected { hexstr2color(&sat.loaded_config.selection_color) } else { hexstr2color(&sat.style.color) };
+        gizmos.sphere(Isometry3d::from_translation(position), size * sat.style.size * if is_selected { 2.0 } else { 1.0 }, color).resolution(6);
+        let line_of_sight = rotate_z(eci, -gst) - observer_ecef;
+        if line_of_sight.normalize().dot(up) > 0.0 {
+            gizmos.line(observer, position, color.with_alpha(0.6));
+        }
+    }
+}
@@ -0,0 +1,348 @@
+use bevy::prelude::*;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::{clock, terrain, view, Satellite, SatelliteTrail, Star};
+
+const GPSD_DEFAULT_PORT: u16 = 2947;
+/// Name of the station that follows the GPS when stations are configured
+pub const GPS_STATION: &str = "gps";
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// A position from the GPS, degrees and meters above mean sea level
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GpsFix {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub altitude: Option<f64>,
+}
+
+/// Where fixes come from, parsed from `gps_source`
+#[derive(Debug, Clone, PartialEq)]
+pub enum GpsSource {
+    /// gpsd JSON protocol, "gpsd" or "gpsd://host[:port]"
+    Gpsd(String),
+    /// NMEA 0183 sentences over TCP, "tcp://host:port"
+    NmeaTcp(String),
+    /// NMEA 0183 sentences from a serial device (already set to the right baud rate), pty or file
+    NmeaFile(PathBuf),
+}
+
+impl GpsSource {
+    pub fn parse(source: &str) -> Option<Self> {
+        let source = source.trim();
+        if source.is_empty() {
+            return None;
+        }
+        if source == "gpsd" {
+            return Some(GpsSource::Gpsd(format!("localhost:{}", GPSD_DEFAULT_PORT)));
+        }
+        if let Some(address) = source.strip_prefix("gpsd://") {
+            let address = if address.contains(':') { address.to_string() } else { format!("{}:{}", address, GPSD_DEFAULT_PORT) };
+            return Some(GpsSource::Gpsd(address));
+        }
+        if let Some(address) = source.strip_prefix("tcp://") {
+            return Some(GpsSource::NmeaTcp(address.to_string()));
+        }
+        Some(GpsSource::NmeaFile(PathBuf::from(source)))
+    }
+}
+
+/// Verify the checksum of an NMEA sentence and return its fields, without the talker ID in the first one
+fn nmea_fields(sentence: &str) -> Option<Vec<&str>> {
+    let body = sentence.trim().strip_prefix('$')?;
+    let (body, checksum) = match body.split_once('*') {
+        Some((body, checksum)) => (body, Some(checksum)),
+        None => (body, None),
+    };
+    if let Some(checksum) = checksum {
+        let expected = u8::from_str_radix(checksum.get(..2)?, 16).ok()?;
+        if body.bytes().fold(0, |sum, byte| sum ^ byte) != expected {
+            return None;
+        }
+    }
+    let mut fields: Vec<&str> = body.split(',').collect();
+    // "GPGGA", "GNRMC", ... keep only the sentence type
+    fields[0] = fields[0].get(2..)?;
+    Some(fields)
+}
+
+/// "4851.1234" and "N" to decimal degrees
+fn nmea_coordinate(value: &str, hemisphere: &str) -> Option<f64> {
+    let value: f64 = value.parse().ok()?;
+    let degrees = (value / 100.0).trunc();
+    let decimal = degrees + (value - degrees * 100.0) / 60.0;
+    match hemisphere {
+        "N" | "E" => Some(decimal),
+        "S" | "W" => Some(-decimal),
+        _ => None,
+    }
+}
+
+/// A fix from a GGA (with altitude) or RMC sentence
+pub fn parse_nmea(sentence: &str) -> Option<GpsFix> {
+    let fields = nmea_fields(sentence)?;
+    match fields[0] {
+        "GGA" => {
+            if fields.get(6)?.parse::<u8>().ok()? == 0 {
+                return None;
+            }
+            Some(GpsFix {
+                latitude: nmea_coordinate(fields.get(2)?, fields.get(3)?)?,
+                longitude: nmea_coordinate(fields.get(4)?, fields.get(5)?)?,
+                altitude: fields.get(9).and_then(|altitude| altitude.parse().ok()),
+            })
+        }
+        "RMC" => {
+            if *fields.get(2)? != "A" {
+                return None;
+            }
+            Some(GpsFix {
+                latitude: nmea_coordinate(fields.get(3)?, fields.get(4)?)?,
+                longitude: nmea_coordinate(fields.get(5)?, fields.get(6)?)?,
+                altitude: None,
+            })
+        }
+        _ => None,
+    }
+}
+
+/// A fix from a gpsd TPV report with at least a 2D fix
+pub fn parse_gpsd(line: &str) -> Option<GpsFix> {
+    let report: serde_json::Value = serde_json::from_str(line).ok()?;
+    if report["class"].as_str()? != "TPV" || report["mode"].as_u64()? < 2 {
+        return None;
+    }
+    Some(GpsFix {
+        latitude: report["lat"].as_f64()?,
+        longitude: report["lon"].as_f64()?,
+        altitude: report["altMSL"].as_f64().or(report["alt"].as_f64()),
+    })
+}
+
+/// Read fixes line by line until the stream ends or fails
+fn read_fixes(reader: impl BufRead, parse: fn(&str) -> Option<GpsFix>, latest: &Mutex<Option<GpsFix>>, follow: bool) {
+    let mut reader = reader;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        match reader.read_line(&mut line) {
+            Ok(0) if follow => std::thread::sleep(Duration::from_secs(1)),
+            Ok(0) | Err(_) => return,
+            Ok(_) => {
+                if let Some(fix) = parse(&line) {
+                    *latest.lock().unwrap() = Some(fix);
+                }
+            }
+        }
+    }
+}
+
+fn run_source(source: GpsSource, latest: Arc<Mutex<Option<GpsFix>>>) {
+    loop {
+        match &source {
+            GpsSource::Gpsd(address) => match TcpStream::connect(address) {
+                Ok(mut stream) => {
+                    println!("Reading GPS fixes from gpsd at {}", address);
+                    if stream.write_all(b"?WATCH={\"enable\":true,\"json\":true};\n").is_ok() {
+                        read_fixes(BufReader::new(stream), parse_gpsd, &latest, false);
+                    }
+                }
+                Err(_) => eprintln!("Couldn't connect to gpsd at {}", address),
+            },
+            GpsSource::NmeaTcp(address) => match TcpStream::connect(address) {
+                Ok(stream) => {
+                    println!("Reading NMEA sentences from {}", address);
+                    read_fixes(BufReader::new(stream), parse_nmea, &latest, false);
+                }
+                Err(_) => eprintln!("Couldn't connect to the NMEA stream at {}", address),
+            },
+            GpsSource::NmeaFile(path) => match std::fs::File::open(path) {
+                Ok(file) => {
+                    println!("Reading NMEA sentences from {}", path.display());
+                    // Keep waiting at the end of files, like a pty or serial device would
+                    read_fixes(BufReader::new(file), parse_nmea, &latest, true);
+                }
+                Err(_) => eprintln!("Couldn't open the NMEA source {}", path.display()),
+            },
+        }
+        eprintln!("Lost the GPS source, retrying in {} s", RETRY_DELAY.as_secs());
+        std::thread::sleep(RETRY_DELAY);
+    }
+}
+
+/// The most recent fix, written by the reader thread
+#[derive(Resource, Clone, Default)]
+pub struct GpsReceiver {
+    pub latest: Arc<Mutex<Option<GpsFix>>>,
+}
+
+impl GpsReceiver {
+    /// Start reading the configured source in the background, if any
+    pub fn from_config(config: &Config) -> Self {
+        let receiver = GpsReceiver::default();
+        if let Some(source) = GpsSource::parse(&config.gps_source) {
+            let latest = receiver.latest.clone();
+            std::thread::spawn(move || run_source(source, latest));
+        }
+        receiver
+    }
+}
+
+/// Great circle distance plus altitude change, in meters
+fn distance_meters(config: &Config, fix: &GpsFix) -> f64 {
+    let (lat_a, lat_b) = (config.user_latitude.to_radians(), fix.latitude.to_radians());
+    let d_lat = lat_b - lat_a;
+    let d_lon = (fix.longitude - config.user_longitude).to_radians();
+    let a = (d_lat / 2.0).sin().powi(2) + lat_a.cos() * lat_b.cos() * (d_lon / 2.0).sin().powi(2);
+    let ground = 2.0 * 6_371_000.0 * a.sqrt().asin();
+    let vertical = fix.altitude.map_or(0.0, |altitude| altitude - config.user_altitude);
+    ground.hypot(vertical)
+}
+
+fn move_to(config: &mut Config, fix: &GpsFix) {
+    config.move_observer(fix.latitude, fix.longitude, fix.altitude.unwrap_or(config.user_altitude));
+}
+
+/// Fixes only move the observer while the active station is the "gps" one, or when there are no stations,
+/// so switching to a fixed station isn't undone by the next fix
+pub fn follows_gps(config: &Config) -> bool {
+    config.observers.is_empty() || config.active_observer.eq_ignore_ascii_case(GPS_STATION)
+}
+
+/// Move the observer to the GPS position once it's further than the threshold, recomputing the tracks
+pub fn apply_gps_fix(
+    receiver: Res<GpsReceiver>,
+    mut view: ResMut<view::ViewState>,
+    mut sat_query: Query<&mut Satellite>,
+    mut trail_query: Query<&mut SatelliteTrail>,
+    mut star_query: Query<&mut Star>,
+    mut generation: ResMut<clock::TrackGeneration>,
+) {
+    if !follows_gps(&view.loaded_config) {
+        return;
+    }
+    let Some(mut fix) = *receiver.latest.lock().unwrap() else { return };
+    fix.altitude = fix.altitude.map(|altitude| terrain::from_mean_sea_level(fix.latitude, fix.longitude, altitude, &view.loaded_config));
+    if distance_meters(&view.loaded_config, &fix) < view.loaded_config.gps_move_threshold_meters {
+        return;
+    }
+    println!("GPS moved the observer to {:.5}, {:.5}", fix.latitude, fix.longitude);
+    move_to(&mut view.loaded_config, &fix);
+    for mut sat in &mut sat_query {
+        move_to(&mut sat.loaded_config, &fix);
+    }
+    for mut trail in &mut trail_query {
+        move_to(&mut trail.loaded_config, &fix);
+    }
+    for mut star in &mut star_query {
+        move_to(&mut star.loaded_config, &fix);
+    }
+    generation.0 += 1;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const GGA: &str = "$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*47";
+    const RMC: &str = "$GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W*6A";
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn parses_gga_with_altitude() {
+        let fix = parse_nmea(GGA).unwrap();
+        assert!(close(fix.latitude, 48.0 + 7.038 / 60.0));
+        assert!(close(fix.longitude, 11.0 + 31.0 / 60.0));
+        assert_eq!(fix.altitude, Some(545.4));
+    }
+
+    #[test]
+    fn parses_rmc_without_altitude() {
+        let fix = parse_nmea(RMC).unwrap();
+        assert!(close(fix.latitude, 48.0 + 7.038 / 60.0));
+        assert!(close(fix.longitude, 11.0 + 31.0 / 60.0));
+        assert_eq!(fix.altitude, None);
+    }
+
+    #[test]
+    fn rejects_bad_checksums() {
+        assert_eq!(parse_nmea(&GGA.replace("*47", "*48")), None);
+        assert_eq!(parse_nmea(&RMC.replace("*6A", "*6B")), None);
+        assert_eq!(parse_nmea(&GGA.replace("4807.038", "4807.039")), None);
+    }
+
+    #[test]
+    fn accepts_sentences_without_checksum() {
+        assert_eq!(parse_nmea(GGA.split('*').next().unwrap()), parse_nmea(GGA));
+    }
+
+    #[test]
+    fn ignores_sentences_without_a_fix() {
+        assert_eq!(parse_nmea("$GPGGA,123519,,,,,0,00,,,M,,M,,*6B"), None);
+        assert_eq!(parse_nmea("$GPRMC,123519,V,4807.038,N,01131.000,E,,,230394,,*0A"), None);
+        assert_eq!(parse_nmea("$GPGSV,3,1,11,03,03,111,00*4A"), None);
+        assert_eq!(parse_nmea("garbage"), None);
+    }
+
+    #[test]
+    fn southern_and_western_hemispheres_are_negative() {
+        let fix = parse_nmea("$GNGGA,010203.00,3351.300,S,15112.600,E,1,10,0.8,12.0,M,22.0,M,,*52").unwrap();
+        assert!(close(fix.latitude, -(33.0 + 51.3 / 60.0)));
+        assert!(close(fix.longitude, 151.0 + 12.6 / 60.0));
+        let fix = parse_nmea("$GPRMC,010203,A,4042.768,N,07400.360,W,000.0,000.0,010124,,*04").unwrap();
+        assert!(close(fix.latitude, 40.0 + 42.768 / 60.0));
+        assert!(close(fix.longitude, -(74.0 + 0.36 / 60.0)));
+    }
+
+    #[test]
+    fn parses_gpsd_tpv_reports() {
+        let fix = parse_gpsd(r#"{"class":"TPV","mode":3,"lat":48.1173,"lon":11.5167,"altMSL":545.4,"alt":545.0}"#).unwrap();
+        assert_eq!(fix, GpsFix { latitude: 48.1173, longitude: 11.5167, altitude: Some(545.4) });
+        let fix = parse_gpsd(r#"{"class":"TPV","mode":3,"lat":48.1173,"lon":11.5167,"alt":545.0}"#).unwrap();
+        assert_eq!(fix.altitude, Some(545.0));
+        let fix = parse_gpsd(r#"{"class":"TPV","mode":2,"lat":48.1173,"lon":11.5167}"#).unwrap();
+        assert_eq!(fix.altitude, None);
+    }
+
+    #[test]
+    fn ignores_gpsd_reports_without_a_fix() {
+        assert_eq!(parse_gpsd(r#"{"class":"TPV","mode":1}"#), None);
+        assert_eq!(parse_gpsd(r#"{"class":"TPV","mode":0,"lat":48.1,"lon":11.5}"#), None);
+        assert_eq!(parse_gpsd(r#"{"class":"SKY","satellites":[]}"#), None);
+        assert_eq!(parse_gpsd(r#"{"class":"VERSION","release":"3.25"}"#), None);
+    }
+
+    #[test]
+    fn parses_gps_sources() {
+        assert_eq!(GpsSource::parse(""), None);
+        assert_eq!(GpsSource::parse("gpsd"), Some(GpsSource::Gpsd("localhost:2947".to_string())));
+        assert_eq!(GpsSource::parse("gpsd://pi.local"), Some(GpsSource::Gpsd("pi.local:2947".to_string())));
+        assert_eq!(GpsSource::parse("gpsd://pi.local:3000"), Some(GpsSource::Gpsd("pi.local:3000".to_string())));
+        assert_eq!(GpsSource::parse("tcp://192.168.1.5:10110"), Some(GpsSource::NmeaTcp("192.168.1.5:10110".to_string())));
+        assert_eq!(GpsSource::parse("/dev/ttyACM0"), Some(GpsSource::NmeaFile(PathBuf::from("/dev/ttyACM0"))));
+    }
+
+    #[test]
+    fn read_fixes_keeps_the_latest_valid_fix() {
+        let stream = format!("{}\r\n$GPGSV,3,1,11,03,03,111,00*4A\r\n{}\r\n$GPGGA,123519,,,,,0,00,,,M,,M,,*6B\r\n", RMC, GGA);
+        let latest = Mutex::new(None);
+        read_fixes(Cursor::new(stream), parse_nmea, &latest, false);
+        assert_eq!(*latest.lock().unwrap(), parse_nmea(GGA));
+    }
+
+    #[test]
+    fn read_fixes_leaves_nothing_without_a_fix() {
+        let latest = Mutex::new(None);
+        read_fixes(Cursor::new("$GPGGA,123519,,,,,0,00,,,M,,M,,*6B\n"), parse_nmea, &latest, false);
+        assert_eq!(*latest.lock().unwrap(), None);
+    }
+}
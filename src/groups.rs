@@ -0,0 +1,106 @@
+use bevy::prelude::*;
+use homedir::my_home;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+
+use crate::config::{Config, SatGroup};
+use crate::{keymap, view, Satellite, SatelliteTrail};
+
+fn membership_path() -> PathBuf {
+    let mut path = my_home().unwrap().expect("couldn't get home directory");
+    path.push(".config/ontake/tasogare/GROUPS");
+    path
+}
+
+/// Remember which objects were fetched from the sources of each group
+pub fn save_membership(membership: &HashMap<String, Vec<u64>>) {
+    match serde_json::to_string(membership) {
+        Ok(contents) => {
+            if std::fs::write(membership_path(), contents).is_err() {
+                eprintln!("Failed to write group membership");
+            }
+        }
+        Err(_) => eprintln!("Failed to serialize group membership"),
+    }
+}
+
+/// Add the objects fetched from each group's sources to its NORAD ID list
+pub fn apply_membership(config: &mut Config) {
+    let membership: HashMap<String, Vec<u64>> = match std::fs::read_to_string(membership_path()) {
+        Ok(contents) => serde_json::from_str(&contents).unwrap_or_default(),
+        Err(_) => return,
+    };
+    for group in &mut config.sat_groups {
+        if let Some(members) = membership.get(&group.name) {
+            for norad_id in members {
+                if !group.norad_ids.contains(norad_id) {
+                    group.norad_ids.push(*norad_id);
+                }
+            }
+        }
+    }
+}
+
+/// Groups whose members are currently hidden from the sky view, pass lists and exports
+#[derive(Resource, Default, Debug)]
+pub struct GroupVisibility {
+    pub groups: Vec<SatGroup>,
+    pub hidden: HashSet<String>,
+}
+
+impl GroupVisibility {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            groups: config.sat_groups.clone(),
+            hidden: config.sat_groups.iter().filter(|group| group.hidden).map(|group| group.name.clone()).collect(),
+        }
+    }
+
+    /// An object is hidden as soon as one of its groups is
+    pub fn is_hidden(&self, norad_id: u64, object_type: Option<&str>) -> bool {
+        self.groups
+            .iter()
+            .any(|group| self.hidden.contains(&group.name) && group.contains(norad_id, object_type))
+    }
+
+    pub fn toggle(&mut self, index: usize) {
+        if let Some(group) = self.groups.get(index) {
+            if !self.hidden.remove(&group.name) {
+                self.hidden.insert(group.name.clone());
+                println!("Hiding group {}", group.name);
+            } else {
+                println!("Showing group {}", group.name);
+            }
+        }
+    }
+}
+
+pub fn toggle_groups(keys: Res<ButtonInput<KeyCode>>, bindings: Res<keymap::Keybindings>, mut visibility: ResMut<GroupVisibility>) {
+    for (index, action) in keymap::Action::TOGGLE_GROUPS.iter().enumerate() {
+        if bindings.just_pressed(&keys, *action) {
+            visibility.toggle(index);
+        }
+    }
+}
+
+pub fn apply_group_visibility(visibility: Res<GroupVisibility>, mode: Res<view::ViewMode>, mut sat_query: Query<(&Satellite, &mut Visibility)>) {
+    if !visibility.is_changed() && !mode.is_changed() {
+        return;
+    }
+    for (sat, mut sat_visibility) in &mut sat_query {
+        // The dome markers are hidden on the other views, which draw their own
+        *sat_visibility = if is_satellite_hidden(&visibility, sat) || *mode != view::ViewMode::Dome {
+            Visibility::Hidden
+        } else {
+            Visibility::Inherited
+        };
+    }
+}
+
+pub fn is_satellite_hidden(visibility: &GroupVisibility, sat: &Satellite) -> bool {
+    visibility.is_hidden(sat.norad_id, sat.satcat.as_ref().map(|entry| entry.object_type.as_str()))
+}
+
+pub fn is_trail_hidden(visibility: &GroupVisibility, trail: &SatelliteTrail) -> bool {
+    visibility.is_hidden(trail.norad_id, trail.object_type.as_deref())
+}
@@ -0,0 +1,195 @@
+use bevy::prelude::*;
+use chrono::{DateTime, Duration, FixedOffset, Utc};
+use chrono_tz::Tz;
+use std::time::Instant;
+
+use crate::{clock, config, groups, gst_from_datetime, hexstr2color, locator, passes, sun, view, Satellite};
+
+/// A marker component for the heads-up display about the observer, time and next pass
+#[derive(Component)]
+pub struct Hud;
+
+/// Passes shown on the HUD, recomputed at most once a second or when the tracks change
+#[derive(Default)]
+pub struct HudPasses {
+    computed_at: Option<Instant>,
+    generation: u64,
+    passes: Vec<passes::Pass>,
+}
+
+pub fn setup_hud(mut commands: Commands, asset_server: Res<AssetServer>, loaded_config: Res<config::Config>) {
+    if !loaded_config.hud_enabled {
+        return;
+    }
+    commands.spawn((
+        Text::new(""),
+        TextFont {
+            font: asset_server.load("fonts/FiraMono-Bold.ttf"),
+            font_size: loaded_config.info_panel_font_size,
+            ..Default::default()
+        },
+        TextColor(hexstr2color(&loaded_config.sat_name_color)),
+        BackgroundColor(hexstr2color(&loaded_config.sat_name_bg_color)),
+        Node {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(10.),
+            right: Val::Px(10.),
+            padding: UiRect::all(Val::Px(6.)),
+            ..default()
+        },
+        Hud,
+    ));
+}
+
+/// "1h 02m 03s", "02m 03s" or "03s"
+fn format_countdown(duration: Duration) -> String {
+    let seconds = duration.num_seconds().max(0);
+    match (seconds / 3600, seconds % 3600 / 60, seconds % 60) {
+        (0, 0, s) => format!("{:02}s", s),
+        (0, m, s) => format!("{:02}m {:02}s", m, s),
+        (h, m, s) => format!("{}h {:02}m {:02}s", h, m, s),
+    }
+}
+
+/// Hours, minutes and seconds of an angle (radians) of sidereal time
+fn format_sidereal(angle: f64) -> String {
+    let seconds = (angle.rem_euclid(2.0 * std::f64::consts::PI) / (2.0 * std::f64::consts::PI) * 86_400.0) as u32;
+    format!("{:02}:{:02}:{:02}", seconds / 3600, seconds % 3600 / 60, seconds % 60)
+}
+
+/// The simulated time in the configured time zone (an IANA name or a fixed offset), or the system's
+fn local_time(now: DateTime<Utc>, time_zone: &str) -> String {
+    if let Ok(zone) = time_zone.trim().parse::<Tz>() {
+        return now.with_timezone(&zone).format("%H:%M:%S %Z").to_string();
+    }
+    match time_zone.trim().parse::<FixedOffset>() {
+        Ok(offset) => now.with_timezone(&offset).format("%H:%M:%S %:z").to_string(),
+        Err(_) => now.with_timezone(&chrono::Local).format("%H:%M:%S %Z").to_string(),
+    }
+}
+
+fn next_pass_line(upcoming: &[passes::Pass], now: DateTime<Utc>) -> String {
+    let current = upcoming.iter().filter(|pass| pass.aos <= now && pass.los > now).min_by_key(|pass| pass.los);
+    let next = upcoming.iter().find(|pass| pass.aos > now);
+    let mut parts = Vec::new();
+    if let Some(pass) = current {
+        parts.push(format!("LOS {} in {} (max {:.0}°)", pass.name, format_countdown(pass.los - now), pass.max_elevation.to_degrees()));
+    }
+    if let Some(pass) = next {
+        parts.push(format!("AOS {} in {} (max {:.0}°)", pass.name, format_countdown(pass.aos - now), pass.max_elevation.to_degrees()));
+    }
+    if parts.is_empty() {
+        "No upcoming passes".to_string()
+    } else {
+        parts.join("  ")
+    }
+}
+
+pub fn update_hud(
+    view: Res<view::ViewState>,
+    clock: Res<clock::SimClock>,
+    generation: Res<clock::TrackGeneration>,
+    sat_query: Query<&Satellite>,
+    visibility: Res<groups::GroupVisibility>,
+    mut hud_passes: Local<HudPasses>,
+    mut hud_query: Query<&mut Text, With<Hud>>,
+) {
+    let Ok(mut text) = hud_query.get_single_mut() else { return };
+    let loaded_config = &view.loaded_config;
+    let now = clock.now();
+    let stale = hud_passes.computed_at.is_none_or(|computed_at| computed_at.elapsed().as_secs_f64() >= 1.0);
+    if loaded_config.hud_items.iter().any(|item| item == "next_pass") && (stale || hud_passes.generation != generation.0 || visibility.is_changed()) {
+        hud_passes.passes = passes::upcoming_passes(sat_query.iter(), now, &visibility);
+        hud_passes.computed_at = Some(Instant::now());
+        hud_passes.generation = generation.0;
+    }
+    let (lat, lon) = (loaded_config.user_latitude.to_radians(), loaded_config.user_longitude.to_radians());
+    let lines: Vec<String> = loaded_config
+        .hud_items
+        .iter()
+        .filter_map(|item| match item.as_str() {
+            "observer" => Some(format!(
+                "{}  {}  {:.4}° {:.4}°  {:.0} m above WGS84",
+                loaded_config.active_observer,
+                locator::to_maidenhead(loaded_config.user_latitude, loaded_config.user_longitude, loaded_config.hud_locator_length),
+                loaded_config.user_latitude,
+                loaded_config.user_longitude,
+                loaded_config.observer_height()
+            )),
+            "clock" => Some(format!("{} UTC  {} local", now.format("%Y-%m-%d %H:%M:%S"), local_time(now, &loaded_config.hud_time_zone))),
+            "sidereal" => Some(format!("LST {}  GST {}", format_sidereal(gst_from_datetime(now) + lon), format_sidereal(gst_from_datetime(now)))),
+            "sun" => {
+                let elevation = sun::elevation(now, lat, lon);
+                Some(format!("Sun {:+.1}°  {}", elevation.to_degrees(), sun::twilight_phase(elevation)))
+            }
+            "next_pass" => Some(next_pass_line(&hud_passes.passes, now)),
+            _ => None,
+        })
+        .collect();
+    let contents = lines.join("\n");
+    if text.0 != contents {
+        text.0 = contents;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn pass(name: &str, aos: DateTime<Utc>, minutes: i64, max_elevation_degrees: f32) -> passes::Pass {
+        passes::Pass { norad_id: 0, name: name.to_string(), aos, los: aos + Duration::minutes(minutes), max_elevation: max_elevation_degrees.to_radians() }
+    }
+
+    #[test]
+    fn formats_countdowns() {
+        assert_eq!(format_countdown(Duration::seconds(3)), "03s");
+        assert_eq!(format_countdown(Duration::seconds(123)), "02m 03s");
+        assert_eq!(format_countdown(Duration::seconds(3723)), "1h 02m 03s");
+        assert_eq!(format_countdown(Duration::seconds(-5)), "00s");
+    }
+
+    #[test]
+    fn formats_sidereal_time() {
+        assert_eq!(format_sidereal(0.0), "00:00:00");
+        assert_eq!(format_sidereal(std::f64::consts::PI), "12:00:00");
+        assert_eq!(format_sidereal(-std::f64::consts::FRAC_PI_2), "18:00:00");
+    }
+
+    #[test]
+    fn uses_iana_time_zones_with_daylight_saving() {
+        let summer = Utc.with_ymd_and_hms(2024, 7, 1, 12, 0, 0).unwrap();
+        let winter = Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap();
+        assert_eq!(local_time(summer, "Europe/Paris"), "14:00:00 CEST");
+        assert_eq!(local_time(winter, "Europe/Paris"), "13:00:00 CET");
+        assert_eq!(local_time(winter, "Asia/Tokyo"), "21:00:00 JST");
+    }
+
+    #[test]
+    fn falls_back_to_fixed_offsets() {
+        let now = Utc.with_ymd_and_hms(2024, 7, 1, 12, 0, 0).unwrap();
+        assert_eq!(local_time(now, "+09:00"), "21:00:00 +09:00");
+        assert_eq!(local_time(now, "-05:30"), "06:30:00 -05:30");
+    }
+
+    #[test]
+    fn reads_the_old_hud_utc_offset_key() {
+        let loaded_config: crate::config::Config = serde_json::from_str(r#"{"hud_utc_offset": "+09:00"}"#).unwrap();
+        assert_eq!(loaded_config.hud_time_zone, "+09:00");
+        let loaded_config: crate::config::Config = serde_json::from_str(r#"{"hud_time_zone": "Asia/Tokyo"}"#).unwrap();
+        assert_eq!(loaded_config.hud_time_zone, "Asia/Tokyo");
+    }
+
+    #[test]
+    fn describes_current_and_next_passes() {
+        let now = Utc.with_ymd_and_hms(2024, 7, 1, 12, 0, 0).unwrap();
+        assert_eq!(next_pass_line(&[], now), "No upcoming passes");
+        let upcoming = [
+            pass("OLD", now - Duration::minutes(20), 10, 30.0),
+            pass("ISS", now - Duration::minutes(2), 7, 61.0),
+            pass("NOAA 19", now + Duration::seconds(3723), 12, 24.0),
+        ];
+        assert_eq!(next_pass_line(&upcoming, now), "LOS ISS in 05m 00s (max 61°)  AOS NOAA 19 in 1h 02m 03s (max 24°)");
+        assert_eq!(next_pass_line(&upcoming[..1], now), "No upcoming passes");
+    }
+}
@@ -0,0 +1,87 @@
+use bevy::prelude::*;
+
+use crate::{clock, config, groups, hexstr2color, passes, range_rate, transmitters, Satellite};
+
+/// A marker component for the panel listing the satellites currently in view
+#[derive(Component)]
+pub struct InfoPanel;
+
+pub fn setup_info_panel(mut commands: Commands, asset_server: Res<AssetServer>, loaded_config: Res<config::Config>) {
+    if !loaded_config.info_panel_enabled {
+        return;
+    }
+    commands.spawn((
+        Text::new(""),
+        TextFont {
+            font: asset_server.load("fonts/FiraMono-Bold.ttf"),
+            font_size: loaded_config.info_panel_font_size,
+            ..Default::default()
+        },
+        TextColor(hexstr2color(&loaded_config.sat_name_color)),
+        BackgroundColor(hexstr2color(&loaded_config.sat_name_bg_color)),
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Px(10.),
+            right: Val::Px(10.),
+            padding: UiRect::all(Val::Px(6.)),
+            ..default()
+        },
+        InfoPanel,
+    ));
+}
+
+pub fn update_info_panel(sat_query: Query<&Satellite>, mut panel_query: Query<(&mut Text, &mut Visibility), With<InfoPanel>>, clock: Res<clock::SimClock>, visibility: Res<groups::GroupVisibility>) {
+    let now = clock.now();
+    let current_date = now.timestamp_millis();
+    let mut lines = Vec::new();
+    for sat in &sat_query {
+        let in_view = !sat.times.is_empty() && sat.times[0] <= current_date && sat.times[sat.times.len() - 1] >= current_date;
+        if !in_view || visibility.is_hidden(sat.norad_id, sat.satcat.as_ref().map(|entry| entry.object_type.as_str())) {
+            continue;
+        }
+        let mut line = match &sat.satcat {
+            Some(entry) => format!(
+                "{} ({})  {}  {}  launched {}  RCS {}  {}",
+                sat.name,
+                sat.norad_id,
+                entry.object_type_name(),
+                entry.owner,
+                entry.launch_date,
+                entry.rcs_size(),
+                entry.status_name()
+            ),
+            None => format!("{} ({})", sat.name, sat.norad_id),
+        };
+        let sat_range_rate = range_rate(&sat.history, now, &sat.loaded_config);
+        for transmitter in &sat.transmitters {
+            line.push_str(&format!("\n  {}", transmitter.describe()));
+        }
+        if let (Some(downlink), Some(sat_range_rate)) = (transmitters::downlink_frequency_hz(sat.norad_id, &sat.transmitters, &sat.loaded_config), sat_range_rate) {
+            let shift = transmitters::doppler_shift_hz(downlink, sat_range_rate);
+            line.push_str(&format!("\n  tune {:.4} MHz (doppler {:+.2} kHz)", (downlink as f64 + shift) / 1e6, shift / 1e3));
+        }
+        lines.push(line);
+    }
+    lines.sort();
+    let upcoming: Vec<passes::Pass> = passes::upcoming_passes(sat_query.iter(), now, &visibility)
+        .into_iter()
+        .filter(|pass| pass.aos > now)
+        .take(sat_query.iter().next().map(|sat| sat.loaded_config.pass_list_length).unwrap_or(0))
+        .collect();
+    if !upcoming.is_empty() {
+        lines.push("Next passes:".to_string());
+        for pass in upcoming {
+            lines.push(format!("  {}  {} - {}", pass.name, pass.aos.format("%H:%M:%S"), pass.los.format("%H:%M:%S")));
+        }
+    }
+    let contents = lines.join("\n");
+    for (mut text, mut visibility) in &mut panel_query {
+        if text.0 != contents {
+            text.0 = contents.clone();
+        }
+        let wanted = if lines.is_empty() { Visibility::Hidden } else { Visibility::Inherited };
+        if *visibility != wanted {
+            *visibility = wanted;
+        }
+    }
+}
@@ -0,0 +1,456 @@
+use bevy::{prelude::*, render::view::screenshot::{save_to_disk, Screenshot}};
+use chrono::Utc;
+use homedir::my_home;
+
+use crate::config::{self, Keymap};
+use crate::{clock, globe, groups, hexstr2color, selection, view, Satellite};
+
+const TIME_RATES: [f64; 12] = [1.0, 2.0, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1800.0, 3600.0, 7200.0];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    ToggleStars,
+    ToggleGrid,
+    ToggleTrails,
+    ToggleLabels,
+    RotateLeft,
+    RotateRight,
+    ZoomIn,
+    ZoomOut,
+    NextSatellite,
+    PreviousSatellite,
+    TimeFaster,
+    TimeSlower,
+    TimePause,
+    TimeReset,
+    Screenshot,
+    ExportPasses,
+    ToggleHelp,
+    FollowSelected,
+    ResetView,
+    ToggleMap,
+    ToggleGlobeFrame,
+    ExportFootprints,
+    NextObserver,
+    ToggleSchedule,
+    ToggleGroup1,
+    ToggleGroup2,
+    ToggleGroup3,
+    ToggleGroup4,
+    ToggleGroup5,
+    ToggleGroup6,
+    ToggleGroup7,
+    ToggleGroup8,
+    ToggleGroup9,
+}
+
+impl Action {
+    pub const ALL: [Action; 33] = [
+        Action::ToggleStars,
+        Action::ToggleGrid,
+        Action::ToggleTrails,
+        Action::ToggleLabels,
+        Action::RotateLeft,
+        Action::RotateRight,
+        Action::ZoomIn,
+        Action::ZoomOut,
+        Action::NextSatellite,
+        Action::PreviousSatellite,
+        Action::TimeFaster,
+        Action::TimeSlower,
+        Action::TimePause,
+        Action::TimeReset,
+        Action::Screenshot,
+        Action::ExportPasses,
+        Action::ToggleHelp,
+        Action::FollowSelected,
+        Action::ResetView,
+        Action::ToggleMap,
+        Action::ToggleGlobeFrame,
+        Action::ExportFootprints,
+        Action::NextObserver,
+        Action::ToggleSchedule,
+        Action::ToggleGroup1,
+        Action::ToggleGroup2,
+        Action::ToggleGroup3,
+        Action::ToggleGroup4,
+        Action::ToggleGroup5,
+        Action::ToggleGroup6,
+        Action::ToggleGroup7,
+        Action::ToggleGroup8,
+        Action::ToggleGroup9,
+    ];
+
+    /// Visibility toggles of the first nine entries of `sat_groups`, in order
+    pub const TOGGLE_GROUPS: [Action; 9] = [
+        Action::ToggleGroup1,
+        Action::ToggleGroup2,
+        Action::ToggleGroup3,
+        Action::ToggleGroup4,
+        Action::ToggleGroup5,
+        Action::ToggleGroup6,
+        Action::ToggleGroup7,
+        Action::ToggleGroup8,
+        Action::ToggleGroup9,
+    ];
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Action::ToggleStars => "toggle stars",
+            Action::ToggleGrid => "toggle grid",
+            Action::ToggleTrails => "toggle trails",
+            Action::ToggleLabels => "toggle labels",
+            Action::RotateLeft => "rotate view left",
+            Action::RotateRight => "rotate view right",
+            Action::ZoomIn => "zoom in",
+            Action::ZoomOut => "zoom out",
+            Action::NextSatellite => "select next satellite",
+            Action::PreviousSatellite => "select previous satellite",
+            Action::TimeFaster => "speed up time",
+            Action::TimeSlower => "slow down time",
+            Action::TimePause => "pause/resume time",
+            Action::TimeReset => "back to real time",
+            Action::Screenshot => "take a screenshot",
+            Action::ExportPasses => "export upcoming passes",
+            Action::ToggleHelp => "toggle this help",
+            Action::FollowSelected => "keep the selected satellite centered",
+            Action::ResetView => "reset zoom and pan",
+            Action::ToggleMap => "cycle sky dome, world map and globe",
+            Action::ToggleGlobeFrame => "globe: switch inertial/Earth-fixed frame",
+            Action::ExportFootprints => "export coverage footprints",
+            Action::NextObserver => "switch to the next station",
+            Action::ToggleSchedule => "toggle the combined pass schedule",
+            Action::ToggleGroup1 => "toggle satellite group 1",
+            Action::ToggleGroup2 => "toggle satellite group 2",
+            Action::ToggleGroup3 => "toggle satellite group 3",
+            Action::ToggleGroup4 => "toggle satellite group 4",
+            Action::ToggleGroup5 => "toggle satellite group 5",
+            Action::ToggleGroup6 => "toggle satellite group 6",
+            Action::ToggleGroup7 => "toggle satellite group 7",
+            Action::ToggleGroup8 => "toggle satellite group 8",
+            Action::ToggleGroup9 => "toggle satellite group 9",
+        }
+    }
+
+    fn binding<'a>(&self, keymap: &'a Keymap) -> &'a str {
+        match self {
+            Action::ToggleStars => &keymap.toggle_stars,
+            Action::ToggleGrid => &keymap.toggle_grid,
+            Action::ToggleTrails => &keymap.toggle_trails,
+            Action::ToggleLabels => &keymap.toggle_labels,
+            Action::RotateLeft => &keymap.rotate_left,
+            Action::RotateRight => &keymap.rotate_right,
+            Action::ZoomIn => &keymap.zoom_in,
+            Action::ZoomOut => &keymap.zoom_out,
+            Action::NextSatellite => &keymap.next_satellite,
+            Action::PreviousSatellite => &keymap.previous_satellite,
+            Action::TimeFaster => &keymap.time_faster,
+            Action::TimeSlower => &keymap.time_slower,
+            Action::TimePause => &keymap.time_pause,
+            Action::TimeReset => &keymap.time_reset,
+            Action::Screenshot => &keymap.screenshot,
+            Action::ExportPasses => &keymap.export_passes,
+            Action::ToggleHelp => &keymap.toggle_help,
+            Action::FollowSelected => &keymap.follow_selected,
+            Action::ResetView => &keymap.reset_view,
+            Action::ToggleMap => &keymap.toggle_map,
+            Action::ToggleGlobeFrame => &keymap.toggle_globe_frame,
+            Action::ExportFootprints => &keymap.export_footprints,
+            Action::NextObserver => &keymap.next_observer,
+            Action::ToggleSchedule => &keymap.toggle_schedule,
+            Action::ToggleGroup1 => &keymap.toggle_group_1,
+            Action::ToggleGroup2 => &keymap.toggle_group_2,
+            Action::ToggleGroup3 => &keymap.toggle_group_3,
+            Action::ToggleGroup4 => &keymap.toggle_group_4,
+            Action::ToggleGroup5 => &keymap.toggle_group_5,
+            Action::ToggleGroup6 => &keymap.toggle_group_6,
+            Action::ToggleGroup7 => &keymap.toggle_group_7,
+            Action::ToggleGroup8 => &keymap.toggle_group_8,
+            Action::ToggleGroup9 => &keymap.toggle_group_9,
+        }
+    }
+}
+
+/// Parse a key name, either a bevy `KeyCode` name ("KeyS", "ArrowLeft", "F1")
+/// or a shorthand for letters, digits and common punctuation ("S", "1", "=", "[")
+pub fn parse_key(name: &str) -> Option<KeyCode> {
+    let name = name.trim();
+    let letters = [
+        KeyCode::KeyA, KeyCode::KeyB, KeyCode::KeyC, KeyCode::KeyD, KeyCode::KeyE, KeyCode::KeyF, KeyCode::KeyG,
+        KeyCode::KeyH, KeyCode::KeyI, KeyCode::KeyJ, KeyCode::KeyK, KeyCode::KeyL, KeyCode::KeyM, KeyCode::KeyN,
+        KeyCode::KeyO, KeyCode::KeyP, KeyCode::KeyQ, KeyCode::KeyR, KeyCode::KeyS, KeyCode::KeyT, KeyCode::KeyU,
+        KeyCode::KeyV, KeyCode::KeyW, KeyCode::KeyX, KeyCode::KeyY, KeyCode::KeyZ,
+    ];
+    let digits = [
+        KeyCode::Digit0, KeyCode::Digit1, KeyCode::Digit2, KeyCode::Digit3, KeyCode::Digit4,
+        KeyCode::Digit5, KeyCode::Digit6, KeyCode::Digit7, KeyCode::Digit8, KeyCode::Digit9,
+    ];
+    let function_keys = [
+        KeyCode::F1, KeyCode::F2, KeyCode::F3, KeyCode::F4, KeyCode::F5, KeyCode::F6,
+        KeyCode::F7, KeyCode::F8, KeyCode::F9, KeyCode::F10, KeyCode::F11, KeyCode::F12,
+    ];
+    let stripped = name.strip_prefix("Key").filter(|rest| rest.len() == 1).unwrap_or(name);
+    if stripped.len() == 1 {
+        let c = stripped.chars().next()?.to_ascii_uppercase();
+        if c.is_ascii_uppercase() {
+            return Some(letters[(c as u8 - b'A') as usize]);
+        }
+    }
+    let stripped = name.strip_prefix("Digit").unwrap_or(name);
+    if stripped.len() == 1 {
+        if let Some(d) = stripped.chars().next()?.to_digit(10) {
+            return Some(digits[d as usize]);
+        }
+    }
+    if let Some(n) = name.strip_prefix('F').and_then(|n| n.parse::<usize>().ok()) {
+        return function_keys.get(n.checked_sub(1)?).copied();
+    }
+    match name {
+        "ArrowLeft" | "Left" => Some(KeyCode::ArrowLeft),
+        "ArrowRight" | "Right" => Some(KeyCode::ArrowRight),
+        "ArrowUp" | "Up" => Some(KeyCode::ArrowUp),
+        "ArrowDown" | "Down" => Some(KeyCode::ArrowDown),
+        "Space" | " " => Some(KeyCode::Space),
+        "Tab" => Some(KeyCode::Tab),
+        "Enter" => Some(KeyCode::Enter),
+        "Backspace" => Some(KeyCode::Backspace),
+        "Escape" => Some(KeyCode::Escape),
+        "Home" => Some(KeyCode::Home),
+        "End" => Some(KeyCode::End),
+        "PageUp" => Some(KeyCode::PageUp),
+        "PageDown" => Some(KeyCode::PageDown),
+        "Equal" | "=" => Some(KeyCode::Equal),
+        "Minus" | "-" => Some(KeyCode::Minus),
+        "BracketLeft" | "[" => Some(KeyCode::BracketLeft),
+        "BracketRight" | "]" => Some(KeyCode::BracketRight),
+        "Comma" | "," => Some(KeyCode::Comma),
+        "Period" | "." => Some(KeyCode::Period),
+        "Semicolon" | ";" => Some(KeyCode::Semicolon),
+        "Quote" | "'" => Some(KeyCode::Quote),
+        "Backquote" | "`" => Some(KeyCode::Backquote),
+        "Backslash" | "\\" => Some(KeyCode::Backslash),
+        "NumpadAdd" => Some(KeyCode::NumpadAdd),
+        "NumpadSubtract" => Some(KeyCode::NumpadSubtract),
+        "PrintScreen" => Some(KeyCode::PrintScreen),
+        _ => None,
+    }
+}
+
+/// Keys bound to each action, parsed from the `keymap` section of the config
+#[derive(Resource, Debug, Clone)]
+pub struct Keybindings {
+    pub bindings: Vec<(Action, KeyCode, String)>,
+}
+
+impl Keybindings {
+    pub fn from_config(keymap: &Keymap) -> Self {
+        let mut bindings = Vec::new();
+        for action in Action::ALL {
+            let name = action.binding(keymap);
+            match parse_key(name) {
+                Some(key) => bindings.push((action, key, name.to_string())),
+                None => {
+                    if !name.is_empty() {
+                        eprintln!("Unknown key \"{}\" for {}", name, action.description());
+                    }
+                }
+            }
+        }
+        Self { bindings }
+    }
+
+    pub fn just_pressed(&self, keys: &ButtonInput<KeyCode>, action: Action) -> bool {
+        self.bindings.iter().any(|(a, key, _)| *a == action && keys.just_pressed(*key))
+    }
+
+    pub fn pressed(&self, keys: &ButtonInput<KeyCode>, action: Action) -> bool {
+        self.bindings.iter().any(|(a, key, _)| *a == action && keys.pressed(*key))
+    }
+}
+
+/// A marker component for the help overlay listing the key bindings
+#[derive(Component)]
+pub struct HelpOverlay;
+
+pub fn setup_help_overlay(mut commands: Commands, asset_server: Res<AssetServer>, bindings: Res<Keybindings>, loaded_config: Res<config::Config>) {
+    let mut lines = vec!["Keys".to_string()];
+    for (action, _, name) in &bindings.bindings {
+        lines.push(format!("{:>12}  {}", name, action.description()));
+    }
+    lines.push(format!("{:>12}  {}", "/", "search satellites"));
+    lines.push(format!("{:>12}  {}", "click", "select satellite"));
+    lines.push(format!("{:>12}  {}", "wheel", "zoom at the cursor"));
+    lines.push(format!("{:>12}  {}", "drag", "pan the view"));
+    commands.spawn((
+        Text::new(lines.join("\n")),
+        TextFont {
+            font: asset_server.load("fonts/FiraMono-Bold.ttf"),
+            font_size: loaded_config.info_panel_font_size,
+            ..Default::default()
+        },
+        TextColor(hexstr2color(&loaded_config.sat_name_color)),
+        BackgroundColor(hexstr2color(&loaded_config.sat_name_bg_color)),
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Percent(20.),
+            left: Val::Percent(30.),
+            padding: UiRect::all(Val::Px(10.)),
+            ..default()
+        },
+        Visibility::Hidden,
+        HelpOverlay,
+    ));
+}
+
+pub fn handle_view_actions(
+    keys: Res<ButtonInput<KeyCode>>,
+    bindings: Res<Keybindings>,
+    time: Res<Time>,
+    mut view: ResMut<view::ViewState>,
+    mut mode: ResMut<view::ViewMode>,
+    mut globe: ResMut<globe::GlobeState>,
+    mut hovered: ResMut<selection::HoveredSatellite>,
+    mut help_query: Query<&mut Visibility, With<HelpOverlay>>,
+) {
+    let loaded_config = view.loaded_config.clone();
+    let dt = time.delta_secs();
+    if bindings.just_pressed(&keys, Action::ToggleStars) {
+        view.show_stars = !view.show_stars;
+    }
+    if bindings.just_pressed(&keys, Action::ToggleGrid) {
+        view.show_grid = !view.show_grid;
+    }
+    if bindings.just_pressed(&keys, Action::ToggleTrails) {
+        view.show_trails = !view.show_trails;
+    }
+    if bindings.just_pressed(&keys, Action::ToggleLabels) {
+        view.show_labels = !view.show_labels;
+    }
+    if bindings.pressed(&keys, Action::RotateLeft) {
+        view.azimuth = (view.azimuth - loaded_config.rotate_speed_degrees * dt).rem_euclid(360.0);
+    }
+    if bindings.pressed(&keys, Action::RotateRight) {
+        view.azimuth = (view.azimuth + loaded_config.rotate_speed_degrees * dt).rem_euclid(360.0);
+    }
+    if bindings.pressed(&keys, Action::ZoomIn) {
+        view.target_zoom = (view.target_zoom * loaded_config.zoom_speed.powf(dt)).min(loaded_config.max_zoom);
+        view.zoom_anchor = None;
+    }
+    if bindings.pressed(&keys, Action::ZoomOut) {
+        view.target_zoom = (view.target_zoom / loaded_config.zoom_speed.powf(dt)).max(loaded_config.min_zoom);
+        view.zoom_anchor = None;
+    }
+    if bindings.just_pressed(&keys, Action::FollowSelected) {
+        view.follow_selected = !view.follow_selected;
+    }
+    if bindings.just_pressed(&keys, Action::ToggleMap) {
+        *mode = mode.next();
+        view.pan = Vec2::ZERO;
+        view.zoom_anchor = None;
+        hovered.0 = None;
+    }
+    if bindings.just_pressed(&keys, Action::ToggleGlobeFrame) {
+        globe.frame = globe.frame.other();
+    }
+    if bindings.just_pressed(&keys, Action::ResetView) {
+        view.target_zoom = 1.0;
+        view.zoom_anchor = None;
+        view.pan = Vec2::ZERO;
+        view.follow_selected = false;
+    }
+    if bindings.just_pressed(&keys, Action::ToggleHelp) {
+        view.show_help = !view.show_help;
+        for mut visibility in &mut help_query {
+            *visibility = if view.show_help { Visibility::Inherited } else { Visibility::Hidden };
+        }
+    }
+}
+
+pub fn handle_time_actions(
+    keys: Res<ButtonInput<KeyCode>>,
+    bindings: Res<Keybindings>,
+    mut sim_clock: ResMut<clock::SimClock>,
+    mut generation: ResMut<clock::TrackGeneration>,
+    mut paused_rate: Local<Option<f64>>,
+) {
+    if bindings.just_pressed(&keys, Action::TimeFaster) {
+        let rate = TIME_RATES.iter().copied().find(|rate| *rate > sim_clock.rate).unwrap_or(TIME_RATES[TIME_RATES.len() - 1]);
+        sim_clock.set_rate(rate);
+        *paused_rate = None;
+        println!("Time rate x{}", rate);
+    }
+    if bindings.just_pressed(&keys, Action::TimeSlower) {
+        let rate = TIME_RATES.iter().copied().rev().find(|rate| *rate < sim_clock.rate).unwrap_or(TIME_RATES[0]);
+        sim_clock.set_rate(rate);
+        *paused_rate = None;
+        println!("Time rate x{}", rate);
+    }
+    if bindings.just_pressed(&keys, Action::TimePause) {
+        match paused_rate.take() {
+            Some(rate) => sim_clock.set_rate(rate),
+            None => {
+                *paused_rate = Some(sim_clock.rate);
+                sim_clock.set_rate(0.0);
+            }
+        }
+    }
+    if bindings.just_pressed(&keys, Action::TimeReset) {
+        sim_clock.jump_to(Utc::now());
+        sim_clock.set_rate(1.0);
+        *paused_rate = None;
+        generation.0 += 1;
+    }
+}
+
+/// Cycle through the satellites in view (or every visible one if none is in view), by name
+pub fn handle_selection_actions(
+    keys: Res<ButtonInput<KeyCode>>,
+    bindings: Res<Keybindings>,
+    sat_query: Query<(Entity, &Satellite)>,
+    visibility: Res<groups::GroupVisibility>,
+    mut selected: ResMut<selection::SelectedSatellite>,
+    sim_clock: Res<clock::SimClock>,
+) {
+    let next = bindings.just_pressed(&keys, Action::NextSatellite);
+    let previous = bindings.just_pressed(&keys, Action::PreviousSatellite);
+    if !next && !previous {
+        return;
+    }
+    let now = sim_clock.now().timestamp_millis();
+    let visible: Vec<(Entity, &Satellite)> = sat_query.iter().filter(|(_, sat)| !groups::is_satellite_hidden(&visibility, sat)).collect();
+    let mut candidates: Vec<(Entity, &Satellite)> = visible
+        .iter()
+        .copied()
+        .filter(|(_, sat)| !sat.times.is_empty() && sat.times[0] <= now && sat.times[sat.times.len() - 1] >= now)
+        .collect();
+    if candidates.is_empty() {
+        candidates = visible;
+    }
+    if candidates.is_empty() {
+        return;
+    }
+    candidates.sort_by(|a, b| a.1.name.cmp(&b.1.name));
+    let current = selected.0.and_then(|entity| candidates.iter().position(|(e, _)| *e == entity));
+    let index = match (current, next) {
+        (Some(i), true) => (i + 1) % candidates.len(),
+        (Some(i), false) => (i + candidates.len() - 1) % candidates.len(),
+        (None, true) => 0,
+        (None, false) => candidates.len() - 1,
+    };
+    selected.0 = Some(candidates[index].0);
+}
+
+pub fn handle_screenshot_action(mut commands: Commands, keys: Res<ButtonInput<KeyCode>>, bindings: Res<Keybindings>) {
+    if !bindings.just_pressed(&keys, Action::Screenshot) {
+        return;
+    }
+    let mut path = my_home().unwrap().expect("couldn't get home directory");
+    path.push(".config/ontake/tasogare/screenshots");
+    if std::fs::create_dir_all(&path).is_err() {
+        eprintln!("Failed to create screenshot directory");
+        return;
+    }
+    path.push(format!("tasogare-{}.png", Utc::now().format("%Y%m%d-%H%M%S")));
+    println!("Saving screenshot to {}", path.display());
+    commands.spawn(Screenshot::primary_window()).observe(save_to_disk(path));
+}
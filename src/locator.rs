@@ -0,0 +1,260 @@
+use std::sync::{Mutex, OnceLock};
+
+use crate::config::Config;
+use crate::terrain;
+
+/// Size in degrees of longitude and latitude of the fields, squares, subsquares and extended squares
+const GRID_STEPS: [(f64, f64); 4] = [(20.0, 10.0), (2.0, 1.0), (2.0 / 24.0, 1.0 / 24.0), (2.0 / 240.0, 1.0 / 240.0)];
+
+/// Location given with --location, overriding the configured observer
+static LOCATION_OVERRIDE: OnceLock<String> = OnceLock::new();
+
+/// Locations resolved so far, so loading the config doesn't read the gazetteer every time
+static RESOLVED: Mutex<Vec<(String, Option<Place>)>> = Mutex::new(Vec::new());
+
+/// A location resolved from a locator or a place name, degrees and meters
+#[derive(Debug, Clone, PartialEq)]
+pub struct Place {
+    pub name: String,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub elevation: Option<f64>,
+}
+
+/// Maidenhead locator of a latitude and longitude (degrees), 2 to 8 characters (e.g. "JN18eu")
+pub fn to_maidenhead(latitude: f64, longitude: f64, length: usize) -> String {
+    let mut lon = (longitude + 180.0).rem_euclid(360.0);
+    let mut lat = (latitude + 90.0).clamp(0.0, 180.0 - 1e-9);
+    let mut locator = String::new();
+    for (i, (lon_step, lat_step)) in GRID_STEPS.iter().enumerate().take((length / 2).clamp(1, 4)) {
+        let x = (lon / lon_step).floor();
+        let y = (lat / lat_step).floor();
+        lon -= x * lon_step;
+        lat -= y * lat_step;
+        let base = match i {
+            0 => b'A',
+            2 => b'a',
+            _ => b'0',
+        };
+        locator.push((base + x as u8) as char);
+        locator.push((base + y as u8) as char);
+    }
+    locator
+}
+
+/// Latitude and longitude (degrees) of the center of a 2, 4, 6 or 8 character Maidenhead locator
+pub fn from_maidenhead(locator: &str) -> Option<(f64, f64)> {
+    let chars: Vec<char> = locator.trim().to_ascii_uppercase().chars().collect();
+    if chars.is_empty() || !chars.len().is_multiple_of(2) || chars.len() > 8 {
+        return None;
+    }
+    let (mut lon, mut lat) = (0.0, 0.0);
+    for (i, pair) in chars.chunks(2).enumerate() {
+        let digit = |c: char| match i {
+            0 => ('A'..='R').contains(&c).then(|| c as u32 - 'A' as u32),
+            2 => ('A'..='X').contains(&c).then(|| c as u32 - 'A' as u32),
+            _ => c.to_digit(10),
+        };
+        let (lon_step, lat_step) = GRID_STEPS[i];
+        lon += digit(pair[0])? as f64 * lon_step;
+        lat += digit(pair[1])? as f64 * lat_step;
+    }
+    let (lon_step, lat_step) = GRID_STEPS[chars.len() / 2 - 1];
+    Some((lat + lat_step / 2.0 - 90.0, lon + lon_step / 2.0 - 180.0))
+}
+
+/// Gazetteer built into the binary in the cities15000.txt layout, used when gazetteer_path can't be found
+const BUNDLED_GAZETTEER: &str = include_str!("../assets/data/cities.txt");
+
+/// Look a place up in a GeoNames style gazetteer (tab separated, like cities15000.txt), by name,
+/// ASCII name or alternate name, optionally followed by ", <country code>". The most populated match wins
+pub fn lookup_place(query: &str, gazetteer_path: &str) -> Option<Place> {
+    let (name, country) = match query.rsplit_once(',') {
+        Some((name, country)) if country.trim().len() == 2 => (name.trim(), Some(country.trim())),
+        _ => (query.trim(), None),
+    };
+    let Some(path) = crate::config::resolve_data_path(gazetteer_path) else {
+        eprintln!("Gazetteer {} not found (run `just fetch-data` or set gazetteer_path), using the bundled one", gazetteer_path);
+        return find_place(BUNDLED_GAZETTEER, name, country);
+    };
+    match std::fs::read_to_string(&path) {
+        Ok(contents) => find_place(&contents, name, country),
+        Err(_) => {
+            eprintln!("Couldn't read the gazetteer {}, using the bundled one", path.display());
+            find_place(BUNDLED_GAZETTEER, name, country)
+        }
+    }
+}
+
+/// The most populated place of a gazetteer with that name, ties go to the one listed first
+fn find_place(contents: &str, name: &str, country: Option<&str>) -> Option<Place> {
+    contents
+        .lines()
+        // max_by_key keeps the last of equal elements, the bundled table has no populations and lists the largest places first
+        .rev()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split('\t').collect();
+            if fields.len() < 17 {
+                return None;
+            }
+            let matches = fields[1].eq_ignore_ascii_case(name) || fields[2].eq_ignore_ascii_case(name) || fields[3].split(',').any(|alternate| alternate.eq_ignore_ascii_case(name));
+            if !matches || country.is_some_and(|country| !fields[8].eq_ignore_ascii_case(country)) {
+                return None;
+            }
+            let population: u64 = fields[14].parse().unwrap_or(0);
+            // The elevation column is often empty, the DEM one uses -9999 for no data
+            let elevation = fields[15].parse().ok().or(fields[16].parse().ok().filter(|dem: &f64| *dem > -9999.0));
+            let place = Place {
+                name: format!("{}, {}", fields[1], fields[8]),
+                latitude: fields[4].parse().ok()?,
+                longitude: fields[5].parse().ok()?,
+                elevation,
+            };
+            Some((population, place))
+        })
+        .max_by_key(|(population, _)| *population)
+        .map(|(_, place)| place)
+}
+
+/// Resolve a Maidenhead locator or a place name
+pub fn resolve(query: &str, gazetteer_path: &str) -> Option<Place> {
+    let mut resolved = RESOLVED.lock().unwrap();
+    if let Some((_, place)) = resolved.iter().find(|(resolved_query, _)| resolved_query == query) {
+        return place.clone();
+    }
+    let place = match from_maidenhead(query) {
+        // A locator has digits in its second pair, which no place name in the gazetteer starts with
+        Some((latitude, longitude)) if query.trim().len() >= 4 => Some(Place { name: query.trim().to_string(), latitude, longitude, elevation: None }),
+        _ => lookup_place(query, gazetteer_path),
+    };
+    match &place {
+        Some(place) => println!("Resolved {} to {} ({:.4}, {:.4})", query, place.name, place.latitude, place.longitude),
+        None => eprintln!("Couldn't find {} as a locator or in the gazetteer", query),
+    }
+    resolved.push((query.to_string(), place.clone()));
+    place
+}
+
+/// Use a location given on the command line for every config loaded from now on
+pub fn set_override(query: String) {
+    let _ = LOCATION_OVERRIDE.set(query);
+}
+
+fn move_to(loaded_config: &mut Config, place: &Place) {
+    let altitude = match place.elevation {
+        Some(elevation) => terrain::from_mean_sea_level(place.latitude, place.longitude, elevation, loaded_config),
+        None => loaded_config.user_altitude,
+    };
+    loaded_config.move_observer(place.latitude, place.longitude, altitude);
+}
+
+/// Replace user_latitude and user_longitude with the configured user_location, if any
+pub fn apply_user_location(loaded_config: &mut Config) {
+    if loaded_config.user_location.trim().is_empty() {
+        return;
+    }
+    if let Some(place) = resolve(&loaded_config.user_location, &loaded_config.gazetteer_path) {
+        move_to(loaded_config, &place);
+    }
+}
+
+/// Move the observer to the --location place, if one was given
+pub fn apply_override(loaded_config: &mut Config) {
+    let Some(query) = LOCATION_OVERRIDE.get() else { return };
+    if let Some(place) = resolve(query, &loaded_config.gazetteer_path) {
+        move_to(loaded_config, &place);
+        loaded_config.active_observer = place.name;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// GeoNames rows: geonameid, name, asciiname, alternatenames, lat, lon, class, code, country, ..., population, elevation, dem
+    const GAZETTEER: &str = "2988507\tParis\tParis\tLutece,Parigi\t48.85341\t2.3488\tP\tPPLC\tFR\t\t11\t75\t751\t75056\t2138551\t\t42\tEurope/Paris\t2024-01-01\n\
+        4717560\tParis\tParis\t\t33.66094\t-95.55551\tP\tPPLA2\tUS\t\tTX\t277\t\t\t24782\t\t183\tAmerica/Chicago\t2024-01-01\n\
+        2996944\tLyon\tLyon\tLyons\t45.74846\t4.84671\tP\tPPLA\tFR\t\t84\t69\t691\t69123\t522969\t170\t-9999\tEurope/Paris\t2024-01-01\n\
+        truncated\tline\n";
+
+    fn gazetteer(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("tasogare-locator-{}-{}.txt", name, std::process::id()));
+        std::fs::write(&path, GAZETTEER).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn encodes_locators() {
+        assert_eq!(to_maidenhead(48.8566, 2.3522, 6), "JN18eu");
+        assert_eq!(to_maidenhead(48.8566, 2.3522, 2), "JN");
+        assert_eq!(to_maidenhead(48.8566, 2.3522, 4), "JN18");
+        assert_eq!(to_maidenhead(-33.8688, 151.2093, 6), "QF56od");
+        assert_eq!(to_maidenhead(40.7128, -74.0060, 6), "FN20xr");
+        assert_eq!(to_maidenhead(90.0, 180.0, 4), "AR09");
+    }
+
+    #[test]
+    fn decodes_locator_centers() {
+        assert_eq!(from_maidenhead("JN"), Some((45.0, 10.0)));
+        assert_eq!(from_maidenhead("JN18"), Some((48.5, 3.0)));
+        let (lat, lon) = from_maidenhead("jn18EU").unwrap();
+        assert!((lat - (48.0 + 20.5 / 24.0)).abs() < 1e-9);
+        assert!((lon - (2.0 + 4.5 / 12.0)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn rejects_malformed_locators() {
+        for locator in ["", "J", "JN1", "SN", "JNA8", "JN18yu", "JN18eu5", "JN18eu5x", "JN18eu55aa", "Paris"] {
+            assert_eq!(from_maidenhead(locator), None, "{}", locator);
+        }
+    }
+
+    #[test]
+    fn eight_character_locators_round_trip() {
+        for (lat, lon) in [(48.8566, 2.3522), (-33.8688, 151.2093), (40.7128, -74.0060), (-89.99, -179.99), (0.0, 0.0)] {
+            let locator = to_maidenhead(lat, lon, 8);
+            assert_eq!(locator.len(), 8);
+            let (center_lat, center_lon) = from_maidenhead(&locator).unwrap();
+            assert_eq!(to_maidenhead(center_lat, center_lon, 8), locator);
+            assert!((center_lat - lat).abs() <= 1.0 / 480.0 && (center_lon - lon).abs() <= 1.0 / 240.0);
+        }
+    }
+
+    #[test]
+    fn looks_places_up_by_population() {
+        let path = gazetteer("population");
+        let place = lookup_place("paris", &path).unwrap();
+        assert_eq!(place, Place { name: "Paris, FR".to_string(), latitude: 48.85341, longitude: 2.3488, elevation: Some(42.0) });
+        assert_eq!(to_maidenhead(place.latitude, place.longitude, 6), "JN18eu");
+        assert_eq!(lookup_place("Paris, US", &path).unwrap().longitude, -95.55551);
+        assert_eq!(lookup_place("Lyon", &path).unwrap().elevation, Some(170.0));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn looks_places_up_by_alternate_name() {
+        let path = gazetteer("alternate");
+        assert_eq!(lookup_place("Parigi", &path).unwrap().name, "Paris, FR");
+        assert_eq!(lookup_place("Lyons, FR", &path).unwrap().name, "Lyon, FR");
+        assert_eq!(lookup_place("Lyon, US", &path), None);
+        assert_eq!(lookup_place("Atlantis", &path), None);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn missing_gazetteer_falls_back_to_the_bundled_one() {
+        let place = lookup_place("Paris", "/nonexistent/cities15000.txt").unwrap();
+        assert_eq!(place.name, "Paris, FR");
+        assert_eq!(to_maidenhead(place.latitude, place.longitude, 4), "JN18");
+        assert!(place.elevation.is_some());
+        assert_eq!(lookup_place("Tokyo, JP", "/nonexistent/cities15000.txt").unwrap().name, "Tokyo, JP");
+        assert_eq!(lookup_place("Nowhereville", "/nonexistent/cities15000.txt"), None);
+    }
+
+    #[test]
+    fn ties_go_to_the_first_listed_place() {
+        let contents = "\tSpringfield\tSpringfield\t\t39.80172\t-89.64371\tP\tPPL\tUS\t\t\t\t\t\t\t180\t\t\t\n\
+            \tSpringfield\tSpringfield\t\t37.21533\t-93.29824\tP\tPPL\tUS\t\t\t\t\t\t\t396\t\t\t\n";
+        assert_eq!(find_place(contents, "Springfield", None).unwrap().latitude, 39.80172);
+    }
+}
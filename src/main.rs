@@ -1,20 +1,49 @@
-mod config;
+#![allow(clippy::too_many_arguments, clippy::type_complexity)] // bevy systems take their queries as arguments
 
-use clap::Parser;
+mod analysis;
+mod archive;
+mod cache;
+mod clock;
+mod compass;
+mod config;
+mod footprint;
+mod globe;
+mod gps;
+mod groups;
+mod hud;
+mod info_panel;
+mod keymap;
+mod locator;
+mod observers;
+mod passes;
+mod projection;
+mod satcat;
+mod search;
+mod selection;
+mod sky_overlays;
+mod spacetrack;
+mod style;
+mod sun;
+mod terrain;
+mod tle;
+mod transmitters;
+mod view;
+mod world_map;
+
+use clap::{Parser, Subcommand};
 use satkit::types::Vector3;
 use std::f32::consts::PI;
 use chrono::{DateTime, Datelike, Duration, Timelike, Utc};
 
 use star_catalog::{hipparcos, Catalog};
-use homedir::my_home;
 
-/// TODO: https://docs.rs/star-catalog/latest/star_catalog/struct.Star.html
-/// https://docs.rs/map_3d/latest/map_3d/fn.eci2aer.html
-///
-///
-/// Also add satellites and constellations
+// TODO: https://docs.rs/star-catalog/latest/star_catalog/struct.Star.html
+// https://docs.rs/map_3d/latest/map_3d/fn.eci2aer.html
+//
+//
+// Also add satellites and constellations
 
-/// With https://docs.rs/sgp4/latest/sgp4/
+// With https://docs.rs/sgp4/latest/sgp4/
 
 #[derive(Parser, Debug)]
 #[clap(author = "Louis Dalibard (OnTake/make-42)", version, about)]
@@ -24,18 +53,97 @@ struct Args {
     #[arg(short = 'v')]
     verbose: bool,
 
-    /// an optional name to greet
-    #[arg()]
-    name: Option<String>,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// start the simulation at this time (RFC 3339, e.g. 2024-05-01T21:30:00Z) instead of now
+    #[arg(long)]
+    time: Option<String>,
+
+    /// observe from this Maidenhead locator (e.g. JN18eu) or place name (e.g. "Lyon, FR")
+    #[arg(long)]
+    location: Option<String>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Report maneuvers and decay signs from the archived element sets of an object
+    Analyze {
+        /// NORAD catalog number
+        norad: u64,
+    },
+    /// Print the combined pass schedule of all stations, with their mutual visibility windows
+    Schedule,
+    /// List which of the configured locations can hear an object (at --time, or now)
+    Coverage {
+        /// NORAD catalog number
+        norad: u64,
+    },
 }
 
 use bevy::{math::vec2, prelude::*, render::mesh::AnnulusMeshBuilder, sprite::Anchor};
 
 #[tokio::main]
 async fn main() {
-    let _args = Args::parse();
+    let args = Args::parse();
+    if let Some(location) = &args.location {
+        locator::set_override(location.clone());
+    }
     let loaded_config = config::init();
-    config::update_tle(loaded_config).await;
+    // The analysis only reads the archive, don't download anything for it
+    if let Some(Command::Analyze { norad }) = args.command {
+        let history = archive::ElementHistory::from_elements(archive::load_history(norad));
+        let name = loaded_config.display_name(norad, &history.sets.last().and_then(|set| set.elements.object_name.clone()));
+        analysis::print_report(&name, norad, &analysis::analyze(&history, &loaded_config));
+        return;
+    }
+    config::update_tle(loaded_config.clone()).await;
+    tle::report_epoch_ages(&loaded_config);
+    if let Some(Command::Schedule) = args.command {
+        let mut fetched_elements: std::collections::HashMap<u64, Vec<sgp4::Elements>> = std::collections::HashMap::new();
+        for elements in tle::load_elements() {
+            fetched_elements.entry(elements.norad_id).or_default().push(elements);
+        }
+        let histories: Vec<(u64, String, archive::ElementHistory)> = fetched_elements
+            .into_iter()
+            .map(|(norad_id, mut known_elements)| {
+                let name = loaded_config.display_name(norad_id, &known_elements.last().and_then(|elements| elements.object_name.clone()));
+                known_elements.extend(archive::load_history(norad_id));
+                (norad_id, name, archive::ElementHistory::from_elements(known_elements))
+            })
+            .collect();
+        let sats: Vec<(u64, String, &archive::ElementHistory)> = histories.iter().map(|(norad_id, name, history)| (*norad_id, name.clone(), history)).collect();
+        let start = match &args.time {
+            Some(time) => time.parse().expect("couldn't parse --time as an RFC 3339 date"),
+            None => Utc::now(),
+        };
+        let (passes, windows) = observers::combined_schedule(&sats, start, &loaded_config);
+        observers::print_schedule(&passes, &windows);
+        return;
+    }
+    if let Some(Command::Coverage { norad }) = args.command {
+        let mut known_elements = archive::load_history(norad);
+        known_elements.extend(tle::load_elements().into_iter().filter(|elements| elements.norad_id == norad));
+        if known_elements.is_empty() {
+            eprintln!("No element sets for {}", norad);
+            return;
+        }
+        let history = archive::ElementHistory::from_elements(known_elements);
+        let name = loaded_config.display_name(norad, &history.sets.last().and_then(|set| set.elements.object_name.clone()));
+        let date = match &args.time {
+            Some(time) => time.parse().expect("couldn't parse --time as an RFC 3339 date"),
+            None => Utc::now(),
+        };
+        footprint::print_coverage(&name, norad, &history, date, &loaded_config);
+        return;
+    }
+    // SATCAT metadata and transmitters are only shown in the GUI
+    satcat::update_satcat(&loaded_config).await;
+    transmitters::update_transmitters(&loaded_config).await;
+    let sim_clock = match &args.time {
+        Some(time) => clock::SimClock::starting_at(time.parse().expect("couldn't parse --time as an RFC 3339 date")),
+        None => clock::SimClock::realtime(),
+    };
     App::new()
         .add_plugins(
             DefaultPlugins/*.set(ImagePlugin::default_nearest())*/.set(WindowPlugin {
@@ -54,15 +162,113 @@ async fn main() {
         )
         // ClearColor must have 0 alpha, otherwise some color will bleed through
         .insert_resource(ClearColor(Color::NONE))
-        .add_systems(Startup, setup)
+        .insert_resource(sim_clock)
+        .insert_resource(loaded_config.clone())
+        .insert_resource(clock::TrackGeneration(1))
+        .insert_resource(groups::GroupVisibility::from_config(&loaded_config))
+        .insert_resource(view::ViewState::from_config(&loaded_config))
+        .init_resource::<view::ViewMode>()
+        .init_resource::<style::SatMaterials>()
+        .insert_resource(observers::ActiveObserver { name: loaded_config.active_observer.clone() })
+        .init_resource::<observers::ScheduleState>()
+        .insert_resource(gps::GpsReceiver::from_config(&loaded_config))
+        .init_resource::<world_map::GroundTracks>()
+        .insert_resource(globe::GlobeState::from_config(&loaded_config))
+        .init_gizmo_group::<globe::GlobeGizmos>()
+        .insert_resource(UiScale(loaded_config.ui_scale))
+        .insert_resource(keymap::Keybindings::from_config(&loaded_config.keymap))
+        .init_resource::<selection::HoveredSatellite>()
+        .init_resource::<selection::SelectedSatellite>()
+        .init_resource::<search::SearchState>()
+        .add_systems(Startup, (setup, info_panel::setup_info_panel, selection::setup_selection, search::setup_search, keymap::setup_help_overlay, world_map::setup_world_map, globe::setup_globe, observers::setup_schedule_panel, hud::setup_hud, compass::setup_compass))
         .add_systems(
             Update,
             (
+                select_element_sets,
                 set_star_positions,
                 set_sat_positions,
                 compute_sat_trails,
                 compute_sat_positions,
-                draw_satellite_trail,
+                draw_satellite_trail.run_if(view::in_dome),
+                update_sat_labels,
+                info_panel::update_info_panel,
+                style::reload_config_styles,
+                groups::toggle_groups.run_if(search::search_inactive),
+                groups::apply_group_visibility,
+                passes::export_passes_on_key.run_if(search::search_inactive),
+                footprint::export_footprints_on_key.run_if(search::search_inactive),
+                footprint::draw_dome_partner_outline.run_if(view::in_dome),
+                compass::draw_azimuth_ticks.run_if(view::in_dome),
+                sky_overlays::draw_sky_overlays.run_if(view::in_dome),
+                footprint::draw_map_footprints.run_if(view::in_world_map),
+            ),
+        )
+        .add_systems(
+            Update,
+            (
+                selection::pick_satellites.run_if(view::in_dome),
+                world_map::pick_sub_points.run_if(view::in_world_map),
+                selection::update_hover_tooltip,
+                selection::update_selection_panel,
+                selection::draw_selection_markers.run_if(view::in_dome),
+            ).chain(),
+        )
+        .add_systems(
+            Update,
+            (
+                search::handle_search_input,
+                search::update_search_box,
+                search::draw_search_results.run_if(view::in_dome),
+            ).chain(),
+        )
+        .add_systems(
+            Update,
+            (
+                (
+                    keymap::handle_view_actions,
+                    keymap::handle_time_actions,
+                    keymap::handle_selection_actions,
+                    keymap::handle_screenshot_action,
+                    observers::cycle_observer,
+                    observers::toggle_schedule,
+                ).run_if(search::search_inactive),
+                (observers::receive_schedule, observers::update_schedule_panel).chain(),
+                gps::apply_gps_fix,
+                hud::update_hud,
+                view::apply_view_rotation,
+                view::apply_layer_visibility,
+            ),
+        )
+        .add_systems(
+            Update,
+            (
+                view::fit_to_window,
+                view::zoom_with_mouse_wheel.run_if(not(view::in_globe)),
+                view::drag_pan.run_if(not(view::in_globe)),
+                view::smooth_zoom,
+                view::follow_selected_satellite.run_if(view::in_dome),
+                world_map::follow_selected_sub_point.run_if(view::in_world_map),
+                view::apply_zoom,
+                view::apply_readable_scale,
+            ).chain().before(selection::pick_satellites),
+        )
+        .add_systems(
+            Update,
+            (
+                world_map::update_ground_tracks,
+                world_map::draw_world_map,
+            ).chain().run_if(view::in_world_map).before(world_map::pick_sub_points),
+        )
+        .add_systems(
+            Update,
+            (
+                globe::apply_view_mode,
+                (
+                    globe::orbit_camera,
+                    globe::update_earth,
+                    globe::update_orbits,
+                    globe::draw_globe,
+                ).chain().run_if(view::in_globe),
             ),
         )
         .run();
@@ -74,6 +280,7 @@ struct Shape;
 
 /// A marker component for our stars so we can query them separately
 #[derive(Component)]
+#[allow(dead_code)] // the catalogue fields aren't read yet
 struct Star {
     pub id: usize,
     pub ra: f64,
@@ -87,52 +294,73 @@ struct Star {
 
 #[derive(Component)]
 struct Satellite {
-    pub name: String,
+    pub norad_id: u64,
+    pub name: String, // display name only, objects are identified by their NORAD ID
     pub constants: sgp4::Constants,
     pub elements: sgp4::Elements,
+    pub history: archive::ElementHistory,
+    pub analysis: analysis::AnalysisReport,
+    pub satcat: Option<satcat::SatcatEntry>,
+    pub style: style::ResolvedStyle,
+    pub transmitters: Vec<transmitters::Transmitter>,
+    pub generation: u64,
     pub last_pass_end_datetime: DateTime<Utc>,
-    pub positions: Vec<Vec2>,
+    pub positions: Vec<Vec2>, // azimuth, elevation (radians) during the next pass
     pub times: Vec<i64>,
+    pub gcrf_position: Option<Vector3>, // meters, at gcrf_position_at, for the globe
+    pub gcrf_position_at: Option<DateTime<Utc>>,
     pub loaded_config: config::Config,
 }
 
 #[derive(Component)]
 struct SatelliteTrail {
-    pub name: String,
+    pub norad_id: u64,
+    pub name: String, // display name only, objects are identified by their NORAD ID
     pub constants: sgp4::Constants,
     pub elements: sgp4::Elements,
+    pub history: archive::ElementHistory,
+    pub object_type: Option<String>,
+    pub generation: u64,
     pub last_pass_end_datetime: DateTime<Utc>,
-    pub spline:  CubicCardinalSpline<Vec2>,
+    pub points: Vec<Vec2>, // azimuth, elevation (radians) along the next pass
     pub loaded_config: config::Config,
     pub color: Color,
+    pub style: style::ResolvedStyle,
 }
 
+/// A marker component for the satellite name labels
+#[derive(Component)]
+struct SatelliteLabel;
+
+/// A marker component for the background sprite behind satellite name labels
+#[derive(Component)]
+struct SatelliteLabelBackground;
+
 fn hexstr2color(hex_color: &String) -> Color {
-    return bevy::prelude::Color::Srgba(Srgba::hex(hex_color).unwrap());
+    bevy::prelude::Color::Srgba(Srgba::hex(hex_color).unwrap())
 }
 
 fn setup(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<ColorMaterial>>,
+    mut sat_materials: ResMut<style::SatMaterials>,
     asset_server: Res<AssetServer>,
+    loaded_config: Res<config::Config>,
 ) {
-    let loaded_config = config::init();
     let font = asset_server.load("fonts/FiraMono-Bold.ttf");
     let altitude_angle_lines_material = materials.add(hexstr2color(&loaded_config.altitude_angle_lines_color));
     let azimuth_angle_lines_material = materials.add(hexstr2color(&loaded_config.azimuth_angle_lines_color));
-    let sat_trails_color = hexstr2color(&loaded_config.sat_trails_color);
-    let sat_material = materials.add(hexstr2color(&loaded_config.sat_color));
     let sat_name_color =hexstr2color(&loaded_config.sat_name_color);
     let sat_name_bg_color = hexstr2color(&loaded_config.sat_name_bg_color);
     let star_material = materials.add(hexstr2color(&loaded_config.star_color));
-    let north_color = hexstr2color(&loaded_config.north_color);
 
     for m in 0..(loaded_config.altitude_angle_steps) {
         let angle = m as f32/(loaded_config.altitude_angle_steps) as f32*PI/2.0;
+        let ring_radius = loaded_config.scene_radius*loaded_config.sky_projection.radial_distance(angle as f64) as f32;
         commands.spawn((
-            Mesh2d(meshes.add(AnnulusMeshBuilder {annulus:Annulus{inner_circle:Circle{radius:loaded_config.scene_radius*angle.cos()-loaded_config.altitude_lines_thickness/2.0 },
-                outer_circle:Circle{radius:loaded_config.scene_radius*angle.cos()+loaded_config.altitude_lines_thickness/2.0}}, resolution: loaded_config.shape_resolution})),
+            Mesh2d(meshes.add(AnnulusMeshBuilder {annulus:Annulus{inner_circle:Circle{radius:ring_radius-loaded_config.altitude_lines_thickness/2.0 },
+                outer_circle:Circle{radius:ring_radius+loaded_config.altitude_lines_thickness/2.0}}, resolution: loaded_config.shape_resolution})),
             MeshMaterial2d(altitude_angle_lines_material.clone()),
             Transform::from_xyz(
                 0.,
@@ -154,11 +382,12 @@ fn setup(
                 -30.,
             ).with_rotation(Quat::from_rotation_z(angle+loaded_config.user_azimuth / 180.0 * std::f32::consts::PI)),
             Shape,
+            view::AzimuthLine { angle },
         ));
     };
 
     let s = include_str!("../assets/data/hipparcos.json");
-    let mut catalog: Catalog = serde_json::from_str(&s).expect("couldn't parse hipparcos.json");
+    let mut catalog: Catalog = serde_json::from_str(s).expect("couldn't parse hipparcos.json");
     catalog.sort();
     catalog.add_names(hipparcos::HIP_ALIASES, true).unwrap();
     catalog.derive_data();
@@ -186,95 +415,105 @@ fn setup(
         ));
         };
     });
-    let mut path = my_home().unwrap().expect("couldn't get home directory");
-    path.push(".config/ontake/tasogare/TLEDATA");
-    let tle_content = std::fs::read_to_string(path.as_path()).expect("couldn't read input TLE");
-    let tle_lines: Vec<&str> = tle_content.lines().collect();
     let text_font = TextFont {
         font: font.clone(),
         font_size: loaded_config.sat_name_font_size,
         ..Default::default()
     };
-    for i in (0..tle_lines.len()).step_by(3) {
-        let name = tle_lines[i].to_owned().trim().to_string();
-        let line1 = tle_lines[i + 1];
-        let line2 = tle_lines[i + 2];
-    let elements = sgp4::Elements::from_tle(
-           Some(name),
-           line1.as_bytes(),
-           line2.as_bytes(),
-       ).unwrap();
-
+    let all_elements = tle::load_elements();
+    let mut fetched_elements: std::collections::HashMap<u64, Vec<sgp4::Elements>> = std::collections::HashMap::new();
+    for elements in &all_elements {
+        fetched_elements.entry(elements.norad_id).or_default().push(elements.clone());
+    }
+    let satcat = satcat::load_satcat(&loaded_config);
+    let mut transmitters = transmitters::load_transmitters(&loaded_config);
+    for elements in tle::latest_per_object(all_elements) {
+    let satcat_entry = satcat.get(&elements.norad_id).cloned();
+    if satcat_entry.as_ref().is_some_and(|entry| !entry.matches_filters(&loaded_config)) {
+        continue;
+    }
+    let object_type = satcat_entry.as_ref().map(|entry| entry.object_type.clone());
+    let sat_style = style::resolve(&loaded_config, elements.norad_id, object_type.as_deref());
+    let display_name = style::display_name(&loaded_config, &sat_style, elements.norad_id, &elements.object_name);
+    let sat_material = sat_materials.get(&sat_style.color, &mut materials);
+    let label_visibility = if sat_style.label { Visibility::Inherited } else { Visibility::Hidden };
+    let mut known_elements = archive::load_history(elements.norad_id);
+    known_elements.extend(fetched_elements.remove(&elements.norad_id).unwrap_or_default());
+    let history = archive::ElementHistory::from_elements(known_elements);
     let constants = sgp4::Constants::from_elements_afspc_compatibility_mode(&elements).unwrap();
 
 
 
 
     commands.spawn((
-        Mesh2d(meshes.add(Circle::new(loaded_config.sat_radius))),
-        MeshMaterial2d(sat_material.clone()),
+        Mesh2d(meshes.add(Circle::new(sat_style.size))),
+        MeshMaterial2d(sat_material),
         Transform::from_xyz(
             0.,
             0.,
             0.,
         ),
         Satellite {
-            name:elements.object_name.clone().unwrap(),
+            norad_id: elements.norad_id,
+            name: display_name.clone(),
             constants: constants.clone(),
             elements: elements.clone(),
+            history: history.clone(),
+            analysis: analysis::analyze(&history, &loaded_config),
+            satcat: satcat_entry,
+            style: sat_style.clone(),
+            transmitters: transmitters.remove(&elements.norad_id).unwrap_or_default(),
+            generation: 0,
             times: Vec::new(),
             positions: Vec::new(),
+            gcrf_position: None,
+            gcrf_position_at: None,
             last_pass_end_datetime: Utc::now(),
             loaded_config: loaded_config.clone(),
         },
     )).with_children(|commands| {
         commands.spawn((
             (
-                Text2d::new(elements.object_name.clone().unwrap()),
+                Text2d::new(display_name.clone()),
                 text_font.clone(),
                 TextLayout::new(JustifyText::Left, LineBreak::AnyCharacter),
                 TextColor(sat_name_color),
                 Anchor::TopLeft,
                 //Transform::from_translation(Vec3::Z),
             ),
+            label_visibility,
+            SatelliteLabel,
         ));
-        let bg_width = loaded_config.sat_name_font_width*(elements.object_name.clone().unwrap().len() as f32);
+        // Sized once the label text has been laid out, in update_sat_labels
         commands
                 .spawn((
                     Sprite {
                         color: sat_name_bg_color,
-                        custom_size: Some(Vec2::new(bg_width.clone(), loaded_config.sat_name_font_size)),
+                        custom_size: Some(Vec2::ZERO),
                         ..Default::default()
                     },
-                    Transform::from_translation(-10. * Vec3::Z-loaded_config.sat_name_font_size/2.*Vec3::Y+bg_width/2.*Vec3::X-loaded_config.sat_name_font_size/10.*Vec3::X),
+                    Transform::from_translation(-10. * Vec3::Z),
+                    label_visibility,
+                    SatelliteLabelBackground,
                 ));
     });
 
     // Add satellite trail here
     commands.spawn(SatelliteTrail{
-        name:elements.object_name.clone().unwrap(),
-        constants: constants,
-        elements: elements,
+        norad_id: elements.norad_id,
+        name: display_name.clone(),
+        constants,
+        elements,
+        history,
+        object_type,
+        generation: 0,
         last_pass_end_datetime: chrono::Utc::now(),
-        spline: CubicCardinalSpline::new(0.5,Vec::new()),
+        points: Vec::new(),
         loaded_config: loaded_config.clone(),
-        color: sat_trails_color,
+        color: hexstr2color(&sat_style.trail_color),
+        style: sat_style,
     });
     };
-    commands.spawn(
-        (
-            Text2d::new("N"),
-            text_font,
-            TextLayout::new(JustifyText::Left, LineBreak::AnyCharacter),
-            TextColor(north_color),
-            Anchor::BottomCenter,
-            Transform::from_xyz(
-                -loaded_config.scene_radius*(loaded_config.user_azimuth/180.*PI).sin(),
-                loaded_config.scene_radius*(loaded_config.user_azimuth/180.*PI).cos(),
-                -60.,
-            ).with_rotation(Quat::from_rotation_z(loaded_config.user_azimuth/180.*PI),
-        ),
-    ));
 
     /*commands.spawn((
         PointLight {
@@ -290,7 +529,9 @@ fn setup(
 
 
     commands.spawn((
-        Camera2d::default(),Msaa::Sample4
+        Camera2d,Msaa::Sample4,
+        // The globe camera renders under this one, the UI stays on the 2D camera
+        IsDefaultUiCamera,
     ));
 }
 
@@ -304,11 +545,6 @@ pub fn fix(x : f64) -> f64 {
     }
     out
 }
-fn gst() -> f64{
-    let current_date = chrono::Utc::now();
-    gst_from_datetime(current_date)
-}
-
 fn gst_from_datetime(date: DateTime<Utc>) -> f64{
     let mut year = date.year() as f64;
     let mut month = date.month() as f64;
@@ -317,8 +553,8 @@ fn gst_from_datetime(date: DateTime<Utc>) -> f64{
     let m = date.minute() as f64;
     let s = date.second() as f64+(date.timestamp_millis().rem_euclid(1000) as f64)/1000.;
     if month<3.0 {
-        year = year - 1.0;
-        month = month + 12.0;
+        year -= 1.0;
+        month += 12.0;
     }
     let a = fix(year/100.0);
     let b = 2.0 - a + fix(a/4.0);
@@ -327,30 +563,62 @@ fn gst_from_datetime(date: DateTime<Utc>) -> f64{
     let t_ut1 = (jd - 2451545.0)/36525.0;
     let gmst_sec = 67310.54841 + 3.164400184812866e+09 * t_ut1 + 0.093104 * t_ut1 * t_ut1
                         - 6.2e-6 * t_ut1 * t_ut1 * t_ut1;
-    return (gmst_sec * 2.0 * std::f64::consts::PI / 86400.0).rem_euclid(2.0 * std::f64::consts::PI)
+    (gmst_sec * 2.0 * std::f64::consts::PI / 86400.0).rem_euclid(2.0 * std::f64::consts::PI)
+}
+
+/// GCRF position (meters) and speed (km/s) of a satellite, propagated from the closest element set
+fn eci_state(history: &archive::ElementHistory, date: DateTime<Utc>) -> Option<(Vector3, f64)> {
+    let set = history.closest(date)?;
+    let prediction = set.constants.propagate_afspc_compatibility_mode(set.elements.datetime_to_minutes_since_epoch(&date.naive_utc()).ok()?).ok()?;
+    let q = satkit::frametransform::qteme2gcrf(&satkit::Instant::new(date.timestamp_micros()));
+    let roted_vect = q.transform_vector(&Vector3::new(prediction.position[0]*1000.0, prediction.position[1]*1000.0, prediction.position[2]*1000.0));
+    let speed = (prediction.velocity[0].powi(2) + prediction.velocity[1].powi(2) + prediction.velocity[2].powi(2)).sqrt();
+    Some((roted_vect, speed))
+}
+
+/// Azimuth, elevation (radians) and range (meters) of a satellite seen from the configured location
+fn look_angles(history: &archive::ElementHistory, date: DateTime<Utc>, loaded_config: &config::Config) -> Option<(f64, f64, f64)> {
+    let (roted_vect, _) = eci_state(history, date)?;
+    Some(map_3d::eci2aer(gst_from_datetime(date),roted_vect[0],roted_vect[1],roted_vect[2],loaded_config.user_latitude.to_radians(),loaded_config.user_longitude.to_radians(),loaded_config.observer_height(),map_3d::Ellipsoid::WGS84))
+}
+
+/// Sub-satellite latitude, longitude (radians) and altitude (meters)
+fn sub_satellite_point(history: &archive::ElementHistory, date: DateTime<Utc>) -> Option<(f64, f64, f64)> {
+    let (roted_vect, _) = eci_state(history, date)?;
+    let (x, y, z) = map_3d::eci2ecef(gst_from_datetime(date),roted_vect[0],roted_vect[1],roted_vect[2]);
+    Some(map_3d::ecef2geodetic(x, y, z, map_3d::Ellipsoid::WGS84))
 }
 
-fn set_star_positions(mut query: Query<(&mut Transform,&Star), With<Star>>, _: Res<Time>) {
-    let gst = gst();
+/// Rate of change of the range in m/s (positive when the satellite moves away)
+fn range_rate(history: &archive::ElementHistory, date: DateTime<Utc>, loaded_config: &config::Config) -> Option<f64> {
+    let (_, _, range_a) = look_angles(history, date, loaded_config)?;
+    let (_, _, range_b) = look_angles(history, date + Duration::seconds(1), loaded_config)?;
+    Some(range_b - range_a)
+}
+
+/// Azimuth and elevation (radians) of a direction given as an equatorial unit vector, for stars and sky overlays
+fn equatorial_to_horizontal(x: f64, y: f64, z: f64, gst: f64, loaded_config: &config::Config) -> (f64, f64) {
+    let nr = map_3d::EARTH_RADIUS*1000.;  // Should reduce errors due to them not being at infinity
+    let (az,el,_) = map_3d::eci2aer(gst,x*nr,y*nr,z*nr,loaded_config.user_latitude/180.0*(PI as f64),loaded_config.user_longitude/180.0*(PI as f64),loaded_config.observer_height(),map_3d::Ellipsoid::WGS84);
+    (az,el)
+}
+
+fn set_star_positions(mut query: Query<(&mut Transform,&Star), With<Star>>, clock: Res<clock::SimClock>, view: Res<view::ViewState>) {
+    let gst = gst_from_datetime(clock.now());
     for (mut transform,star) in &mut query {
-        let nr = map_3d::EARTH_RADIUS*1000.;  // Should reduce errors due to them not being at infinity
-        let rx = star.vector.x as f64*nr;
-        let ry = star.vector.y as f64*nr;
-        let rz = star.vector.z as f64*nr;
-
-        let (mut az,el,_) = map_3d::eci2aer(gst,rx,ry,rz,(star.loaded_config.user_latitude as f64)/180.0*(PI as f64),(star.loaded_config.user_longitude as f64)/180.0*(PI as f64),star.loaded_config.user_altitude as f64,map_3d::Ellipsoid::WGS84);
-        az -= (star.loaded_config.user_azimuth / 180.0 * std::f32::consts::PI) as f64;
-        transform.translation = transform.local_x()*((star.loaded_config.scene_radius as f64*az.sin()*el.cos()) as f32)+transform.local_y()*((star.loaded_config.scene_radius as f64*az.cos()*el.cos()) as f32)-40.0*transform.local_z();
+        let (az,el) = equatorial_to_horizontal(star.vector.x as f64,star.vector.y as f64,star.vector.z as f64,gst,&star.loaded_config);
+        let position = view::sky_to_scene(az, el, star.loaded_config.scene_radius, &view);
+        transform.translation = transform.local_x()*position.x+transform.local_y()*position.y-40.0*transform.local_z();
         if el<0.0{
             transform.translation = transform.local_x()*100000000000000000000000.0;
         }
     }
 }
 
-fn set_sat_positions(mut query: Query<(&mut Transform,&Satellite), With<Satellite>>, _: Res<Time>) {
-    let current_date = chrono::Utc::now();
+fn set_sat_positions(mut query: Query<(&mut Transform,&Satellite), With<Satellite>>, clock: Res<clock::SimClock>, view: Res<view::ViewState>) {
+    let current_date = clock.now();
     for (mut transform,sat) in &mut query {
-        if sat.times.len() == 0 {
+        if sat.times.is_empty() {
             transform.translation = transform.local_x()*100000000000000000000000.0;
             continue;
         }
@@ -378,37 +646,49 @@ fn set_sat_positions(mut query: Query<(&mut Transform,&Satellite), With<Satellit
                 b = i;
                 lambda = ((current_date.timestamp_millis()-sat.times[a]) as f32)/((sat.times[b]-sat.times[a]) as f32);
             }
-            let interp_x = sat.positions[a][0]*(1.0-lambda)+sat.positions[b][0]*lambda;
-            let interp_y = sat.positions[a][1]*(1.0-lambda)+sat.positions[b][1]*lambda;
-            transform.translation = transform.local_x()*(interp_x as f32)+transform.local_y()*(interp_y as f32)+transform.local_z()*0.2;
+            let position_a = view::sky_to_scene(sat.positions[a][0] as f64, sat.positions[a][1] as f64, sat.loaded_config.scene_radius, &view);
+            let position_b = view::sky_to_scene(sat.positions[b][0] as f64, sat.positions[b][1] as f64, sat.loaded_config.scene_radius, &view);
+            let interp_x = position_a.x*(1.0-lambda)+position_b.x*lambda;
+            let interp_y = position_a.y*(1.0-lambda)+position_b.y*lambda;
+            transform.translation = transform.local_x()*interp_x+transform.local_y()*interp_y+transform.local_z()*0.2;
         } else {
             transform.translation = transform.local_x()*100000000000000000000000.0;
         }
     }
 }
 
-fn compute_sat_positions(mut query: Query<&mut Satellite, With<Satellite>>, _: Res<Time>) {
+fn compute_sat_positions(mut query: Query<&mut Satellite, With<Satellite>>, clock: Res<clock::SimClock>, generation: Res<clock::TrackGeneration>) {
     for mut sat in &mut query {
-    let mut current_date = chrono::Utc::now();
-    let mut forecasted_aos_datetime = chrono::Utc::now();
-        if current_date.signed_duration_since(sat.last_pass_end_datetime) > Duration::seconds(0 as i64) {
-            println!("Computing positions for satellite {} by propagating keplerian elements", sat.name);
+    let mut current_date = clock.now();
+    let mut forecasted_aos_datetime = current_date;
+        // The globe only needs the position once the simulated time has moved by globe_position_step_seconds
+        let step_ms = (sat.loaded_config.globe_position_step_seconds * 1000.0) as i64;
+        if sat.gcrf_position_at.is_none_or(|at| (current_date - at).num_milliseconds().abs() >= step_ms) {
+            sat.gcrf_position = eci_state(&sat.history, current_date).map(|(position, _)| position);
+            sat.gcrf_position_at = Some(current_date);
+        }
+        if sat.generation != generation.0 {
+            sat.generation = generation.0;
+            sat.last_pass_end_datetime = current_date - Duration::seconds(1);
+        }
+        if current_date.signed_duration_since(sat.last_pass_end_datetime) > Duration::seconds(0_i64) {
+            if tle::refuse_prediction(&sat.elements, current_date, &sat.loaded_config) {
+                sat.positions = Vec::new();
+                sat.times = Vec::new();
+                sat.last_pass_end_datetime = current_date.checked_add_signed(Duration::seconds(sat.loaded_config.trail_sim_step_seconds)).unwrap();
+                continue;
+            }
+            println!("Computing positions for satellite {} ({}) by propagating keplerian elements", sat.name, sat.norad_id);
             let mut points = Vec::new();
             let mut times = Vec::new();
             let mut passed_over_horizon = false;
             let mut passed_under_horizon = false;
-            while current_date.signed_duration_since(sat.last_pass_end_datetime) < Duration::seconds(sat.loaded_config.trail_max_forecast_seconds) && current_date.signed_duration_since(forecasted_aos_datetime) < Duration::seconds(sat.loaded_config.trail_max_length_seconds) && !(passed_over_horizon && passed_under_horizon) {
+            while current_date.signed_duration_since(sat.last_pass_end_datetime) < Duration::seconds(sat.loaded_config.trail_max_forecast_seconds) && current_date.signed_duration_since(forecasted_aos_datetime) < Duration::seconds(sat.style.trail_length_seconds) && !(passed_over_horizon && passed_under_horizon) {
             let gst = gst_from_datetime(current_date);
-            let prediction = sat.constants.propagate_afspc_compatibility_mode(sat.elements.datetime_to_minutes_since_epoch(&current_date.naive_utc()).unwrap()).unwrap();
-            let rx = prediction.position[0]*1000.0 as f64;
-            let ry = prediction.position[1]*1000.0 as f64;
-            let rz = prediction.position[2]*1000.0 as f64;
-            let q = satkit::frametransform::qteme2gcrf(&satkit::Instant::new(current_date.timestamp_micros()));
-            let roted_vect = q.transform_vector(&Vector3::new(rx, ry, rz));
-
-            let (mut az,el,_) = map_3d::eci2aer(gst,roted_vect[0],roted_vect[1],roted_vect[2],(sat.loaded_config.user_latitude as f64)/180.0*(PI as f64),(sat.loaded_config.user_longitude as f64)/180.0*(PI as f64),sat.loaded_config.user_altitude as f64,map_3d::Ellipsoid::WGS84);
+            let Some((roted_vect, _)) = eci_state(&sat.history, current_date) else { break };
+
+            let (az,el,_) = map_3d::eci2aer(gst,roted_vect[0],roted_vect[1],roted_vect[2],sat.loaded_config.user_latitude/180.0*(PI as f64),sat.loaded_config.user_longitude/180.0*(PI as f64),sat.loaded_config.observer_height(),map_3d::Ellipsoid::WGS84);
             //println!("Azimuth: {}, Elevation: {}", az, el);
-            az -= (sat.loaded_config.user_azimuth / 180.0 * std::f32::consts::PI) as f64;
             if !passed_over_horizon{
                 forecasted_aos_datetime = current_date;
             }
@@ -419,7 +699,7 @@ fn compute_sat_positions(mut query: Query<&mut Satellite, With<Satellite>>, _: R
                 passed_under_horizon = true;
             }
             if passed_over_horizon{
-                points.push(vec2((sat.loaded_config.scene_radius as f64*az.sin()*el.cos()) as f32, (sat.loaded_config.scene_radius as f64*az.cos()*el.cos()) as f32));
+                points.push(vec2(az as f32, el as f32));
                 times.push(current_date.timestamp_millis());
             }
            current_date = current_date.checked_add_signed(Duration::seconds(sat.loaded_config.trail_sim_step_seconds)).unwrap();
@@ -431,27 +711,30 @@ fn compute_sat_positions(mut query: Query<&mut Satellite, With<Satellite>>, _: R
     }
 }
 
-fn compute_sat_trails(mut query: Query<&mut SatelliteTrail, With<SatelliteTrail>>, _: Res<Time>) {
+fn compute_sat_trails(mut query: Query<&mut SatelliteTrail, With<SatelliteTrail>>, clock: Res<clock::SimClock>, generation: Res<clock::TrackGeneration>) {
     for mut sat in &mut query {
-    let mut current_date = chrono::Utc::now();
-    let mut forecasted_aos_datetime = chrono::Utc::now();
-        if current_date.signed_duration_since(sat.last_pass_end_datetime) > Duration::seconds(0 as i64) {
-            println!("Computing trails for satellite {} by propagating keplerian elements", sat.name);
+    let mut current_date = clock.now();
+    let mut forecasted_aos_datetime = current_date;
+        if sat.generation != generation.0 {
+            sat.generation = generation.0;
+            sat.last_pass_end_datetime = current_date - Duration::seconds(1);
+        }
+        if current_date.signed_duration_since(sat.last_pass_end_datetime) > Duration::seconds(0_i64) {
+            if tle::refuse_prediction(&sat.elements, current_date, &sat.loaded_config) {
+                sat.points = Vec::new();
+                sat.last_pass_end_datetime = current_date.checked_add_signed(Duration::seconds(sat.loaded_config.trail_sim_step_seconds)).unwrap();
+                continue;
+            }
+            println!("Computing trails for satellite {} ({}) by propagating keplerian elements", sat.name, sat.norad_id);
             let mut points = Vec::new();
             let mut passed_over_horizon = false;
             let mut passed_under_horizon = false;
-            while current_date.signed_duration_since(sat.last_pass_end_datetime) < Duration::seconds(sat.loaded_config.trail_max_forecast_seconds) && current_date.signed_duration_since(forecasted_aos_datetime) < Duration::seconds(sat.loaded_config.trail_max_length_seconds) && !(passed_over_horizon && passed_under_horizon) {
+            while current_date.signed_duration_since(sat.last_pass_end_datetime) < Duration::seconds(sat.loaded_config.trail_max_forecast_seconds) && current_date.signed_duration_since(forecasted_aos_datetime) < Duration::seconds(sat.style.trail_length_seconds) && !(passed_over_horizon && passed_under_horizon) {
             let gst = gst_from_datetime(current_date);
-            let prediction = sat.constants.propagate_afspc_compatibility_mode(sat.elements.datetime_to_minutes_since_epoch(&current_date.naive_utc()).unwrap()).unwrap();
-            let rx = prediction.position[0]*1000.0 as f64;
-            let ry = prediction.position[1]*1000.0 as f64;
-            let rz = prediction.position[2]*1000.0 as f64;
-            let q = satkit::frametransform::qteme2gcrf(&satkit::Instant::new(current_date.timestamp_micros()));
-            let roted_vect = q.transform_vector(&Vector3::new(rx, ry, rz));
-
-            let (mut az,el,_) = map_3d::eci2aer(gst,roted_vect[0],roted_vect[1],roted_vect[2],(sat.loaded_config.user_latitude as f64)/180.0*(PI as f64),(sat.loaded_config.user_longitude as f64)/180.0*(PI as f64),sat.loaded_config.user_altitude as f64,map_3d::Ellipsoid::WGS84);
+            let Some((roted_vect, _)) = eci_state(&sat.history, current_date) else { break };
+
+            let (az,el,_) = map_3d::eci2aer(gst,roted_vect[0],roted_vect[1],roted_vect[2],sat.loaded_config.user_latitude/180.0*(PI as f64),sat.loaded_config.user_longitude/180.0*(PI as f64),sat.loaded_config.observer_height(),map_3d::Ellipsoid::WGS84);
             //println!("Azimuth: {}, Elevation: {}", az, el);
-            az -= (sat.loaded_config.user_azimuth / 180.0 * std::f32::consts::PI) as f64;
             if !passed_over_horizon{
                 forecasted_aos_datetime = current_date;
             }
@@ -462,21 +745,39 @@ fn compute_sat_trails(mut query: Query<&mut SatelliteTrail, With<SatelliteTrail>
                 passed_under_horizon = true;
             }
             if passed_over_horizon{
-                points.push(vec2((sat.loaded_config.scene_radius as f64*az.sin()*el.cos()) as f32, (sat.loaded_config.scene_radius as f64*az.cos()*el.cos()) as f32));
+                points.push(vec2(az as f32, el as f32));
             }
             current_date = current_date.checked_add_signed(Duration::seconds(sat.loaded_config.trail_sim_step_seconds)).unwrap();
         }
-        sat.spline = CubicCardinalSpline::new(0.5,points);
+        sat.points = points;
         sat.last_pass_end_datetime = current_date;
         }
     }
 }
 
-fn draw_satellite_trail(query: Query<&SatelliteTrail, With<SatelliteTrail>>, _: Res<Time>, mut gizmos: Gizmos) {
+fn draw_satellite_trail(query: Query<&SatelliteTrail, With<SatelliteTrail>>, visibility: Res<groups::GroupVisibility>, selected: Res<selection::SelectedSatellite>, sat_query: Query<&Satellite>, view: Res<view::ViewState>, mut gizmos: Gizmos) {
+        if !view.show_trails {
+            return;
+        }
+        let selected_norad_id = selected.0.and_then(|entity| sat_query.get(entity).ok()).map(|sat| sat.norad_id);
         for trail in &query {
-            let spline = trail.spline.clone();
+            if groups::is_trail_hidden(&visibility, trail) {
+                continue;
+            }
+            let spline = CubicCardinalSpline::new(0.5, trail.points.iter().map(|point| view::sky_to_scene(point.x as f64, point.y as f64, trail.loaded_config.scene_radius, &view)).collect::<Vec<Vec2>>());
             match spline.to_curve() {
                 Ok(curve) => {
+                    if selected_norad_id == Some(trail.norad_id) {
+                        // Emphasize the selected trail by drawing it thicker in the selection color
+                        let selection_color = hexstr2color(&trail.loaded_config.selection_color);
+                        for offset in [Vec2::ZERO, Vec2::X, -Vec2::X, Vec2::Y, -Vec2::Y] {
+                            gizmos.linestrip(
+                                curve.iter_positions(trail.loaded_config.trail_resolution).map(|pt| (pt + offset).extend(0.0)),
+                                selection_color,
+                            );
+                        }
+                        continue;
+                    }
                     gizmos.linestrip(
                         curve.iter_positions(trail.loaded_config.trail_resolution).map(|pt| pt.extend(0.0)),
                         trail.color,
@@ -486,3 +787,78 @@ fn draw_satellite_trail(query: Query<&SatelliteTrail, With<SatelliteTrail>>, _:
             }
         }
 }
+
+fn update_sat_labels(sat_query: Query<(Entity, &Satellite, &Children)>, selected: Res<selection::SelectedSatellite>, view: Res<view::ViewState>, windows: Query<&Window, With<bevy::window::PrimaryWindow>>, mut label_query: Query<(&mut Text2d, &mut TextColor, &mut Visibility, &bevy::text::TextLayoutInfo), With<SatelliteLabel>>, mut bg_query: Query<(&mut Sprite, &mut Transform, &mut Visibility), (With<SatelliteLabelBackground>, Without<SatelliteLabel>)>, clock: Res<clock::SimClock>) {
+    let current_date = clock.now();
+    // Text2d is laid out in physical pixels
+    let scale_factor = windows.get_single().map(|window| window.scale_factor()).unwrap_or(1.0);
+    for (entity, sat, children) in &sat_query {
+        let age = tle::epoch_age_days(&sat.elements, current_date);
+        let mut label = sat.name.clone();
+        for badge in sat.analysis.badges(current_date, &sat.loaded_config) {
+            label.push_str(&format!(" [{}]", badge));
+        }
+        let color = match tle::epoch_age_status(age, &sat.loaded_config) {
+            tle::EpochAgeStatus::Fresh => hexstr2color(&sat.loaded_config.sat_name_color),
+            tle::EpochAgeStatus::Stale => {
+                label.push_str(&format!(" [!{:.0}d]", age));
+                hexstr2color(&sat.loaded_config.sat_name_stale_color)
+            }
+            tle::EpochAgeStatus::Expired => {
+                label.push_str(&format!(" [!!{:.0}d]", age));
+                hexstr2color(&sat.loaded_config.sat_name_expired_color)
+            }
+        };
+        let color = if selected.0 == Some(entity) { hexstr2color(&sat.loaded_config.selection_color) } else { color };
+        let visibility = if sat.style.label && view.show_labels { Visibility::Inherited } else { Visibility::Hidden };
+        let mut text_size = Vec2::ZERO;
+        for child in children.iter() {
+            if let Ok((mut text, mut text_color, mut label_visibility, layout)) = label_query.get_mut(*child) {
+                text_size = layout.size / scale_factor;
+                if *label_visibility != visibility {
+                    *label_visibility = visibility;
+                }
+                if text.0 != label {
+                    text.0 = label.clone();
+                }
+                if text_color.0 != color {
+                    text_color.0 = color;
+                }
+            }
+        }
+        for child in children.iter() {
+            if let Ok((mut sprite, mut transform, mut bg_visibility)) = bg_query.get_mut(*child) {
+                if *bg_visibility != visibility {
+                    *bg_visibility = visibility;
+                }
+                let padding = sat.loaded_config.sat_name_font_size/10.;
+                let bg_size = text_size + Vec2::new(2.*padding, 0.);
+                if sprite.custom_size != Some(bg_size) {
+                    sprite.custom_size = Some(bg_size);
+                    transform.translation = -10. * Vec3::Z-bg_size.y/2.*Vec3::Y+bg_size.x/2.*Vec3::X-padding*Vec3::X;
+                }
+            }
+        }
+    }
+}
+
+/// Use the element set closest to the simulated time for labels and quick lookups
+fn select_element_sets(mut sat_query: Query<&mut Satellite>, mut trail_query: Query<&mut SatelliteTrail>, clock: Res<clock::SimClock>) {
+    let current_date = clock.now();
+    for mut sat in &mut sat_query {
+        let set = match sat.history.closest(current_date) {
+            Some(set) if set.elements.datetime != sat.elements.datetime => set.clone(),
+            _ => continue,
+        };
+        sat.elements = set.elements;
+        sat.constants = set.constants;
+    }
+    for mut trail in &mut trail_query {
+        let set = match trail.history.closest(current_date) {
+            Some(set) if set.elements.datetime != trail.elements.datetime => set.clone(),
+            _ => continue,
+        };
+        trail.elements = set.elements;
+        trail.constants = set.constants;
+    }
+}
@@ -0,0 +1,370 @@
+use bevy::prelude::*;
+use bevy::tasks::{block_on, futures_lite::future, AsyncComputeTaskPool, Task};
+use chrono::{DateTime, Duration, Utc};
+
+use crate::config::{Config, Location};
+use crate::{archive, clock, eci_state, groups, gst_from_datetime, hexstr2color, keymap, terrain, view, Satellite, SatelliteTrail, Star};
+
+/// The station whose sky is shown
+#[derive(Resource, Debug, Clone)]
+pub struct ActiveObserver {
+    pub name: String,
+}
+
+/// The configured stations, or the single user_* location when none are listed
+pub fn observers(loaded_config: &Config) -> Vec<Location> {
+    if !loaded_config.observers.is_empty() {
+        return loaded_config.observers.clone();
+    }
+    vec![Location {
+        name: if loaded_config.active_observer.is_empty() { "here".to_string() } else { loaded_config.active_observer.clone() },
+        latitude: loaded_config.user_latitude,
+        longitude: loaded_config.user_longitude,
+        altitude: loaded_config.user_altitude,
+    }]
+}
+
+/// Make a station (by name, else the first one) the observer location of a config
+pub fn apply(loaded_config: &mut Config, name: &str) {
+    let stations = observers(loaded_config);
+    let Some(station) = stations.iter().find(|station| station.name == name).or(stations.first()) else { return };
+    loaded_config.move_observer(station.latitude, station.longitude, station.altitude);
+    loaded_config.active_observer = station.name.clone();
+}
+
+/// Cycle through the stations, moving every copy of the config to the new one
+pub fn cycle_observer(
+    keys: Res<ButtonInput<KeyCode>>,
+    bindings: Res<keymap::Keybindings>,
+    mut active: ResMut<ActiveObserver>,
+    mut view: ResMut<view::ViewState>,
+    mut sat_query: Query<&mut Satellite>,
+    mut trail_query: Query<&mut SatelliteTrail>,
+    mut star_query: Query<&mut Star>,
+    mut generation: ResMut<clock::TrackGeneration>,
+) {
+    if !bindings.just_pressed(&keys, keymap::Action::NextObserver) {
+        return;
+    }
+    let stations = observers(&view.loaded_config);
+    if stations.len() < 2 {
+        return;
+    }
+    let index = stations.iter().position(|station| station.name == active.name).map_or(0, |i| (i + 1) % stations.len());
+    active.name = stations[index].name.clone();
+    println!("Observing from {}", active.name);
+    apply(&mut view.loaded_config, &active.name);
+    for mut sat in &mut sat_query {
+        apply(&mut sat.loaded_config, &active.name);
+    }
+    for mut trail in &mut trail_query {
+        apply(&mut trail.loaded_config, &active.name);
+    }
+    for mut star in &mut star_query {
+        apply(&mut star.loaded_config, &active.name);
+    }
+    generation.0 += 1;
+}
+
+/// A pass of a satellite over one station
+#[derive(Debug, Clone)]
+pub struct StationPass {
+    pub norad_id: u64,
+    pub name: String,
+    pub station: String,
+    pub aos: DateTime<Utc>,
+    pub los: DateTime<Utc>,
+    pub max_elevation: f64, // radians
+}
+
+/// When a satellite is seen by two stations or more at once
+#[derive(Debug, Clone)]
+pub struct MutualWindow {
+    pub norad_id: u64,
+    pub name: String,
+    pub stations: Vec<String>,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+/// Passes of each satellite over each station, and the windows where several stations see it, sorted by time
+pub fn combined_schedule(sats: &[(u64, String, &archive::ElementHistory)], start: DateTime<Utc>, loaded_config: &Config) -> (Vec<StationPass>, Vec<MutualWindow>) {
+    let stations = observers(loaded_config);
+    let heights: Vec<f64> = stations.iter().map(|station| terrain::ellipsoidal_height(station.latitude, station.longitude, station.altitude, loaded_config)).collect();
+    let names: Vec<String> = stations.iter().map(|station| station.name.clone()).collect();
+    schedule_from_elevations(sats, &names, start, loaded_config, |history, date| {
+        let (position, _) = eci_state(history, date)?;
+        let gst = gst_from_datetime(date);
+        let elevations = stations
+            .iter()
+            .zip(&heights)
+            .map(|(station, height)| map_3d::eci2aer(gst, position[0], position[1], position[2], station.latitude.to_radians(), station.longitude.to_radians(), *height, map_3d::Ellipsoid::WGS84).1)
+            .collect();
+        Some(elevations)
+    })
+}
+
+/// The schedule from the elevation (radians) of each satellite above every station, sampled every schedule_step_seconds
+fn schedule_from_elevations<T>(
+    sats: &[(u64, String, T)],
+    stations: &[String],
+    start: DateTime<Utc>,
+    loaded_config: &Config,
+    elevations: impl Fn(&T, DateTime<Utc>) -> Option<Vec<f64>>,
+) -> (Vec<StationPass>, Vec<MutualWindow>) {
+    let step = loaded_config.schedule_step_seconds.max(1);
+    let steps = loaded_config.schedule_hours * 3600 / step;
+    let min_elevation = loaded_config.schedule_min_elevation_degrees.to_radians();
+    let mut passes = Vec::new();
+    let mut windows = Vec::new();
+    for (norad_id, name, sat) in sats {
+        let mut open_passes: Vec<Option<StationPass>> = vec![None; stations.len()];
+        let mut open_window: Option<MutualWindow> = None;
+        for i in 0..=steps {
+            let date = start + Duration::seconds(i * step);
+            let Some(sat_elevations) = elevations(sat, date) else { continue };
+            let mut seen_by = Vec::new();
+            for ((station, el), open_pass) in stations.iter().zip(sat_elevations).zip(open_passes.iter_mut()) {
+                if el >= min_elevation && i < steps {
+                    seen_by.push(station.clone());
+                    let pass = open_pass.get_or_insert_with(|| StationPass {
+                        norad_id: *norad_id,
+                        name: name.clone(),
+                        station: station.clone(),
+                        aos: date,
+                        los: date,
+                        max_elevation: el,
+                    });
+                    pass.los = date;
+                    pass.max_elevation = pass.max_elevation.max(el);
+                } else if let Some(pass) = open_pass.take() {
+                    passes.push(pass);
+                }
+            }
+            if seen_by.len() >= 2 {
+                let window = open_window.get_or_insert_with(|| MutualWindow {
+                    norad_id: *norad_id,
+                    name: name.clone(),
+                    stations: Vec::new(),
+                    start: date,
+                    end: date,
+                });
+                window.end = date;
+                for station in seen_by {
+                    if !window.stations.contains(&station) {
+                        window.stations.push(station);
+                    }
+                }
+            } else if let Some(window) = open_window.take() {
+                windows.push(window);
+            }
+        }
+    }
+    passes.sort_by_key(|pass| pass.aos);
+    windows.sort_by_key(|window| window.start);
+    (passes, windows)
+}
+
+/// Print the combined schedule, one line per pass with the stations seeing it together marked
+pub fn print_schedule(passes: &[StationPass], windows: &[MutualWindow]) {
+    println!("{:<20} {:<20} {:<24} {:>8}  Station", "AOS (UTC)", "LOS (UTC)", "Satellite", "Max el");
+    for pass in passes {
+        println!("{:<20} {:<20} {:<24} {:>7.1}°  {}", pass.aos.format("%Y-%m-%d %H:%M:%S"), pass.los.format("%Y-%m-%d %H:%M:%S"), pass.name, pass.max_elevation.to_degrees(), pass.station);
+    }
+    println!();
+    println!("Mutual visibility");
+    for window in windows {
+        println!("{:<20} {:<20} {:<24} {}", window.start.format("%Y-%m-%d %H:%M:%S"), window.end.format("%Y-%m-%d %H:%M:%S"), window.name, window.stations.join(" + "));
+    }
+}
+
+/// The combined schedule shown in the GUI, computed in the background when the panel is opened
+#[derive(Resource, Default)]
+pub struct ScheduleState {
+    pub shown: bool,
+    pub passes: Vec<StationPass>,
+    pub windows: Vec<MutualWindow>,
+    pub task: Option<Task<(Vec<StationPass>, Vec<MutualWindow>)>>,
+}
+
+/// A marker component for the combined schedule panel
+#[derive(Component)]
+pub struct SchedulePanel;
+
+pub fn setup_schedule_panel(mut commands: Commands, asset_server: Res<AssetServer>, loaded_config: Res<Config>) {
+    commands.spawn((
+        Text::new(""),
+        TextFont {
+            font: asset_server.load("fonts/FiraMono-Bold.ttf"),
+            font_size: loaded_config.info_panel_font_size,
+            ..Default::default()
+        },
+        TextColor(hexstr2color(&loaded_config.sat_name_color)),
+        BackgroundColor(hexstr2color(&loaded_config.sat_name_bg_color)),
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Px(10.),
+            left: Val::Percent(30.),
+            padding: UiRect::all(Val::Px(6.)),
+            ..default()
+        },
+        Visibility::Hidden,
+        SchedulePanel,
+    ));
+}
+
+pub fn toggle_schedule(
+    keys: Res<ButtonInput<KeyCode>>,
+    bindings: Res<keymap::Keybindings>,
+    mut schedule: ResMut<ScheduleState>,
+    sat_query: Query<&Satellite>,
+    visibility: Res<groups::GroupVisibility>,
+    view: Res<view::ViewState>,
+    clock: Res<clock::SimClock>,
+) {
+    if !bindings.just_pressed(&keys, keymap::Action::ToggleSchedule) {
+        return;
+    }
+    schedule.shown = !schedule.shown;
+    if !schedule.shown {
+        // Dropping the task cancels it
+        schedule.task = None;
+        return;
+    }
+    let now = clock.now();
+    let sats: Vec<(u64, String, archive::ElementHistory)> = sat_query
+        .iter()
+        .filter(|sat| !groups::is_satellite_hidden(&visibility, sat))
+        .map(|sat| (sat.norad_id, sat.name.clone(), sat.history.clone()))
+        .collect();
+    println!("Computing the combined schedule of {} satellites", sats.len());
+    let loaded_config = view.loaded_config.clone();
+    schedule.passes.clear();
+    schedule.windows.clear();
+    schedule.task = Some(AsyncComputeTaskPool::get().spawn(async move {
+        let sats: Vec<(u64, String, &archive::ElementHistory)> = sats.iter().map(|(norad_id, name, history)| (*norad_id, name.clone(), history)).collect();
+        combined_schedule(&sats, now, &loaded_config)
+    }));
+}
+
+/// Pick up the combined schedule once the background computation is done
+pub fn receive_schedule(mut schedule: ResMut<ScheduleState>) {
+    let Some(task) = schedule.task.as_mut() else { return };
+    if let Some((passes, windows)) = block_on(future::poll_once(task)) {
+        schedule.passes = passes;
+        schedule.windows = windows;
+        schedule.task = None;
+    }
+}
+
+pub fn update_schedule_panel(
+    schedule: Res<ScheduleState>,
+    active: Res<ActiveObserver>,
+    view: Res<view::ViewState>,
+    clock: Res<clock::SimClock>,
+    mut panel_query: Query<(&mut Text, &mut Visibility), With<SchedulePanel>>,
+) {
+    let Ok((mut text, mut visibility)) = panel_query.get_single_mut() else { return };
+    if !schedule.shown {
+        if *visibility != Visibility::Hidden {
+            *visibility = Visibility::Hidden;
+        }
+        return;
+    }
+    let now = clock.now();
+    let length = view.loaded_config.schedule_panel_length;
+    let mut lines = vec![format!("Schedule (active station: {})", active.name)];
+    if schedule.task.is_some() {
+        lines.push("computing…".to_string());
+    }
+    for pass in schedule.passes.iter().filter(|pass| pass.los >= now).take(length) {
+        let mutual = schedule.windows.iter().any(|window| window.norad_id == pass.norad_id && window.start <= pass.los && window.end >= pass.aos);
+        lines.push(format!(
+            "{} - {}  {:<20} {:>5.1}°  {}{}",
+            pass.aos.format("%m-%d %H:%M"),
+            pass.los.format("%H:%M"),
+            pass.name,
+            pass.max_elevation.to_degrees(),
+            pass.station,
+            if mutual { " *" } else { "" }
+        ));
+    }
+    let windows: Vec<&MutualWindow> = schedule.windows.iter().filter(|window| window.end >= now).take(length).collect();
+    if !windows.is_empty() {
+        lines.push("* mutual visibility:".to_string());
+        for window in windows {
+            lines.push(format!("{} - {}  {:<20} {}", window.start.format("%m-%d %H:%M"), window.end.format("%H:%M"), window.name, window.stations.join(" + ")));
+        }
+    }
+    let contents = lines.join("\n");
+    if text.0 != contents {
+        text.0 = contents;
+    }
+    if *visibility != Visibility::Inherited {
+        *visibility = Visibility::Inherited;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn start() -> DateTime<Utc> {
+        "2024-05-01T00:00:00Z".parse().unwrap()
+    }
+
+    fn minute(minute: i64) -> DateTime<Utc> {
+        start() + Duration::minutes(minute)
+    }
+
+    /// One hour sampled every minute
+    fn config() -> Config {
+        Config { schedule_hours: 1, schedule_step_seconds: 60, schedule_min_elevation_degrees: 10.0, ..Default::default() }
+    }
+
+    /// Each satellite is given the minutes [from, to) during which every station sees it, 30° up, and -5° otherwise
+    fn schedule(sats: &[(u64, &str, Vec<(i64, i64)>)]) -> (Vec<StationPass>, Vec<MutualWindow>) {
+        let sats: Vec<(u64, String, Vec<(i64, i64)>)> = sats.iter().map(|(norad_id, name, seen)| (*norad_id, name.to_string(), seen.clone())).collect();
+        let stations = vec!["paris".to_string(), "lyon".to_string()];
+        schedule_from_elevations(&sats, &stations, start(), &config(), |seen, date| {
+            let minute = (date - start()).num_minutes();
+            Some(seen.iter().map(|(from, to)| if (*from..*to).contains(&minute) { 30f64.to_radians() } else { -5f64.to_radians() }).collect())
+        })
+    }
+
+    #[test]
+    fn overlapping_passes_make_a_mutual_window() {
+        let (passes, windows) = schedule(&[(25544, "ISS", vec![(10, 20), (15, 25)])]);
+        assert_eq!(passes.len(), 2);
+        assert_eq!((passes[0].station.as_str(), passes[0].aos, passes[0].los), ("paris", minute(10), minute(19)));
+        assert_eq!((passes[1].station.as_str(), passes[1].aos, passes[1].los), ("lyon", minute(15), minute(24)));
+        assert!((passes[0].max_elevation - 30f64.to_radians()).abs() < 1e-12);
+        assert_eq!(windows.len(), 1);
+        assert_eq!((windows[0].start, windows[0].end), (minute(15), minute(19)));
+        assert_eq!(windows[0].stations, vec!["paris", "lyon"]);
+    }
+
+    #[test]
+    fn separate_passes_have_no_mutual_window() {
+        let (passes, windows) = schedule(&[(25544, "ISS", vec![(10, 20), (30, 40)])]);
+        assert_eq!(passes.len(), 2);
+        assert_eq!((passes[0].aos, passes[1].aos), (minute(10), minute(30)));
+        assert!(windows.is_empty());
+    }
+
+    #[test]
+    fn passes_are_closed_at_the_end_and_sorted_across_satellites() {
+        let (passes, windows) = schedule(&[(25544, "ISS", vec![(40, 90), (40, 90)]), (43013, "NOAA 20", vec![(5, 8), (70, 80)])]);
+        let summary: Vec<(&str, &str, DateTime<Utc>, DateTime<Utc>)> = passes.iter().map(|pass| (pass.name.as_str(), pass.station.as_str(), pass.aos, pass.los)).collect();
+        assert_eq!(summary, vec![("NOAA 20", "paris", minute(5), minute(7)), ("ISS", "paris", minute(40), minute(59)), ("ISS", "lyon", minute(40), minute(59))]);
+        assert_eq!(windows.len(), 1);
+        assert_eq!((windows[0].norad_id, windows[0].start, windows[0].end), (25544, minute(40), minute(59)));
+    }
+
+    #[test]
+    fn low_passes_are_left_out() {
+        let sats = vec![(25544, "ISS".to_string(), ())];
+        let (passes, windows) = schedule_from_elevations(&sats, &["paris".to_string()], start(), &config(), |_, _| Some(vec![5f64.to_radians()]));
+        assert!(passes.is_empty() && windows.is_empty());
+    }
+}
@@ -0,0 +1,53 @@
+use bevy::prelude::*;
+use chrono::{DateTime, TimeZone, Utc};
+use homedir::my_home;
+
+use crate::{clock, groups, keymap, Satellite};
+
+#[derive(Debug, Clone)]
+pub struct Pass {
+    pub norad_id: u64,
+    pub name: String,
+    pub aos: DateTime<Utc>,
+    pub los: DateTime<Utc>,
+    pub max_elevation: f32, // radians
+}
+
+/// Current and next passes of every satellite that isn't hidden, sorted by AOS
+pub fn upcoming_passes<'a>(sats: impl Iterator<Item = &'a Satellite>, now: DateTime<Utc>, visibility: &groups::GroupVisibility) -> Vec<Pass> {
+    let mut passes: Vec<Pass> = sats
+        .filter(|sat| !visibility.is_hidden(sat.norad_id, sat.satcat.as_ref().map(|entry| entry.object_type.as_str())))
+        .filter(|sat| !sat.times.is_empty() && sat.times[sat.times.len() - 1] >= now.timestamp_millis())
+        .filter_map(|sat| {
+            Some(Pass {
+                norad_id: sat.norad_id,
+                name: sat.name.clone(),
+                aos: Utc.timestamp_millis_opt(sat.times[0]).single()?,
+                los: Utc.timestamp_millis_opt(sat.times[sat.times.len() - 1]).single()?,
+                max_elevation: sat.positions.iter().map(|position| position.y).fold(0.0, f32::max),
+            })
+        })
+        .collect();
+    passes.sort_by_key(|pass| pass.aos);
+    passes
+}
+
+/// Write the upcoming passes to ~/.config/ontake/tasogare/passes.csv
+pub fn export_passes(passes: &[Pass]) {
+    let mut path = my_home().unwrap().expect("couldn't get home directory");
+    path.push(".config/ontake/tasogare/passes.csv");
+    let mut csv = String::from("norad_id,name,aos,los\n");
+    for pass in passes {
+        csv.push_str(&format!("{},\"{}\",{},{}\n", pass.norad_id, pass.name.replace('"', "\"\""), pass.aos.to_rfc3339(), pass.los.to_rfc3339()));
+    }
+    match std::fs::write(&path, csv) {
+        Ok(_) => println!("Exported {} passes to {}", passes.len(), path.display()),
+        Err(_) => eprintln!("Failed to export passes"),
+    }
+}
+
+pub fn export_passes_on_key(keys: Res<ButtonInput<KeyCode>>, bindings: Res<keymap::Keybindings>, sat_query: Query<&Satellite>, clock: Res<clock::SimClock>, visibility: Res<groups::GroupVisibility>) {
+    if bindings.just_pressed(&keys, keymap::Action::ExportPasses) {
+        export_passes(&upcoming_passes(sat_query.iter(), clock.now(), &visibility));
+    }
+}
@@ -0,0 +1,129 @@
+use std::f64::consts::{FRAC_PI_2, FRAC_PI_4, PI};
+
+use serde::{Deserialize, Serialize};
+
+/// Azimuthal projections of the sky dome, all centered on the zenith with the horizon on `scene_radius`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkyProjection {
+    /// The sky seen from above, squashes low elevations into the rim
+    Orthographic,
+    /// Distance from the center proportional to the zenith angle, the usual polar plot for antenna tracking
+    AzimuthalEquidistant,
+    /// Conformal, enlarges the sky near the horizon
+    Stereographic,
+    /// Lambert azimuthal equal-area, equal sky areas cover equal scene areas
+    LambertEqualArea,
+}
+
+impl SkyProjection {
+    /// Distance from the zenith for an elevation (radians), 0 at the zenith and 1 on the horizon
+    pub fn radial_distance(&self, el: f64) -> f64 {
+        let zenith_angle = FRAC_PI_2 - el;
+        match self {
+            SkyProjection::Orthographic => el.cos(),
+            SkyProjection::AzimuthalEquidistant => zenith_angle / FRAC_PI_2,
+            SkyProjection::Stereographic => (zenith_angle / 2.0).tan(),
+            SkyProjection::LambertEqualArea => (zenith_angle / 2.0).sin() * std::f64::consts::SQRT_2,
+        }
+    }
+
+    /// Map azimuth and elevation (radians, azimuth already relative to the top of the dome) to scene coordinates
+    pub fn project(&self, az: f64, el: f64, scene_radius: f32) -> (f32, f32) {
+        let r = scene_radius as f64 * self.radial_distance(el);
+        ((r * az.sin()) as f32, (r * az.cos()) as f32)
+    }
+}
+
+/// Cylindrical projections of the world map, `scene_radius` wide on each side of the central meridian
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapProjection {
+    /// Plate carrée, latitude and longitude on a regular grid
+    Equirectangular,
+    /// Conformal, cut at 85° of latitude
+    Mercator,
+}
+
+impl MapProjection {
+    /// Map latitude and longitude (radians) to scene coordinates
+    pub fn project(&self, lat: f64, lon: f64, scene_radius: f32) -> (f32, f32) {
+        // Keep ±180° as they are so lines can run up to the edges of the map
+        let lon = if lon.abs() > PI { (lon + PI).rem_euclid(2.0 * PI) - PI } else { lon };
+        let scale = scene_radius as f64 / PI;
+        let y = match self {
+            MapProjection::Equirectangular => lat,
+            MapProjection::Mercator => {
+                let lat = lat.clamp(-85f64.to_radians(), 85f64.to_radians());
+                (FRAC_PI_4 + lat / 2.0).tan().ln()
+            }
+        };
+        ((scale * lon) as f32, (scale * y) as f32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SKY_PROJECTIONS: [SkyProjection; 4] =
+        [SkyProjection::Orthographic, SkyProjection::AzimuthalEquidistant, SkyProjection::Stereographic, SkyProjection::LambertEqualArea];
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-6
+    }
+
+    #[test]
+    fn zenith_at_the_center_and_horizon_on_the_rim() {
+        for projection in SKY_PROJECTIONS {
+            assert!(close(projection.radial_distance(FRAC_PI_2), 0.0), "{:?}", projection);
+            assert!(close(projection.radial_distance(0.0), 1.0), "{:?}", projection);
+        }
+    }
+
+    #[test]
+    fn radial_distance_decreases_with_elevation() {
+        for projection in SKY_PROJECTIONS {
+            let distances: Vec<f64> = (0..=90).map(|degrees| projection.radial_distance((degrees as f64).to_radians())).collect();
+            assert!(distances.windows(2).all(|pair| pair[1] < pair[0]), "{:?}", projection);
+        }
+    }
+
+    #[test]
+    fn radial_distance_at_45_degrees() {
+        let el = FRAC_PI_4;
+        assert!(close(SkyProjection::Orthographic.radial_distance(el), FRAC_PI_4.cos()));
+        assert!(close(SkyProjection::AzimuthalEquidistant.radial_distance(el), 0.5));
+        assert!(close(SkyProjection::Stereographic.radial_distance(el), (PI / 8.0).tan()));
+        assert!(close(SkyProjection::LambertEqualArea.radial_distance(el), (PI / 8.0).sin() * std::f64::consts::SQRT_2));
+    }
+
+    #[test]
+    fn north_up_and_east_right() {
+        let (x, y) = SkyProjection::AzimuthalEquidistant.project(0.0, 0.0, 100.0);
+        assert!(x.abs() < 1e-4 && (y - 100.0).abs() < 1e-4);
+        let (x, y) = SkyProjection::AzimuthalEquidistant.project(FRAC_PI_2, FRAC_PI_4, 100.0);
+        assert!((x - 50.0).abs() < 1e-4 && y.abs() < 1e-4);
+    }
+
+    #[test]
+    fn equirectangular_map() {
+        let projection = MapProjection::Equirectangular;
+        assert_eq!(projection.project(0.0, 0.0, 100.0), (0.0, 0.0));
+        let (x, y) = projection.project(FRAC_PI_2, PI, 100.0);
+        assert!((x - 100.0).abs() < 1e-4 && (y - 50.0).abs() < 1e-4);
+        // Longitudes past the antimeridian wrap around
+        let (x, _) = projection.project(0.0, 190f64.to_radians(), 100.0);
+        assert!((x + 100.0 * 170.0 / 180.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn mercator_map() {
+        let projection = MapProjection::Mercator;
+        let (_, y) = projection.project(FRAC_PI_4, 0.0, PI as f32);
+        assert!((y as f64 - (3.0 * PI / 8.0).tan().ln()).abs() < 1e-5);
+        // Cut at 85° instead of running off to infinity
+        assert_eq!(projection.project(89f64.to_radians(), 0.0, 100.0), projection.project(85f64.to_radians(), 0.0, 100.0));
+        let (_, south) = projection.project(-FRAC_PI_4, 0.0, 100.0);
+        let (_, north) = projection.project(FRAC_PI_4, 0.0, 100.0);
+        assert!((south + north).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,211 @@
+use std::collections::HashMap;
+use std::fs;
+
+use crate::cache;
+use crate::config::Config;
+
+/// One row of the Celestrak SATCAT (https://celestrak.org/satcat/satcat-format.php)
+#[derive(Debug, Clone, Default)]
+#[allow(dead_code)] // the whole record is kept, not every column is shown
+pub struct SatcatEntry {
+    pub norad_id: u64,
+    pub object_name: String,
+    pub object_id: String, // international designator
+    pub object_type: String, // PAY, R/B, DEB or UNK
+    pub ops_status_code: String,
+    pub owner: String,
+    pub launch_date: String,
+    pub launch_site: String,
+    pub decay_date: String,
+    pub rcs: Option<f64>, // m²
+}
+
+impl SatcatEntry {
+    pub fn object_type_name(&self) -> &'static str {
+        match self.object_type.as_str() {
+            "PAY" => "payload",
+            "R/B" => "rocket body",
+            "DEB" => "debris",
+            _ => "unknown",
+        }
+    }
+
+    pub fn status_name(&self) -> &'static str {
+        match self.ops_status_code.as_str() {
+            "+" => "operational",
+            "-" => "nonoperational",
+            "P" => "partially operational",
+            "B" => "backup",
+            "S" => "spare",
+            "X" => "extended mission",
+            "D" => "decayed",
+            _ => "unknown",
+        }
+    }
+
+    /// SMALL (< 0.1 m²), MEDIUM (< 1 m²) or LARGE, like the old SATCAT size classes
+    pub fn rcs_size(&self) -> &'static str {
+        match self.rcs {
+            Some(rcs) if rcs < 0.1 => "SMALL",
+            Some(rcs) if rcs < 1.0 => "MEDIUM",
+            Some(_) => "LARGE",
+            None => "UNKNOWN",
+        }
+    }
+
+    pub fn matches_filters(&self, config: &Config) -> bool {
+        let matches = |filter: &Vec<String>, value: &str| filter.is_empty() || filter.iter().any(|f| f.eq_ignore_ascii_case(value));
+        matches(&config.satcat_filter_object_types, &self.object_type)
+            && matches(&config.satcat_filter_owners, &self.owner)
+            && matches(&config.satcat_filter_status_codes, &self.ops_status_code)
+            && matches(&config.satcat_filter_rcs_sizes, self.rcs_size())
+            && (config.satcat_filter_launched_after.is_empty() || self.launch_date >= config.satcat_filter_launched_after)
+    }
+}
+
+/// Download the SATCAT to the local cache if the source is a URL and the cache is outdated
+pub async fn update_satcat(config: &Config) {
+    cache::refresh(&config.satcat_source, "SATCAT", config.satcat_update_interval_seconds, |body| !parse_satcat(body).is_empty()).await;
+}
+
+/// SATCAT entries keyed by NORAD ID, empty if no SATCAT is configured or available
+pub fn load_satcat(config: &Config) -> HashMap<u64, SatcatEntry> {
+    if config.satcat_source.is_empty() {
+        return HashMap::new();
+    }
+    let path = cache::source_path(&config.satcat_source, "SATCAT");
+    match fs::read_to_string(&path) {
+        Ok(contents) => parse_satcat(&contents),
+        Err(_) => {
+            eprintln!("Couldn't read SATCAT from {}", path.display());
+            HashMap::new()
+        }
+    }
+}
+
+/// Split one CSV record, honouring double-quoted fields
+fn split_csv_line(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+pub fn parse_satcat(contents: &str) -> HashMap<u64, SatcatEntry> {
+    let mut lines = contents.lines();
+    let header = match lines.next() {
+        Some(header) => split_csv_line(header.trim_start_matches('\u{feff}')),
+        None => return HashMap::new(),
+    };
+    let column = |name: &str| header.iter().position(|h| h.trim() == name);
+    let (name_col, id_col, norad_col, type_col, status_col, owner_col, launch_col, site_col, decay_col, rcs_col) = (
+        column("OBJECT_NAME"),
+        column("OBJECT_ID"),
+        column("NORAD_CAT_ID"),
+        column("OBJECT_TYPE"),
+        column("OPS_STATUS_CODE"),
+        column("OWNER"),
+        column("LAUNCH_DATE"),
+        column("LAUNCH_SITE"),
+        column("DECAY_DATE"),
+        column("RCS"),
+    );
+    let norad_col = match norad_col {
+        Some(col) => col,
+        None => {
+            eprintln!("SATCAT has no NORAD_CAT_ID column");
+            return HashMap::new();
+        }
+    };
+    let mut entries = HashMap::new();
+    for line in lines {
+        let fields = split_csv_line(line);
+        let get = |col: Option<usize>| col.and_then(|col| fields.get(col)).map(|f| f.trim().to_string()).unwrap_or_default();
+        let norad_id = match fields.get(norad_col).and_then(|f| f.trim().parse::<u64>().ok()) {
+            Some(norad_id) => norad_id,
+            None => continue,
+        };
+        entries.insert(
+            norad_id,
+            SatcatEntry {
+                norad_id,
+                object_name: get(name_col),
+                object_id: get(id_col),
+                object_type: get(type_col),
+                ops_status_code: get(status_col),
+                owner: get(owner_col),
+                launch_date: get(launch_col),
+                launch_site: get(site_col),
+                decay_date: get(decay_col),
+                rcs: get(rcs_col).parse().ok(),
+            },
+        );
+    }
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SATCAT: &str = "\u{feff}OBJECT_NAME,OBJECT_ID,NORAD_CAT_ID,OBJECT_TYPE,OPS_STATUS_CODE,OWNER,LAUNCH_DATE,LAUNCH_SITE,DECAY_DATE,PERIOD,INCLINATION,APOGEE,PERIGEE,RCS,DATA_STATUS_CODE,ORBIT_CENTER,ORBIT_TYPE
+ISS (ZARYA),1998-067A,25544,PAY,+,ISS,1998-11-20,TYMSC,,92.90,51.64,419,413,399.0524,,EA,ORB
+\"COSMOS 2251 DEB, \"\"A\"\"\",1993-036AL,33771,DEB,,CIS,1993-06-16,PKMTR,2012-04-23,,,,,0.0042,,EA,IMP
+NO ID,2000-001A,,PAY,+,US,2000-01-01,AFETR,,,,,,,,EA,ORB
+";
+
+    #[test]
+    fn splits_quoted_fields() {
+        assert_eq!(split_csv_line("a,\"b,c\",\"d \"\"e\"\"\",,f"), vec!["a", "b,c", "d \"e\"", "", "f"]);
+    }
+
+    #[test]
+    fn parses_entries_by_header_name() {
+        let entries = parse_satcat(SATCAT);
+        assert_eq!(entries.len(), 2);
+        let iss = &entries[&25544];
+        assert_eq!(iss.object_name, "ISS (ZARYA)");
+        assert_eq!(iss.object_id, "1998-067A");
+        assert_eq!(iss.object_type_name(), "payload");
+        assert_eq!(iss.status_name(), "operational");
+        assert_eq!(iss.launch_site, "TYMSC");
+        assert_eq!(iss.rcs_size(), "LARGE");
+        let debris = &entries[&33771];
+        assert_eq!(debris.object_name, "COSMOS 2251 DEB, \"A\"");
+        assert_eq!(debris.decay_date, "2012-04-23");
+        assert_eq!(debris.status_name(), "unknown");
+        assert_eq!(debris.rcs_size(), "SMALL");
+    }
+
+    #[test]
+    fn rejects_listings_without_catalog_numbers() {
+        assert!(parse_satcat("OBJECT_NAME,OBJECT_ID\nISS (ZARYA),1998-067A\n").is_empty());
+        assert!(parse_satcat("").is_empty());
+        assert!(parse_satcat("<html><body>503 Service Unavailable</body></html>").is_empty());
+    }
+
+    #[test]
+    fn filters_entries() {
+        let entries = parse_satcat(SATCAT);
+        let mut config = Config::default();
+        assert!(entries[&33771].matches_filters(&config));
+        config.satcat_filter_object_types = vec!["pay".to_string()];
+        assert!(entries[&25544].matches_filters(&config));
+        assert!(!entries[&33771].matches_filters(&config));
+        config.satcat_filter_launched_after = "2000-01-01".to_string();
+        assert!(!entries[&25544].matches_filters(&config));
+    }
+}
@@ -0,0 +1,230 @@
+use bevy::{
+    input::{
+        keyboard::{Key, KeyboardInput},
+        ButtonState,
+    },
+    prelude::*,
+};
+
+use crate::{config, groups, hexstr2color, selection, view, Satellite};
+
+const MAX_RESULTS: usize = 8;
+
+/// State of the `/` search box
+#[derive(Resource, Default)]
+pub struct SearchState {
+    pub active: bool,
+    pub query: String,
+    pub results: Vec<Entity>,
+    pub cursor: usize,
+}
+
+/// A marker component for the search box text
+#[derive(Component)]
+pub struct SearchBox;
+
+/// Run condition for keyboard shortcuts, which must not fire while typing a search
+pub fn search_inactive(search: Res<SearchState>) -> bool {
+    !search.active
+}
+
+/// Score how well `query` matches a satellite, higher is better.
+/// Catalog numbers must match as a prefix, names as an in-order subsequence
+/// with bonuses for consecutive characters and word starts.
+pub fn fuzzy_score(query: &str, name: &str, norad_id: u64) -> Option<i64> {
+    let query = query.trim().to_lowercase();
+    if query.is_empty() {
+        return None;
+    }
+    let norad = norad_id.to_string();
+    if norad == query {
+        return Some(10_000);
+    }
+    if query.chars().all(|c| c.is_ascii_digit()) && norad.starts_with(&query) {
+        return Some(5_000 - norad.len() as i64);
+    }
+    let name = name.to_lowercase();
+    let name_chars: Vec<char> = name.chars().collect();
+    let mut score = 0;
+    let mut position = 0;
+    let mut previous_match: Option<usize> = None;
+    for c in query.chars() {
+        let found = name_chars[position..].iter().position(|n| *n == c)? + position;
+        score += 10;
+        if previous_match == Some(found.wrapping_sub(1)) {
+            score += 15;
+        }
+        if found == 0 || !name_chars[found - 1].is_alphanumeric() {
+            score += 20;
+        }
+        score -= (found - position) as i64;
+        previous_match = Some(found);
+        position = found + 1;
+    }
+    if name.starts_with(&query) {
+        score += 100;
+    }
+    Some(score - name_chars.len() as i64 / 4)
+}
+
+pub fn setup_search(mut commands: Commands, asset_server: Res<AssetServer>, loaded_config: Res<config::Config>) {
+    commands.spawn((
+        Text::new(""),
+        TextFont {
+            font: asset_server.load("fonts/FiraMono-Bold.ttf"),
+            font_size: loaded_config.info_panel_font_size,
+            ..Default::default()
+        },
+        TextColor(hexstr2color(&loaded_config.sat_name_color)),
+        BackgroundColor(hexstr2color(&loaded_config.sat_name_bg_color)),
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Px(10.),
+            left: Val::Px(10.),
+            padding: UiRect::all(Val::Px(6.)),
+            ..default()
+        },
+        Visibility::Hidden,
+        SearchBox,
+    ));
+}
+
+pub fn handle_search_input(
+    mut events: EventReader<KeyboardInput>,
+    mut search: ResMut<SearchState>,
+    mut selected: ResMut<selection::SelectedSatellite>,
+    sat_query: Query<(Entity, &Satellite)>,
+    visibility: Res<groups::GroupVisibility>,
+) {
+    let mut changed = false;
+    for event in events.read() {
+        if event.state != ButtonState::Pressed {
+            continue;
+        }
+        if !search.active {
+            if matches!(&event.logical_key, Key::Character(c) if c.as_str() == "/") {
+                search.active = true;
+                search.query.clear();
+                search.cursor = 0;
+                changed = true;
+            }
+            continue;
+        }
+        match &event.logical_key {
+            Key::Escape => {
+                search.active = false;
+                search.results.clear();
+            }
+            Key::Enter => {
+                if let Some(entity) = search.results.get(search.cursor) {
+                    selected.0 = Some(*entity);
+                }
+                search.active = false;
+                search.results.clear();
+            }
+            Key::ArrowDown => search.cursor = (search.cursor + 1).min(search.results.len().saturating_sub(1)),
+            Key::ArrowUp => search.cursor = search.cursor.saturating_sub(1),
+            Key::Backspace => {
+                search.query.pop();
+                changed = true;
+            }
+            Key::Space => {
+                search.query.push(' ');
+                changed = true;
+            }
+            Key::Character(c) => {
+                search.query.push_str(c.as_str());
+                changed = true;
+            }
+            _ => (),
+        }
+    }
+    if changed && search.active {
+        let mut scored: Vec<(i64, Entity)> = sat_query
+            .iter()
+            .filter(|(_, sat)| !groups::is_satellite_hidden(&visibility, sat))
+            .filter_map(|(entity, sat)| Some((fuzzy_score(&search.query, &sat.name, sat.norad_id)?, entity)))
+            .collect();
+        scored.sort_by_key(|b| std::cmp::Reverse(b.0));
+        search.results = scored.into_iter().take(MAX_RESULTS).map(|(_, entity)| entity).collect();
+        search.cursor = 0;
+    }
+}
+
+pub fn update_search_box(search: Res<SearchState>, sat_query: Query<&Satellite>, mut box_query: Query<(&mut Text, &mut Visibility), With<SearchBox>>) {
+    if !search.is_changed() {
+        return;
+    }
+    let Ok((mut text, mut visibility)) = box_query.get_single_mut() else { return };
+    if !search.active {
+        *visibility = Visibility::Hidden;
+        return;
+    }
+    let mut lines = vec![format!("/{}_", search.query)];
+    for (i, entity) in search.results.iter().enumerate() {
+        if let Ok(sat) = sat_query.get(*entity) {
+            let marker = if i == search.cursor { ">" } else { " " };
+            lines.push(format!("{} {} ({})", marker, sat.name, sat.norad_id));
+        }
+    }
+    text.0 = lines.join("\n");
+    *visibility = Visibility::Inherited;
+}
+
+/// Rings around the current search results on the dome, the highlighted one larger
+pub fn draw_search_results(search: Res<SearchState>, view: Res<view::ViewState>, sat_query: Query<(&GlobalTransform, &Satellite)>, mut gizmos: Gizmos) {
+    if !search.active {
+        return;
+    }
+    for (i, entity) in search.results.iter().enumerate() {
+        if let Ok((transform, sat)) = sat_query.get(*entity) {
+            let scale = if i == search.cursor { 4.0 } else { 2.5 };
+            gizmos.circle_2d(
+                Isometry2d::from_translation(transform.translation().truncate()),
+                sat.style.size * scale * view::readable_scale(&view),
+                hexstr2color(&sat.loaded_config.selection_color),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exact_catalog_number_wins() {
+        assert_eq!(fuzzy_score("25544", "ISS (ZARYA)", 25544), Some(10_000));
+        assert!(fuzzy_score("255", "ISS (ZARYA)", 25544).unwrap() > fuzzy_score("iss", "ISS (ZARYA)", 25544).unwrap());
+        assert_eq!(fuzzy_score("544", "ISS (ZARYA)", 25544), None);
+    }
+
+    #[test]
+    fn names_match_as_subsequence() {
+        assert!(fuzzy_score("nh", "NOAA 19", 33591).is_none());
+        assert!(fuzzy_score("noa19", "NOAA 19", 33591).is_some());
+        assert!(fuzzy_score("zarya", "ISS (ZARYA)", 25544).is_some());
+        assert!(fuzzy_score("ayraz", "ISS (ZARYA)", 25544).is_none());
+    }
+
+    #[test]
+    fn ignores_case_and_surrounding_spaces() {
+        assert_eq!(fuzzy_score("  Noaa ", "NOAA 19", 33591), fuzzy_score("noaa", "NOAA 19", 33591));
+        assert_eq!(fuzzy_score("   ", "NOAA 19", 33591), None);
+    }
+
+    #[test]
+    fn prefers_prefixes_consecutive_characters_and_word_starts() {
+        let score = |query: &str, name: &str| fuzzy_score(query, name, 1).unwrap();
+        assert!(score("noaa", "NOAA 19") > score("noaa", "SNOAAX 1"));
+        assert!(score("goes", "GOES 16") > score("goes", "GXOXEXS 16"));
+        assert!(score("ss", "STARLINK-1 SAT") > score("ss", "XSXS"));
+        // Shorter names rank first when everything else is equal
+        assert!(score("noaa", "NOAA 15") > score("noaa", "NOAA 15 DEB (LONG NAME)"));
+    }
+
+    #[test]
+    fn query_longer_than_name_does_not_match() {
+        assert_eq!(fuzzy_score("noaa 19 extra", "NOAA 19", 33591), None);
+    }
+}
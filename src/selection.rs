@@ -0,0 +1,232 @@
+use bevy::{prelude::*, window::PrimaryWindow};
+use chrono::{TimeZone, Utc};
+
+use crate::{clock, config, eci_state, footprint, view, hexstr2color, look_angles, range_rate, sub_satellite_point, transmitters, Satellite};
+
+const EARTH_MU: f64 = 398600.4418; // km³/s²
+const EARTH_EQUATORIAL_RADIUS: f64 = 6378.137; // km
+
+/// The satellite under the cursor, if any
+#[derive(Resource, Default)]
+pub struct HoveredSatellite(pub Option<Entity>);
+
+/// The satellite whose details are shown in the selection panel
+#[derive(Resource, Default)]
+pub struct SelectedSatellite(pub Option<Entity>);
+
+/// A marker component for the tooltip following the cursor
+#[derive(Component)]
+pub struct HoverTooltip;
+
+/// A marker component for the panel describing the selected satellite
+#[derive(Component)]
+pub struct SelectionPanel;
+
+pub fn setup_selection(mut commands: Commands, asset_server: Res<AssetServer>, loaded_config: Res<config::Config>) {
+    let font = asset_server.load("fonts/FiraMono-Bold.ttf");
+    let text_font = TextFont {
+        font: font.clone(),
+        font_size: loaded_config.info_panel_font_size,
+        ..Default::default()
+    };
+    commands.spawn((
+        Text::new(""),
+        text_font.clone(),
+        TextColor(hexstr2color(&loaded_config.sat_name_color)),
+        BackgroundColor(hexstr2color(&loaded_config.sat_name_bg_color)),
+        Node {
+            position_type: PositionType::Absolute,
+            padding: UiRect::all(Val::Px(4.)),
+            ..default()
+        },
+        Visibility::Hidden,
+        HoverTooltip,
+    ));
+    commands.spawn((
+        Text::new(""),
+        text_font,
+        TextColor(hexstr2color(&loaded_config.sat_name_color)),
+        BackgroundColor(hexstr2color(&loaded_config.sat_name_bg_color)),
+        Node {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(10.),
+            left: Val::Px(10.),
+            padding: UiRect::all(Val::Px(6.)),
+            ..default()
+        },
+        Visibility::Hidden,
+        SelectionPanel,
+    ));
+}
+
+/// Hover the satellite closest to the cursor and select it on click (but not at the end of a drag)
+pub fn pick_satellites(
+    windows: Query<&Window, With<PrimaryWindow>>,
+    camera_query: Query<(&Camera, &GlobalTransform), With<Camera2d>>,
+    sat_query: Query<(Entity, &GlobalTransform, &InheritedVisibility, &Satellite)>,
+    buttons: Res<ButtonInput<MouseButton>>,
+    view: Res<view::ViewState>,
+    mut hovered: ResMut<HoveredSatellite>,
+    mut selected: ResMut<SelectedSatellite>,
+) {
+    let Ok(window) = windows.get_single() else { return };
+    let Ok((camera, camera_transform)) = camera_query.get_single() else { return };
+    let cursor = window
+        .cursor_position()
+        .and_then(|cursor| camera.viewport_to_world_2d(camera_transform, cursor).ok());
+    let mut closest = None;
+    if let Some(cursor) = cursor {
+        let mut closest_distance = f32::MAX;
+        for (entity, transform, visibility, sat) in &sat_query {
+            if !visibility.get() {
+                continue;
+            }
+            let distance = transform.translation().truncate().distance(cursor);
+            if distance < sat.loaded_config.pick_radius.max(sat.style.size) * view::readable_scale(&view) && distance < closest_distance {
+                closest_distance = distance;
+                closest = Some(entity);
+            }
+        }
+    }
+    if hovered.0 != closest {
+        hovered.0 = closest;
+    }
+    if buttons.just_released(MouseButton::Left) && view.drag_distance <= view::DRAG_THRESHOLD {
+        selected.0 = closest;
+    }
+}
+
+pub fn update_hover_tooltip(
+    windows: Query<&Window, With<PrimaryWindow>>,
+    hovered: Res<HoveredSatellite>,
+    sat_query: Query<&Satellite>,
+    mut tooltip_query: Query<(&mut Text, &mut Node, &mut Visibility), With<HoverTooltip>>,
+    clock: Res<clock::SimClock>,
+) {
+    let Ok((mut text, mut node, mut visibility)) = tooltip_query.get_single_mut() else { return };
+    let sat = hovered.0.and_then(|entity| sat_query.get(entity).ok());
+    let cursor = windows.get_single().ok().and_then(|window| window.cursor_position());
+    match (sat, cursor) {
+        (Some(sat), Some(cursor)) => {
+            let mut contents = sat.name.clone();
+            if let Some((az, el, _)) = look_angles(&sat.history, clock.now(), &sat.loaded_config) {
+                contents.push_str(&format!("  az {:.1}° el {:.1}°", az.to_degrees().rem_euclid(360.0), el.to_degrees()));
+            }
+            if text.0 != contents {
+                text.0 = contents;
+            }
+            node.left = Val::Px(cursor.x + 14.);
+            node.top = Val::Px(cursor.y + 14.);
+            *visibility = Visibility::Inherited;
+        }
+        _ => {
+            if *visibility != Visibility::Hidden {
+                *visibility = Visibility::Hidden;
+            }
+        }
+    }
+}
+
+/// Apogee and perigee altitudes (km) from the mean motion and eccentricity
+fn apsides_km(elements: &sgp4::Elements) -> (f64, f64) {
+    let mean_motion = elements.mean_motion * 2.0 * std::f64::consts::PI / 86400.0; // rad/s
+    let semi_major_axis = (EARTH_MU / mean_motion.powi(2)).cbrt();
+    (
+        semi_major_axis * (1.0 + elements.eccentricity) - EARTH_EQUATORIAL_RADIUS,
+        semi_major_axis * (1.0 - elements.eccentricity) - EARTH_EQUATORIAL_RADIUS,
+    )
+}
+
+pub fn update_selection_panel(
+    mut selected: ResMut<SelectedSatellite>,
+    sat_query: Query<&Satellite>,
+    mut panel_query: Query<(&mut Text, &mut Visibility), With<SelectionPanel>>,
+    clock: Res<clock::SimClock>,
+) {
+    let Ok((mut text, mut visibility)) = panel_query.get_single_mut() else { return };
+    let sat = match selected.0.map(|entity| sat_query.get(entity)) {
+        Some(Ok(sat)) => sat,
+        Some(Err(_)) => {
+            selected.0 = None;
+            return;
+        }
+        None => {
+            if *visibility != Visibility::Hidden {
+                *visibility = Visibility::Hidden;
+            }
+            return;
+        }
+    };
+    let now = clock.now();
+    let mut lines = vec![format!("{} (NORAD {})", sat.name, sat.norad_id)];
+    if let Some((az, el, range)) = look_angles(&sat.history, now, &sat.loaded_config) {
+        lines.push(format!("Az {:.2}°  El {:.2}°  Range {:.1} km", az.to_degrees().rem_euclid(360.0), el.to_degrees(), range / 1000.0));
+    }
+    if let Some(rate) = range_rate(&sat.history, now, &sat.loaded_config) {
+        lines.push(format!("Range rate {:+.3} km/s", rate / 1000.0));
+    }
+    if let Some((lat, lon, alt)) = sub_satellite_point(&sat.history, now) {
+        lines.push(format!("Altitude {:.1} km  Sub-point {:.3}°, {:.3}°", alt / 1000.0, lat.to_degrees(), lon.to_degrees()));
+    }
+    if let Some((_, speed)) = eci_state(&sat.history, now) {
+        lines.push(format!("Velocity {:.3} km/s", speed));
+    }
+    let elements = &sat.elements;
+    let (apogee, perigee) = apsides_km(elements);
+    lines.push(format!(
+        "Inc {:.3}°  Period {:.1} min  Apogee {:.0} km  Perigee {:.0} km",
+        elements.inclination,
+        1440.0 / elements.mean_motion,
+        apogee,
+        perigee
+    ));
+    lines.push(format!("TLE epoch {} ({:.1} days old)", elements.datetime.format("%Y-%m-%d %H:%M:%S"), crate::tle::epoch_age_days(elements, now)));
+    if !sat.times.is_empty() {
+        let aos = Utc.timestamp_millis_opt(sat.times[0]).single();
+        let los = Utc.timestamp_millis_opt(sat.times[sat.times.len() - 1]).single();
+        if let (Some(aos), Some(los)) = (aos, los) {
+            if aos <= now {
+                lines.push(format!("In view until {}", los.format("%H:%M:%S")));
+            } else {
+                lines.push(format!("Next pass {} - {}", aos.format("%Y-%m-%d %H:%M:%S"), los.format("%H:%M:%S")));
+            }
+        }
+    }
+    let heard_from: Vec<String> = footprint::locations_in_footprint(&sat.history, now, &sat.loaded_config)
+        .into_iter()
+        .map(|(location, el)| format!("{} ({:.0}°)", location.name, el.to_degrees()))
+        .collect();
+    if !heard_from.is_empty() {
+        lines.push(format!("Heard from {}", heard_from.join(", ")));
+    }
+    if let Some(entry) = &sat.satcat {
+        lines.push(format!("{}  {}  launched {}  RCS {}  {}", entry.object_type_name(), entry.owner, entry.launch_date, entry.rcs_size(), entry.status_name()));
+    }
+    for transmitter in &sat.transmitters {
+        lines.push(format!("  {}", transmitter.describe()));
+    }
+    if let (Some(downlink), Some(rate)) = (transmitters::downlink_frequency_hz(sat.norad_id, &sat.transmitters, &sat.loaded_config), range_rate(&sat.history, now, &sat.loaded_config)) {
+        let shift = transmitters::doppler_shift_hz(downlink, rate);
+        lines.push(format!("  tune {:.4} MHz (doppler {:+.2} kHz)", (downlink as f64 + shift) / 1e6, shift / 1e3));
+    }
+    let contents = lines.join("\n");
+    if text.0 != contents {
+        text.0 = contents;
+    }
+    if *visibility != Visibility::Inherited {
+        *visibility = Visibility::Inherited;
+    }
+}
+
+/// Ring around the hovered and selected satellites
+pub fn draw_selection_markers(hovered: Res<HoveredSatellite>, selected: Res<SelectedSatellite>, view: Res<view::ViewState>, sat_query: Query<(&GlobalTransform, &Satellite)>, mut gizmos: Gizmos) {
+    for (entity, scale) in [(hovered.0, 2.0), (selected.0, 3.0)] {
+        if let Some(Ok((transform, sat))) = entity.map(|entity| sat_query.get(entity)) {
+            gizmos.circle_2d(
+                Isometry2d::from_translation(transform.translation().truncate()),
+                sat.style.size * scale * view::readable_scale(&view),
+                hexstr2color(&sat.loaded_config.selection_color),
+            );
+        }
+    }
+}
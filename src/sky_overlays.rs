@@ -0,0 +1,117 @@
+use bevy::prelude::*;
+use std::f64::consts::PI;
+
+use crate::config::Config;
+use crate::{clock, equatorial_to_horizontal, gst_from_datetime, hexstr2color, view};
+
+/// Mean obliquity of the ecliptic at J2000 (radians)
+const OBLIQUITY: f64 = 23.439_291 * PI / 180.0;
+
+/// Rows of the J2000 equatorial to galactic rotation (Hipparcos definition)
+const EQUATORIAL_TO_GALACTIC: [[f64; 3]; 3] = [
+    [-0.054_875_560_4, -0.873_437_090_2, -0.483_835_015_5],
+    [0.494_109_427_9, -0.444_829_630_0, 0.746_982_244_5],
+    [-0.867_666_149_0, -0.198_076_373_4, 0.455_983_776_2],
+];
+
+/// Equatorial unit vector of a right ascension and declination (radians)
+fn radec_vector(ra: f64, dec: f64) -> [f64; 3] {
+    [dec.cos() * ra.cos(), dec.cos() * ra.sin(), dec.sin()]
+}
+
+fn ecliptic_vector(longitude: f64) -> [f64; 3] {
+    [longitude.cos(), longitude.sin() * OBLIQUITY.cos(), longitude.sin() * OBLIQUITY.sin()]
+}
+
+fn galactic_vector(longitude: f64) -> [f64; 3] {
+    let m = EQUATORIAL_TO_GALACTIC;
+    let (c, s) = (longitude.cos(), longitude.sin());
+    [m[0][0] * c + m[1][0] * s, m[0][1] * c + m[1][1] * s, m[0][2] * c + m[1][2] * s]
+}
+
+/// Draw a curve of equatorial directions on the dome, leaving out what's below the horizon
+fn draw_curve(points: impl Iterator<Item = [f64; 3]>, gst: f64, loaded_config: &Config, view: &view::ViewState, color: Color, gizmos: &mut Gizmos) {
+    let mut segment: Vec<Vec2> = Vec::new();
+    for [x, y, z] in points {
+        let (az, el) = equatorial_to_horizontal(x, y, z, gst, loaded_config);
+        if el >= 0.0 {
+            segment.push(view::sky_to_scene(az, el, loaded_config.scene_radius, view));
+        } else if !segment.is_empty() {
+            gizmos.linestrip_2d(std::mem::take(&mut segment), color);
+        }
+    }
+    if !segment.is_empty() {
+        gizmos.linestrip_2d(segment, color);
+    }
+}
+
+/// Equatorial grid, celestial equator, ecliptic and galactic plane, each enabled in the config
+pub fn draw_sky_overlays(view: Res<view::ViewState>, clock: Res<clock::SimClock>, mut gizmos: Gizmos) {
+    let loaded_config = &view.loaded_config;
+    let gst = gst_from_datetime(clock.now());
+    let points = loaded_config.sky_overlay_points.max(8);
+    let circle = move || (0..=points).map(move |i| 2.0 * PI * i as f64 / points as f64);
+    if loaded_config.equatorial_grid {
+        let color = hexstr2color(&loaded_config.equatorial_grid_color);
+        let ra_step = loaded_config.equatorial_grid_ra_hours.max(0.25) * PI / 12.0;
+        let dec_step = loaded_config.equatorial_grid_dec_degrees.max(1.0).to_radians();
+        let mut ra = 0.0;
+        while ra < 2.0 * PI {
+            let decs = (0..=points / 2).map(|i| -PI / 2.0 + PI * i as f64 / (points / 2) as f64);
+            draw_curve(decs.map(|dec| radec_vector(ra, dec)), gst, loaded_config, &view, color, &mut gizmos);
+            ra += ra_step;
+        }
+        let mut dec = dec_step;
+        while dec < PI / 2.0 {
+            for dec in [dec, -dec] {
+                draw_curve(circle().map(|ra| radec_vector(ra, dec)), gst, loaded_config, &view, color, &mut gizmos);
+            }
+            dec += dec_step;
+        }
+    }
+    if loaded_config.celestial_equator {
+        draw_curve(circle().map(|ra| radec_vector(ra, 0.0)), gst, loaded_config, &view, hexstr2color(&loaded_config.celestial_equator_color), &mut gizmos);
+    }
+    if loaded_config.ecliptic {
+        draw_curve(circle().map(ecliptic_vector), gst, loaded_config, &view, hexstr2color(&loaded_config.ecliptic_color), &mut gizmos);
+    }
+    if loaded_config.galactic_plane {
+        draw_curve(circle().map(galactic_vector), gst, loaded_config, &view, hexstr2color(&loaded_config.galactic_plane_color), &mut gizmos);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dot(a: [f64; 3], b: [f64; 3]) -> f64 {
+        a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+    }
+
+    #[test]
+    fn ecliptic_crosses_the_equator_at_the_equinoxes() {
+        let equinox = ecliptic_vector(0.0);
+        assert!((equinox[0] - 1.0).abs() < 1e-12 && equinox[1].abs() < 1e-12 && equinox[2].abs() < 1e-12);
+        // The June solstice is at RA 6h and a declination of the obliquity
+        let solstice = ecliptic_vector(PI / 2.0);
+        assert!((dot(solstice, radec_vector(PI / 2.0, OBLIQUITY)) - 1.0).abs() < 1e-12);
+        // Every point is 90° from the ecliptic pole, at RA 18h and Dec 90° - obliquity
+        let pole = radec_vector(1.5 * PI, PI / 2.0 - OBLIQUITY);
+        for i in 0..36 {
+            assert!(dot(ecliptic_vector(i as f64 * PI / 18.0), pole).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn galactic_plane_is_90_degrees_from_the_north_galactic_pole() {
+        let pole = radec_vector(192.859_48_f64.to_radians(), 27.128_25_f64.to_radians());
+        for i in 0..36 {
+            let point = galactic_vector(i as f64 * PI / 18.0);
+            assert!((dot(point, point) - 1.0).abs() < 1e-9);
+            assert!(dot(point, pole).abs() < 1e-6);
+        }
+        // Longitude 0 is the galactic center, in Sagittarius
+        let center = radec_vector(266.404_99_f64.to_radians(), (-28.936_17_f64).to_radians());
+        assert!((dot(galactic_vector(0.0), center) - 1.0).abs() < 1e-9);
+    }
+}
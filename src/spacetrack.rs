@@ -0,0 +1,446 @@
+use homedir::my_home;
+use reqwest::header::{COOKIE, SET_COOKIE};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::config::Config;
+
+// https://www.space-track.org/documentation#/api (usage policy)
+const REQUESTS_PER_MINUTE: usize = 30;
+const REQUESTS_PER_HOUR: usize = 300;
+
+#[derive(Debug)]
+pub enum SpaceTrackError {
+    Http(reqwest::Error),
+    MissingCredentials,
+    LoginFailed,
+}
+
+impl From<reqwest::Error> for SpaceTrackError {
+    fn from(err: reqwest::Error) -> Self {
+        SpaceTrackError::Http(err)
+    }
+}
+
+impl std::fmt::Display for SpaceTrackError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SpaceTrackError::Http(err) => write!(f, "{}", err),
+            SpaceTrackError::MissingCredentials => write!(f, "no Space-Track credentials configured"),
+            SpaceTrackError::LoginFailed => write!(f, "Space-Track login failed"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Credentials {
+    pub identity: String,
+    pub password: String,
+}
+
+/// Look for credentials in the config, then in SPACETRACK_IDENTITY/SPACETRACK_PASSWORD,
+/// then in the credentials file (`identity=...` and `password=...` lines)
+pub fn load_credentials(config: &Config) -> Option<Credentials> {
+    if !config.spacetrack_identity.is_empty() && !config.spacetrack_password.is_empty() {
+        return Some(Credentials {
+            identity: config.spacetrack_identity.clone(),
+            password: config.spacetrack_password.clone(),
+        });
+    }
+    if let (Ok(identity), Ok(password)) = (std::env::var("SPACETRACK_IDENTITY"), std::env::var("SPACETRACK_PASSWORD")) {
+        return Some(Credentials { identity, password });
+    }
+    let mut path = my_home().ok()??;
+    path.push(".config/ontake/tasogare");
+    path.push(&config.spacetrack_credentials_file);
+    let contents = std::fs::read_to_string(path).ok()?;
+    let mut identity = None;
+    let mut password = None;
+    for line in contents.lines() {
+        match line.split_once('=') {
+            Some((key, value)) if key.trim() == "identity" => identity = Some(value.trim().to_string()),
+            Some((key, value)) if key.trim() == "password" => password = Some(value.trim().to_string()),
+            _ => (),
+        }
+    }
+    Some(Credentials { identity: identity?, password: password? })
+}
+
+/// Builds `basicspacedata` query paths returning element sets in 3LE format
+#[derive(Debug, Clone, Default)]
+pub struct QueryBuilder {
+    norad_ids: Vec<u64>,
+    epoch_range: Option<(String, String)>,
+}
+
+impl QueryBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn norad_ids(mut self, norad_ids: &[u64]) -> Self {
+        self.norad_ids.extend_from_slice(norad_ids);
+        self
+    }
+
+    /// Dates as understood by Space-Track (e.g. `2024-01-01` or `2024-01-01T12:00:00`).
+    /// Selecting a range queries the full history instead of the latest element sets.
+    pub fn epoch_range(mut self, start: &str, end: &str) -> Self {
+        self.epoch_range = Some((start.to_string(), end.to_string()));
+        self
+    }
+
+    pub fn build(&self) -> String {
+        let ids = self.norad_ids.iter().map(|id| id.to_string()).collect::<Vec<String>>().join(",");
+        match &self.epoch_range {
+            Some((start, end)) => format!(
+                "/basicspacedata/query/class/gp_history/NORAD_CAT_ID/{}/EPOCH/{}--{}/orderby/NORAD_CAT_ID%20asc,EPOCH%20asc/format/3le/emptyresult/show",
+                ids, start, end
+            ),
+            None => format!(
+                "/basicspacedata/query/class/gp/NORAD_CAT_ID/{}/orderby/NORAD_CAT_ID%20asc/format/3le/emptyresult/show",
+                ids
+            ),
+        }
+    }
+}
+
+/// Sliding-window limiter for the per-minute and per-hour request caps
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    requests: VecDeque<Instant>,
+}
+
+impl RateLimiter {
+    /// How long to wait at `now` before the next request, forgetting requests older than an hour
+    fn delay(&mut self, now: Instant) -> Option<Duration> {
+        while self.requests.front().is_some_and(|t| now.duration_since(*t) > Duration::from_secs(3600)) {
+            self.requests.pop_front();
+        }
+        let last_minute = self.requests.iter().filter(|t| now.duration_since(**t) < Duration::from_secs(60)).count();
+        if self.requests.len() >= REQUESTS_PER_HOUR {
+            Some(Duration::from_secs(3600).saturating_sub(now.duration_since(self.requests[0])))
+        } else if last_minute >= REQUESTS_PER_MINUTE {
+            let oldest = self.requests[self.requests.len() - last_minute];
+            Some(Duration::from_secs(60).saturating_sub(now.duration_since(oldest)))
+        } else {
+            None
+        }
+    }
+
+    pub async fn wait(&mut self) {
+        loop {
+            let now = Instant::now();
+            match self.delay(now) {
+                Some(wait) => {
+                    if self.requests.len() >= REQUESTS_PER_HOUR {
+                        println!("Space-Track hourly rate limit reached, waiting {}s", wait.as_secs());
+                    }
+                    tokio::time::sleep(wait).await;
+                }
+                None => {
+                    self.requests.push_back(now);
+                    return;
+                }
+            }
+        }
+    }
+}
+
+pub struct SpaceTrackClient {
+    base_url: String,
+    client: reqwest::Client,
+    cookie: Option<String>,
+    limiter: RateLimiter,
+}
+
+impl SpaceTrackClient {
+    pub fn new(base_url: &str) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            client: reqwest::Client::new(),
+            cookie: None,
+            limiter: RateLimiter::default(),
+        }
+    }
+
+    pub async fn login(&mut self, credentials: &Credentials) -> Result<(), SpaceTrackError> {
+        self.limiter.wait().await;
+        let response = self
+            .client
+            .post(format!("{}/ajaxauth/login", self.base_url))
+            .form(&[("identity", credentials.identity.as_str()), ("password", credentials.password.as_str())])
+            .send()
+            .await?
+            .error_for_status()?;
+        let cookies: Vec<String> = response
+            .headers()
+            .get_all(SET_COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .filter_map(|value| value.split(';').next())
+            .map(|value| value.trim().to_string())
+            .collect();
+        let body = response.text().await?;
+        if cookies.is_empty() || body.contains("\"Login\":\"Failed\"") {
+            return Err(SpaceTrackError::LoginFailed);
+        }
+        self.cookie = Some(cookies.join("; "));
+        Ok(())
+    }
+
+    pub async fn query(&mut self, query: &QueryBuilder) -> Result<String, SpaceTrackError> {
+        let cookie = self.cookie.clone().ok_or(SpaceTrackError::LoginFailed)?;
+        self.limiter.wait().await;
+        let body = self
+            .client
+            .get(format!("{}{}", self.base_url, query.build()))
+            .header(COOKIE, cookie)
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        Ok(body)
+    }
+
+    pub async fn logout(&mut self) -> Result<(), SpaceTrackError> {
+        if let Some(cookie) = self.cookie.take() {
+            self.limiter.wait().await;
+            self.client
+                .get(format!("{}/ajaxauth/logout", self.base_url))
+                .header(COOKIE, cookie)
+                .send()
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+/// Space-Track access for one update run: logs in on the first fetch, then every source shares the
+/// session cookie and the rate limiter
+pub struct Session<'a> {
+    config: &'a Config,
+    client: Option<SpaceTrackClient>,
+    login_failed: bool,
+}
+
+impl<'a> Session<'a> {
+    pub fn new(config: &'a Config) -> Self {
+        Self { config, client: None, login_failed: false }
+    }
+
+    async fn client(&mut self) -> Result<&mut SpaceTrackClient, SpaceTrackError> {
+        if self.login_failed {
+            return Err(SpaceTrackError::LoginFailed);
+        }
+        if self.client.is_none() {
+            let credentials = load_credentials(self.config).ok_or(SpaceTrackError::MissingCredentials)?;
+            let mut client = SpaceTrackClient::new(&self.config.spacetrack_base_url);
+            if let Err(err) = client.login(&credentials).await {
+                // Don't retry for every source, repeated failed logins can lock the account
+                self.login_failed = true;
+                return Err(err);
+            }
+            self.client = Some(client);
+        }
+        Ok(self.client.as_mut().unwrap())
+    }
+
+    /// Fetch the element sets for the given objects, the history between the epochs if both are set
+    pub async fn fetch_tle(&mut self, norad_ids: &[u64], epoch_start: &str, epoch_end: &str) -> Result<String, SpaceTrackError> {
+        let mut query = QueryBuilder::new().norad_ids(norad_ids);
+        match (epoch_start.is_empty(), epoch_end.is_empty()) {
+            (false, false) => query = query.epoch_range(epoch_start, epoch_end),
+            (true, true) => (),
+            _ => eprintln!("Space-Track source needs both epoch_start and epoch_end for a range, fetching the latest element sets"),
+        }
+        println!("Fetching TLE from Space-Track for {} objects", norad_ids.len());
+        self.client().await?.query(&query).await
+    }
+
+    pub async fn logout(mut self) {
+        if let Some(client) = self.client.as_mut() {
+            let _ = client.logout().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    const TLE: &str = "0 ISS (ZARYA)\n1 25544U 98067A   20194.88612269 -.00002218  00000-0 -31515-4 0  9992\n2 25544  51.6461 221.2784 0001413  89.1723 280.4612 15.49507896236008\n";
+
+    fn response(status: &str, headers: &[&str], body: &str) -> String {
+        let mut response = format!("HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n", status, body.len());
+        for header in headers {
+            response.push_str(header);
+            response.push_str("\r\n");
+        }
+        response.push_str("\r\n");
+        response.push_str(body);
+        response
+    }
+
+    fn login_ok() -> String {
+        response("200 OK", &["Set-Cookie: chocolatechip=abc123; path=/; secure"], "\"\"")
+    }
+
+    /// Answer one request per connection with the given responses in order, recording the requests
+    fn serve(responses: Vec<String>) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+        std::thread::spawn(move || {
+            for response in responses {
+                let Ok((mut stream, _)) = listener.accept() else { return };
+                let mut request = Vec::new();
+                let mut buffer = [0; 4096];
+                loop {
+                    let read = stream.read(&mut buffer).unwrap_or(0);
+                    request.extend_from_slice(&buffer[..read]);
+                    let text = String::from_utf8_lossy(&request).to_string();
+                    let Some(header_end) = text.find("\r\n\r\n") else {
+                        if read == 0 {
+                            break;
+                        }
+                        continue;
+                    };
+                    let content_length = text[..header_end]
+                        .lines()
+                        .find_map(|line| line.to_ascii_lowercase().strip_prefix("content-length:").map(|length| length.trim().parse().unwrap_or(0)))
+                        .unwrap_or(0);
+                    if read == 0 || request.len() >= header_end + 4 + content_length {
+                        break;
+                    }
+                }
+                recorded.lock().unwrap().push(String::from_utf8_lossy(&request).to_string());
+                let _ = stream.write_all(response.as_bytes());
+            }
+        });
+        (base_url, requests)
+    }
+
+    fn config(base_url: &str) -> Config {
+        Config {
+            spacetrack_base_url: base_url.to_string(),
+            spacetrack_identity: "user@example.com".to_string(),
+            spacetrack_password: "hunter2".to_string(),
+            ..Config::default()
+        }
+    }
+
+    fn credentials() -> Credentials {
+        Credentials { identity: "user@example.com".to_string(), password: "hunter2".to_string() }
+    }
+
+    #[test]
+    fn builds_latest_and_history_queries() {
+        assert_eq!(
+            QueryBuilder::new().norad_ids(&[25544, 20580]).build(),
+            "/basicspacedata/query/class/gp/NORAD_CAT_ID/25544,20580/orderby/NORAD_CAT_ID%20asc/format/3le/emptyresult/show"
+        );
+        assert_eq!(
+            QueryBuilder::new().norad_ids(&[25544]).norad_ids(&[20580]).epoch_range("2024-01-01", "2024-02-01").build(),
+            "/basicspacedata/query/class/gp_history/NORAD_CAT_ID/25544,20580/EPOCH/2024-01-01--2024-02-01/orderby/NORAD_CAT_ID%20asc,EPOCH%20asc/format/3le/emptyresult/show"
+        );
+    }
+
+    #[tokio::test]
+    async fn login_failure_is_reported() {
+        let (base_url, _) = serve(vec![response("200 OK", &[], "{\"Login\":\"Failed\"}")]);
+        let mut client = SpaceTrackClient::new(&base_url);
+        assert!(matches!(client.login(&credentials()).await, Err(SpaceTrackError::LoginFailed)));
+        assert!(matches!(client.query(&QueryBuilder::new().norad_ids(&[25544])).await, Err(SpaceTrackError::LoginFailed)));
+    }
+
+    #[tokio::test]
+    async fn login_cookie_is_sent_with_queries() {
+        let (base_url, requests) = serve(vec![login_ok(), response("200 OK", &[], TLE)]);
+        let mut client = SpaceTrackClient::new(&base_url);
+        client.login(&credentials()).await.unwrap();
+        let body = client.query(&QueryBuilder::new().norad_ids(&[25544])).await.unwrap();
+        assert_eq!(body, TLE);
+        let requests = requests.lock().unwrap();
+        assert!(requests[0].starts_with("POST /ajaxauth/login"));
+        assert!(requests[0].contains("identity=user%40example.com&password=hunter2"));
+        assert!(requests[1].starts_with("GET /basicspacedata/query/class/gp/NORAD_CAT_ID/25544/"));
+        assert!(requests[1].to_ascii_lowercase().contains("cookie: chocolatechip=abc123\r\n"));
+    }
+
+    #[tokio::test]
+    async fn error_status_fails_the_query() {
+        let (base_url, _) = serve(vec![login_ok(), response("500 Internal Server Error", &[], "")]);
+        let mut client = SpaceTrackClient::new(&base_url);
+        client.login(&credentials()).await.unwrap();
+        assert!(matches!(client.query(&QueryBuilder::new().norad_ids(&[25544])).await, Err(SpaceTrackError::Http(_))));
+    }
+
+    #[tokio::test]
+    async fn session_logs_in_once_for_every_source() {
+        let (base_url, requests) = serve(vec![login_ok(), response("200 OK", &[], TLE), response("200 OK", &[], TLE), response("200 OK", &[], "")]);
+        let config = config(&base_url);
+        let mut session = Session::new(&config);
+        assert_eq!(session.fetch_tle(&[25544], "", "").await.unwrap(), TLE);
+        // Only one end of the range falls back to the latest element sets
+        assert_eq!(session.fetch_tle(&[25544], "2024-01-01", "").await.unwrap(), TLE);
+        session.logout().await;
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.iter().filter(|request| request.starts_with("POST /ajaxauth/login")).count(), 1);
+        assert!(requests[2].starts_with("GET /basicspacedata/query/class/gp/"));
+        assert!(requests[3].starts_with("GET /ajaxauth/logout"));
+    }
+
+    #[tokio::test]
+    async fn session_does_not_retry_a_failed_login() {
+        let (base_url, requests) = serve(vec![response("401 Unauthorized", &[], "")]);
+        let config = config(&base_url);
+        let mut session = Session::new(&config);
+        assert!(matches!(session.fetch_tle(&[25544], "", "").await, Err(SpaceTrackError::Http(_))));
+        assert!(matches!(session.fetch_tle(&[25544], "", "").await, Err(SpaceTrackError::LoginFailed)));
+        assert_eq!(requests.lock().unwrap().len(), 1);
+    }
+
+    fn limiter_with(count: usize, at: Instant) -> RateLimiter {
+        RateLimiter { requests: std::iter::repeat_n(at, count).collect() }
+    }
+
+    #[test]
+    fn limiter_allows_requests_under_the_limits() {
+        let start = Instant::now();
+        let mut limiter = limiter_with(REQUESTS_PER_MINUTE - 1, start);
+        assert_eq!(limiter.delay(start), None);
+    }
+
+    #[test]
+    fn limiter_waits_for_the_minute_window() {
+        let start = Instant::now();
+        let mut limiter = limiter_with(REQUESTS_PER_MINUTE, start);
+        assert_eq!(limiter.delay(start + Duration::from_secs(45)), Some(Duration::from_secs(15)));
+        assert_eq!(limiter.delay(start + Duration::from_secs(60)), None);
+    }
+
+    #[test]
+    fn limiter_waits_for_the_hour_window() {
+        let start = Instant::now();
+        let mut limiter = limiter_with(REQUESTS_PER_HOUR, start);
+        assert_eq!(limiter.delay(start + Duration::from_secs(600)), Some(Duration::from_secs(3000)));
+        assert_eq!(limiter.delay(start + Duration::from_secs(3601)), None);
+        // The expired requests are forgotten
+        assert!(limiter.requests.is_empty());
+    }
+
+    #[tokio::test]
+    async fn limiter_records_each_request() {
+        let mut limiter = RateLimiter::default();
+        for _ in 0..3 {
+            limiter.wait().await;
+        }
+        assert_eq!(limiter.requests.len(), 3);
+    }
+}
@@ -0,0 +1,228 @@
+use bevy::prelude::*;
+use std::collections::HashMap;
+use std::time::SystemTime;
+
+use crate::config::{Config, SatStyle};
+use crate::{clock, gps, groups, hexstr2color, locator, observers, view, Satellite, SatelliteTrail, Star};
+
+/// Display options of one satellite after applying groups and per-satellite overrides
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolvedStyle {
+    pub color: String,
+    pub trail_color: String,
+    pub size: f32,
+    pub label: bool,
+    pub display_name: Option<String>,
+    pub trail_length_seconds: i64,
+}
+
+impl ResolvedStyle {
+    fn apply(&mut self, style: &SatStyle) {
+        if let Some(color) = &style.color {
+            self.color = color.clone();
+        }
+        if let Some(trail_color) = &style.trail_color {
+            self.trail_color = trail_color.clone();
+        }
+        if let Some(size) = style.size {
+            self.size = size;
+        }
+        if let Some(label) = style.label {
+            self.label = label;
+        }
+        if let Some(display_name) = &style.display_name {
+            self.display_name = Some(display_name.clone());
+        }
+        if let Some(trail_length_seconds) = style.trail_length_seconds {
+            self.trail_length_seconds = trail_length_seconds;
+        }
+    }
+}
+
+/// Global defaults, then every matching group in order, then the per-satellite override
+pub fn resolve(config: &Config, norad_id: u64, object_type: Option<&str>) -> ResolvedStyle {
+    let mut resolved = ResolvedStyle {
+        color: config.sat_color.clone(),
+        trail_color: config.sat_trails_color.clone(),
+        size: config.sat_radius,
+        label: true,
+        display_name: None,
+        trail_length_seconds: config.trail_max_length_seconds,
+    };
+    for group in &config.sat_groups {
+        if group.contains(norad_id, object_type) {
+            resolved.apply(&group.style);
+        }
+    }
+    for sat_style in config.sat_styles.iter().filter(|s| s.norad_id == norad_id) {
+        resolved.apply(&sat_style.style);
+    }
+    resolved
+}
+
+/// Name shown for an object: the styled display name, else the alias, else the catalog name
+pub fn display_name(config: &Config, style: &ResolvedStyle, norad_id: u64, object_name: &Option<String>) -> String {
+    match &style.display_name {
+        Some(display_name) => display_name.clone(),
+        None => config.display_name(norad_id, object_name),
+    }
+}
+
+/// One material per satellite color, shared by every satellite drawn in it
+#[derive(Resource, Default)]
+pub struct SatMaterials(HashMap<String, Handle<ColorMaterial>>);
+
+impl SatMaterials {
+    pub fn get(&mut self, color: &String, materials: &mut Assets<ColorMaterial>) -> Handle<ColorMaterial> {
+        self.0.entry(color.clone()).or_insert_with(|| materials.add(hexstr2color(color))).clone()
+    }
+}
+
+/// Settings named like "*color" (groups and per-satellite styles included) that aren't hex colors
+pub fn invalid_colors(config: &Config) -> Vec<String> {
+    fn walk(value: &serde_json::Value, path: &str, invalid: &mut Vec<String>) {
+        match value {
+            serde_json::Value::Object(fields) => {
+                for (key, value) in fields {
+                    let path = if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+                    match value {
+                        serde_json::Value::String(color) if key.ends_with("color") => {
+                            if Srgba::hex(color).is_err() {
+                                invalid.push(path);
+                            }
+                        }
+                        _ => walk(value, &path, invalid),
+                    }
+                }
+            }
+            serde_json::Value::Array(items) => {
+                for (i, item) in items.iter().enumerate() {
+                    walk(item, &format!("{}[{}]", path, i), invalid);
+                }
+            }
+            _ => (),
+        }
+    }
+    let mut invalid = Vec::new();
+    if let Ok(value) = serde_json::to_value(config) {
+        walk(&value, "", &mut invalid);
+    }
+    invalid
+}
+
+/// Watches the config file and re-applies it when it changes, to the Config resource and every copy of it
+pub fn reload_config_styles(
+    mut last_modified: Local<Option<SystemTime>>,
+    mut timer: Local<Option<Timer>>,
+    time: Res<Time>,
+    mut sat_query: Query<(&mut Satellite, &mut MeshMaterial2d<ColorMaterial>, &mut Mesh2d)>,
+    mut trail_query: Query<&mut SatelliteTrail>,
+    mut star_query: Query<&mut Star>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut sat_materials: ResMut<SatMaterials>,
+    mut generation: ResMut<clock::TrackGeneration>,
+    mut visibility: ResMut<groups::GroupVisibility>,
+    active_observer: Res<observers::ActiveObserver>,
+    receiver: Res<gps::GpsReceiver>,
+    mut view: ResMut<view::ViewState>,
+    mut config: ResMut<Config>,
+) {
+    let timer = timer.get_or_insert_with(|| Timer::from_seconds(1.0, TimerMode::Repeating));
+    if !timer.tick(time.delta()).just_finished() {
+        return;
+    }
+    let modified = confy::get_configuration_file_path("ontake/tasogare", "config")
+        .ok()
+        .and_then(|path| std::fs::metadata(path).ok())
+        .and_then(|metadata| metadata.modified().ok());
+    if modified.is_none() || *last_modified == modified {
+        return;
+    }
+    let first_check = last_modified.is_none();
+    *last_modified = modified;
+    if first_check {
+        return;
+    }
+    let mut loaded_config: Config = match confy::load("ontake/tasogare", "config") {
+        Ok(config) => config,
+        Err(_) => {
+            eprintln!("Couldn't reload config, keeping the current one");
+            return;
+        }
+    };
+    let invalid = invalid_colors(&loaded_config);
+    if !invalid.is_empty() {
+        eprintln!("Invalid colors in the config ({}), keeping the current one", invalid.join(", "));
+        return;
+    }
+    println!("Config changed, reloading satellite styles");
+    groups::apply_membership(&mut loaded_config);
+    locator::apply_user_location(&mut loaded_config);
+    observers::apply(&mut loaded_config, &active_observer.name);
+    locator::apply_override(&mut loaded_config);
+    // An observer following the GPS stays where the last fix put it
+    if gps::follows_gps(&loaded_config) && receiver.latest.lock().unwrap().is_some() {
+        let current = &view.loaded_config;
+        loaded_config.move_observer(current.user_latitude, current.user_longitude, current.user_altitude);
+    }
+    visibility.groups = loaded_config.sat_groups.clone();
+
+    for (mut sat, mut material, mut mesh) in &mut sat_query {
+        let object_type = sat.satcat.as_ref().map(|entry| entry.object_type.clone());
+        let style = resolve(&loaded_config, sat.norad_id, object_type.as_deref());
+        if style.color != sat.style.color {
+            material.0 = sat_materials.get(&style.color, &mut materials);
+        }
+        if style.size != sat.style.size {
+            mesh.0 = meshes.add(Circle::new(style.size));
+        }
+        sat.name = display_name(&loaded_config, &style, sat.norad_id, &sat.elements.object_name);
+        sat.style = style;
+        sat.loaded_config = loaded_config.clone();
+    }
+    for mut trail in &mut trail_query {
+        let object_type = trail.object_type.clone();
+        let style = resolve(&loaded_config, trail.norad_id, object_type.as_deref());
+        trail.color = hexstr2color(&style.trail_color);
+        trail.name = display_name(&loaded_config, &style, trail.norad_id, &trail.elements.object_name);
+        trail.style = style;
+        trail.loaded_config = loaded_config.clone();
+    }
+    for mut star in &mut star_query {
+        star.loaded_config = loaded_config.clone();
+    }
+    view.loaded_config = loaded_config.clone();
+    *config = loaded_config;
+    // Trail lengths and the observer may have changed as well
+    generation.0 += 1;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{SatGroup, SatStyleOverride};
+
+    #[test]
+    fn default_colors_are_valid() {
+        assert!(invalid_colors(&Config::default()).is_empty());
+    }
+
+    #[test]
+    fn reports_invalid_colors_by_name() {
+        let config = Config {
+            sat_color: "#GGGGGG".to_string(),
+            sat_groups: vec![SatGroup {
+                name: "stations".to_string(),
+                norad_ids: vec![25544],
+                object_types: Vec::new(),
+                sources: Vec::new(),
+                hidden: false,
+                style: SatStyle { trail_color: Some("blue-ish".to_string()), ..Default::default() },
+            }],
+            sat_styles: vec![SatStyleOverride { norad_id: 25544, style: SatStyle { color: Some("#FF0000".to_string()), ..Default::default() } }],
+            ..Default::default()
+        };
+        assert_eq!(invalid_colors(&config), vec!["sat_color", "sat_groups[0].style.trail_color"]);
+    }
+}
@@ -0,0 +1,100 @@
+use chrono::{DateTime, Utc};
+
+use crate::gst_from_datetime;
+
+/// Right ascension and declination (radians) of the Sun, using the low precision
+/// formulae of the Astronomical Almanac (good to about 0.01° between 1950 and 2050)
+pub fn equatorial_position(date: DateTime<Utc>) -> (f64, f64) {
+    let jd = date.timestamp_millis() as f64 / 86_400_000.0 + 2_440_587.5;
+    let n = jd - 2_451_545.0;
+    let mean_longitude = (280.460 + 0.9856474 * n).to_radians();
+    let mean_anomaly = (357.528 + 0.9856003 * n).to_radians();
+    let ecliptic_longitude = mean_longitude + (1.915f64.to_radians() * mean_anomaly.sin()) + (0.020f64.to_radians() * (2.0 * mean_anomaly).sin());
+    let obliquity = (23.439 - 0.0000004 * n).to_radians();
+    let ra = (obliquity.cos() * ecliptic_longitude.sin()).atan2(ecliptic_longitude.cos());
+    let dec = (obliquity.sin() * ecliptic_longitude.sin()).asin();
+    (ra.rem_euclid(2.0 * std::f64::consts::PI), dec)
+}
+
+/// Latitude and longitude (radians) of the point where the Sun is at the zenith
+pub fn subsolar_point(date: DateTime<Utc>) -> (f64, f64) {
+    let (ra, dec) = equatorial_position(date);
+    let lon = (ra - gst_from_datetime(date) + std::f64::consts::PI).rem_euclid(2.0 * std::f64::consts::PI) - std::f64::consts::PI;
+    (dec, lon)
+}
+
+/// Elevation (radians) of the Sun seen from a latitude and longitude (radians), without refraction
+pub fn elevation(date: DateTime<Utc>, lat: f64, lon: f64) -> f64 {
+    let (ra, dec) = equatorial_position(date);
+    let hour_angle = gst_from_datetime(date) + lon - ra;
+    (lat.sin() * dec.sin() + lat.cos() * dec.cos() * hour_angle.cos()).asin()
+}
+
+/// Day, civil, nautical or astronomical twilight, or night for a Sun elevation (radians)
+pub fn twilight_phase(elevation: f64) -> &'static str {
+    match elevation.to_degrees() {
+        el if el > -0.833 => "day",
+        el if el > -6.0 => "civil twilight",
+        el if el > -12.0 => "nautical twilight",
+        el if el > -18.0 => "astronomical twilight",
+        _ => "night",
+    }
+}
+
+/// Latitude (radians) of the day/night terminator at a longitude (radians)
+pub fn terminator_latitude(subsolar: (f64, f64), lon: f64) -> f64 {
+    let (dec, subsolar_lon) = subsolar;
+    // At the equinoxes the terminator runs along two meridians, keep the division finite
+    let dec = if dec.abs() < 1e-4 { 1e-4_f64.copysign(dec) } else { dec };
+    (-(lon - subsolar_lon).cos() / dec.tan()).atan()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn names_twilight_phases() {
+        let phase = |degrees: f64| twilight_phase(degrees.to_radians());
+        assert_eq!(phase(45.0), "day");
+        assert_eq!(phase(-0.5), "day");
+        assert_eq!(phase(-0.833), "civil twilight");
+        assert_eq!(phase(-3.0), "civil twilight");
+        assert_eq!(phase(-6.0), "nautical twilight");
+        assert_eq!(phase(-9.0), "nautical twilight");
+        assert_eq!(phase(-12.0), "astronomical twilight");
+        assert_eq!(phase(-15.0), "astronomical twilight");
+        assert_eq!(phase(-18.0), "night");
+        assert_eq!(phase(-90.0), "night");
+    }
+
+    #[test]
+    fn declination_follows_the_seasons() {
+        let (_, dec) = equatorial_position(Utc.with_ymd_and_hms(2024, 6, 20, 20, 51, 0).unwrap());
+        assert!((dec.to_degrees() - 23.44).abs() < 0.02);
+        let (ra, dec) = equatorial_position(Utc.with_ymd_and_hms(2024, 3, 20, 3, 6, 0).unwrap());
+        assert!(dec.to_degrees().abs() < 0.02);
+        assert!(ra.min(2.0 * std::f64::consts::PI - ra).to_degrees() < 0.05);
+    }
+
+    #[test]
+    fn sun_is_at_the_zenith_of_the_subsolar_point() {
+        let date = Utc.with_ymd_and_hms(2024, 9, 1, 14, 30, 0).unwrap();
+        let (lat, lon) = subsolar_point(date);
+        assert!((elevation(date, lat, lon).to_degrees() - 90.0).abs() < 0.01);
+        let antipode = (-lat, lon + std::f64::consts::PI);
+        assert!((elevation(date, antipode.0, antipode.1).to_degrees() + 90.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn terminator_is_90_degrees_from_the_subsolar_point() {
+        let subsolar = (0.3, 0.5);
+        for lon in [-2.0, -0.5, 0.5, 1.0, 3.0] {
+            let lat: f64 = terminator_latitude(subsolar, lon);
+            let cos_distance = lat.sin() * subsolar.0.sin() + lat.cos() * subsolar.0.cos() * (lon - subsolar.1).cos();
+            assert!(cos_distance.abs() < 1e-9);
+        }
+        assert!(terminator_latitude((0.0, 0.0), 1.0).is_finite());
+    }
+}
@@ -0,0 +1,214 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{LazyLock, Mutex, OnceLock};
+
+use crate::config::Config;
+
+/// What configured altitudes are measured from
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AltitudeReference {
+    /// Height above mean sea level (the EGM96 geoid), like maps, GPS displays and SRTM tiles give it
+    MeanSeaLevel,
+    /// Height above the WGS84 ellipsoid, like the raw output of a GPS receiver
+    Ellipsoid,
+}
+
+/// The EGM96 15' geoid grid of WW15MGH.DAC: 721 rows from 90°N to 90°S of 1440 columns from 0°E eastwards
+struct Geoid {
+    undulations: Vec<i16>, // centimeters
+}
+
+const GEOID_ROWS: usize = 721;
+const GEOID_COLUMNS: usize = 1440;
+const GEOID_STEP: f64 = 0.25; // degrees
+
+static GEOID: OnceLock<Option<Geoid>> = OnceLock::new();
+
+/// SRTM tiles read so far, by the latitude and longitude of their south west corner, None when missing
+type TileCache = HashMap<(i32, i32), Option<HgtTile>>;
+
+static TILES: LazyLock<Mutex<TileCache>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+
+fn read_big_endian_i16(bytes: &[u8]) -> Vec<i16> {
+    bytes.chunks_exact(2).map(|pair| i16::from_be_bytes([pair[0], pair[1]])).collect()
+}
+
+fn load_geoid(path: &str) -> Option<Geoid> {
+    let Some(resolved) = crate::config::resolve_data_path(path) else {
+        eprintln!(
+            "EGM96 geoid {} not found (get the NGA WW15MGH.DAC grid or set geoid_path), mean sea level heights will be used as ellipsoidal heights, up to about 100 m off",
+            path
+        );
+        return None;
+    };
+    let bytes = match std::fs::read(&resolved) {
+        Ok(bytes) => bytes,
+        Err(_) => {
+            eprintln!("Couldn't read the EGM96 geoid from {}, mean sea level heights will be used as ellipsoidal heights", resolved.display());
+            return None;
+        }
+    };
+    Geoid::from_bytes(&bytes).or_else(|| {
+        eprintln!("{} isn't a WW15MGH.DAC geoid grid", resolved.display());
+        None
+    })
+}
+
+impl Geoid {
+    fn from_bytes(bytes: &[u8]) -> Option<Geoid> {
+        (bytes.len() == GEOID_ROWS * GEOID_COLUMNS * 2).then(|| Geoid { undulations: read_big_endian_i16(bytes) })
+    }
+
+    /// Bilinear interpolation of the grid, wrapping around in longitude
+    fn undulation(&self, latitude: f64, longitude: f64) -> f64 {
+        let row = ((90.0 - latitude) / GEOID_STEP).clamp(0.0, (GEOID_ROWS - 1) as f64);
+        let column = longitude.rem_euclid(360.0) / GEOID_STEP;
+        let (row0, column0) = (row.floor() as usize, column.floor() as usize);
+        let (row1, column1) = ((row0 + 1).min(GEOID_ROWS - 1), (column0 + 1) % GEOID_COLUMNS);
+        let at = |row: usize, column: usize| self.undulations[row * GEOID_COLUMNS + column % GEOID_COLUMNS] as f64 / 100.0;
+        let (dr, dc) = (row - row0 as f64, column - column0 as f64);
+        let top = at(row0, column0) * (1.0 - dc) + at(row0, column1) * dc;
+        let bottom = at(row1, column0) * (1.0 - dc) + at(row1, column1) * dc;
+        top * (1.0 - dr) + bottom * dr
+    }
+}
+
+/// Height of the EGM96 geoid above the WGS84 ellipsoid (m) at a latitude and longitude (degrees), 0 without the grid
+pub fn geoid_undulation(latitude: f64, longitude: f64, loaded_config: &Config) -> f64 {
+    match GEOID.get_or_init(|| load_geoid(&loaded_config.geoid_path)) {
+        Some(geoid) => geoid.undulation(latitude, longitude),
+        None => 0.0,
+    }
+}
+
+/// A 1°×1° SRTM tile, rows from north to south, heights in meters above mean sea level
+struct HgtTile {
+    size: usize, // 1201 for 3" tiles, 3601 for 1" tiles
+    heights: Vec<i16>,
+}
+
+const HGT_VOID: i16 = -32768;
+
+/// Tile name of the SRTM convention, e.g. N48E002.hgt for the tile whose south west corner is 48°N 2°E
+fn hgt_name(lat: i32, lon: i32) -> String {
+    format!("{}{:02}{}{:03}.hgt", if lat >= 0 { 'N' } else { 'S' }, lat.abs(), if lon >= 0 { 'E' } else { 'W' }, lon.abs())
+}
+
+fn load_tile(directory: &str, lat: i32, lon: i32) -> Option<HgtTile> {
+    let path = Path::new(directory).join(hgt_name(lat, lon));
+    let bytes = std::fs::read(&path).ok()?;
+    let size = ((bytes.len() / 2) as f64).sqrt() as usize;
+    if size < 2 || size * size * 2 != bytes.len() {
+        eprintln!("{} isn't an SRTM tile", path.display());
+        return None;
+    }
+    Some(HgtTile { size, heights: read_big_endian_i16(&bytes) })
+}
+
+/// Terrain height above mean sea level (m) from the SRTM tiles in `terrain_tiles_path`, None without a tile or data
+pub fn terrain_height(latitude: f64, longitude: f64, loaded_config: &Config) -> Option<f64> {
+    if loaded_config.terrain_tiles_path.is_empty() {
+        return None;
+    }
+    let (lat, lon) = (latitude.floor() as i32, longitude.floor() as i32);
+    let mut tiles = TILES.lock().unwrap();
+    let tile = tiles.entry((lat, lon)).or_insert_with(|| load_tile(&loaded_config.terrain_tiles_path, lat, lon)).as_ref()?;
+    let last = (tile.size - 1) as f64;
+    let row = (lat as f64 + 1.0 - latitude) * last;
+    let column = (longitude - lon as f64) * last;
+    let (row0, column0) = (row.floor() as usize, column.floor() as usize);
+    let (row1, column1) = ((row0 + 1).min(tile.size - 1), (column0 + 1).min(tile.size - 1));
+    let (dr, dc) = (row - row0 as f64, column - column0 as f64);
+    let corners = [(row0, column0, (1.0 - dr) * (1.0 - dc)), (row0, column1, (1.0 - dr) * dc), (row1, column0, dr * (1.0 - dc)), (row1, column1, dr * dc)];
+    // Skip voids and weigh the remaining samples back to 1
+    let (sum, weight) = corners
+        .iter()
+        .map(|(row, column, weight)| (tile.heights[row * tile.size + column], *weight))
+        .filter(|(height, _)| *height != HGT_VOID)
+        .fold((0.0, 0.0), |(sum, total), (height, weight)| (sum + height as f64 * weight, total + weight));
+    (weight > 0.0).then_some(sum / weight)
+}
+
+/// Convert a height above mean sea level to the configured altitude reference
+pub fn from_mean_sea_level(latitude: f64, longitude: f64, altitude: f64, loaded_config: &Config) -> f64 {
+    match loaded_config.altitude_reference {
+        AltitudeReference::MeanSeaLevel => altitude,
+        AltitudeReference::Ellipsoid => altitude + geoid_undulation(latitude, longitude, loaded_config),
+    }
+}
+
+/// Height above the WGS84 ellipsoid (m) of a place, what `eci2aer` and friends expect: the terrain
+/// plus `height_above_terrain_meters` where tiles cover it, otherwise the configured altitude
+pub fn ellipsoidal_height(latitude: f64, longitude: f64, altitude: f64, loaded_config: &Config) -> f64 {
+    if let Some(terrain) = terrain_height(latitude, longitude, loaded_config) {
+        return terrain + loaded_config.height_above_terrain_meters + geoid_undulation(latitude, longitude, loaded_config);
+    }
+    match loaded_config.altitude_reference {
+        AltitudeReference::MeanSeaLevel => altitude + geoid_undulation(latitude, longitude, loaded_config),
+        AltitudeReference::Ellipsoid => altitude,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A grid whose undulation is (10 × row + column) cm, which bilinear interpolation reproduces exactly
+    fn linear_geoid() -> Geoid {
+        let bytes: Vec<u8> = (0..GEOID_ROWS)
+            .flat_map(|row| (0..GEOID_COLUMNS).map(move |column| (row * 10 + column) as i16))
+            .flat_map(|centimeters| centimeters.to_be_bytes())
+            .collect();
+        Geoid::from_bytes(&bytes).unwrap()
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn reads_big_endian_centimeters() {
+        assert_eq!(read_big_endian_i16(&[0x01, 0x02, 0xff, 0xfe, 0x00]), vec![258, -2]);
+        assert!(Geoid::from_bytes(&[0; 16]).is_none());
+    }
+
+    #[test]
+    fn matches_the_grid_nodes() {
+        let geoid = linear_geoid();
+        assert!(close(geoid.undulation(90.0, 0.0), 0.0));
+        assert!(close(geoid.undulation(89.75, 0.25), 0.11));
+        assert!(close(geoid.undulation(0.0, 180.0), 36.0 + 7.2));
+        assert!(close(geoid.undulation(-90.0, 0.0), 72.0));
+    }
+
+    #[test]
+    fn interpolates_between_nodes() {
+        let geoid = linear_geoid();
+        assert!(close(geoid.undulation(89.875, 0.125), 0.055));
+        assert!(close(geoid.undulation(45.1, 10.05), (44.9 / 0.25 * 10.0 + 10.05 / 0.25) / 100.0));
+    }
+
+    #[test]
+    fn wraps_around_in_longitude() {
+        let geoid = linear_geoid();
+        assert!(close(geoid.undulation(90.0, -0.25), 14.39));
+        assert!(close(geoid.undulation(90.0, 359.75), 14.39));
+        // Halfway between the last column and the first one
+        assert!(close(geoid.undulation(90.0, 359.875), 14.39 / 2.0));
+        assert!(close(geoid.undulation(90.0, -180.0), geoid.undulation(90.0, 180.0)));
+    }
+
+    #[test]
+    fn clamps_latitudes_to_the_poles() {
+        let geoid = linear_geoid();
+        assert!(close(geoid.undulation(91.0, 0.0), 0.0));
+        assert!(close(geoid.undulation(-91.0, 0.0), 72.0));
+    }
+
+    #[test]
+    fn names_hgt_tiles() {
+        assert_eq!(hgt_name(48, 2), "N48E002.hgt");
+        assert_eq!(hgt_name(-34, -71), "S34W071.hgt");
+    }
+}
@@ -0,0 +1,187 @@
+use chrono::{DateTime, Utc};
+use homedir::my_home;
+use std::path::PathBuf;
+
+use crate::config::Config;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EpochAgeStatus {
+    Fresh,
+    Stale,
+    Expired,
+}
+
+pub fn tle_path() -> PathBuf {
+    let mut path = my_home().unwrap().expect("couldn't get home directory");
+    path.push(".config/ontake/tasogare/TLEDATA");
+    path
+}
+
+/// Parse every element set stored in TLEDATA (3 lines per object)
+pub fn load_elements() -> Vec<sgp4::Elements> {
+    let tle_content = std::fs::read_to_string(tle_path().as_path()).expect("couldn't read input TLE");
+    parse_tle(&tle_content)
+}
+
+/// Split a 3LE/TLE listing into (name, line 1, line 2) triples
+pub fn split_tle(tle_content: &str) -> Vec<(String, String, String)> {
+    let tle_lines: Vec<&str> = tle_content.lines().filter(|line| !line.trim().is_empty()).collect();
+    let mut sets = Vec::new();
+    for i in (0..tle_lines.len().saturating_sub(2)).step_by(3) {
+        let name = tle_lines[i].trim().trim_start_matches("0 ").to_string();
+        sets.push((name, tle_lines[i + 1].trim_end().to_string(), tle_lines[i + 2].trim_end().to_string()));
+    }
+    sets
+}
+
+/// Catalog number from columns 3-7 of the first TLE line
+pub fn norad_id_from_line1(line1: &str) -> Option<u64> {
+    line1.get(2..7)?.trim().parse().ok()
+}
+
+pub fn parse_tle(tle_content: &str) -> Vec<sgp4::Elements> {
+    let mut elements = Vec::new();
+    for (name, line1, line2) in split_tle(tle_content) {
+        match sgp4::Elements::from_tle(Some(name.clone()), line1.as_bytes(), line2.as_bytes()) {
+            Ok(parsed) => elements.push(parsed),
+            Err(_) => eprintln!("Couldn't parse TLE for {}", name),
+        }
+    }
+    elements
+}
+
+/// Keep only the most recent element set of each object, in order of first appearance
+pub fn latest_per_object(elements: Vec<sgp4::Elements>) -> Vec<sgp4::Elements> {
+    let mut latest_elements: Vec<sgp4::Elements> = Vec::new();
+    for elements in elements {
+        match latest_elements.iter_mut().find(|e| e.norad_id == elements.norad_id) {
+            Some(latest) => if elements.datetime > latest.datetime { *latest = elements },
+            None => latest_elements.push(elements),
+        }
+    }
+    latest_elements
+}
+
+/// Age of the element set relative to `now`, in days
+pub fn epoch_age_days(elements: &sgp4::Elements, now: DateTime<Utc>) -> f64 {
+    now.naive_utc().signed_duration_since(elements.datetime).num_seconds() as f64 / 86400.0
+}
+
+pub fn epoch_age_status(age_days: f64, config: &Config) -> EpochAgeStatus {
+    if age_days >= config.tle_epoch_expired_age_days {
+        EpochAgeStatus::Expired
+    } else if age_days >= config.tle_epoch_stale_age_days {
+        EpochAgeStatus::Stale
+    } else {
+        EpochAgeStatus::Fresh
+    }
+}
+
+/// Whether passes should no longer be predicted from this element set
+pub fn refuse_prediction(elements: &sgp4::Elements, now: DateTime<Utc>, config: &Config) -> bool {
+    config.tle_refuse_stale_predictions && epoch_age_days(elements, now) > config.tle_max_prediction_age_days
+}
+
+/// Print a warning for every loaded element set older than the configured thresholds
+pub fn report_epoch_ages(config: &Config) {
+    if !tle_path().as_path().exists() {
+        return;
+    }
+    let now = Utc::now();
+    for elements in latest_per_object(load_elements()) {
+        let age = epoch_age_days(&elements, now);
+        let name = config.display_name(elements.norad_id, &elements.object_name);
+        match epoch_age_status(age, config) {
+            EpochAgeStatus::Fresh => (),
+            EpochAgeStatus::Stale => println!("Warning: TLE for {} is {:.1} days old", name, age),
+            EpochAgeStatus::Expired => println!("Warning: TLE for {} is {:.1} days old and should not be trusted", name, age),
+        }
+        if refuse_prediction(&elements, now, config) {
+            println!("Not predicting passes for {} (TLE older than {} days)", name, config.tle_max_prediction_age_days);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ISS: &str = "ISS (ZARYA)
+1 25544U 98067A   20194.88612269 -.00002218  00000-0 -31515-4 0  9992
+2 25544  51.6461 221.2784 0001413  89.1723 280.4612 15.49507896236008
+";
+
+    fn date(date: &str) -> DateTime<Utc> {
+        date.parse().unwrap()
+    }
+
+    #[test]
+    fn splits_3le_and_tle_with_name_prefix() {
+        let listing = format!("0 {}\n\n{}", ISS, ISS);
+        let sets = split_tle(&listing);
+        assert_eq!(sets.len(), 2);
+        assert_eq!(sets[0].0, "ISS (ZARYA)");
+        assert_eq!(sets[1].0, "ISS (ZARYA)");
+        assert!(sets[0].1.starts_with("1 25544U"));
+        assert!(sets[0].2.starts_with("2 25544"));
+    }
+
+    #[test]
+    fn ignores_incomplete_trailing_set() {
+        let listing = format!("{}ISS (ZARYA)\n1 25544U 98067A", ISS);
+        assert_eq!(split_tle(&listing).len(), 1);
+    }
+
+    #[test]
+    fn reads_norad_id() {
+        assert_eq!(norad_id_from_line1("1 25544U 98067A   20194.88612269"), Some(25544));
+        assert_eq!(norad_id_from_line1("1 "), None);
+    }
+
+    #[test]
+    fn parses_elements_and_skips_bad_checksums() {
+        let broken = ISS.replace("0  9992", "0  9993");
+        let elements = parse_tle(&format!("{}{}", ISS, broken));
+        assert_eq!(elements.len(), 1);
+        assert_eq!(elements[0].norad_id, 25544);
+        assert_eq!(elements[0].object_name.as_deref(), Some("ISS (ZARYA)"));
+    }
+
+    #[test]
+    fn keeps_latest_element_set_per_object() {
+        let newer = "ISS (ZARYA)
+1 25544U 98067A   20195.50000000 -.00002218  00000-0 -31515-4 0  9996
+2 25544  51.6461 221.2784 0001413  89.1723 280.4612 15.49507896236008
+";
+        let elements = latest_per_object(parse_tle(&format!("{}{}", newer, ISS)));
+        assert_eq!(elements.len(), 1);
+        assert!((epoch_age_days(&elements[0], date("2020-07-13T12:00:00Z"))).abs() < 1e-4);
+    }
+
+    #[test]
+    fn epoch_age_in_days() {
+        let elements = &parse_tle(ISS)[0];
+        // Epoch 2020-07-12T21:16:01Z
+        assert!((epoch_age_days(elements, date("2020-07-22T21:16:01Z")) - 10.0).abs() < 1e-4);
+        assert!(epoch_age_days(elements, date("2020-07-11T21:16:01Z")) < 0.0);
+    }
+
+    #[test]
+    fn epoch_age_thresholds() {
+        let config = Config::default();
+        assert_eq!(epoch_age_status(1.0, &config), EpochAgeStatus::Fresh);
+        assert_eq!(epoch_age_status(config.tle_epoch_stale_age_days, &config), EpochAgeStatus::Stale);
+        assert_eq!(epoch_age_status(config.tle_epoch_expired_age_days + 1.0, &config), EpochAgeStatus::Expired);
+    }
+
+    #[test]
+    fn refuses_old_predictions_only_when_enabled() {
+        let elements = &parse_tle(ISS)[0];
+        let now = date("2020-08-12T00:00:00Z");
+        let mut config = Config::default();
+        assert!(!refuse_prediction(elements, now, &config));
+        config.tle_refuse_stale_predictions = true;
+        assert!(refuse_prediction(elements, now, &config));
+        assert!(!refuse_prediction(elements, date("2020-07-13T00:00:00Z"), &config));
+    }
+}
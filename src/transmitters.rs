@@ -0,0 +1,134 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+
+use crate::cache;
+use crate::config::Config;
+
+const SPEED_OF_LIGHT: f64 = 299_792_458.0;
+
+/// One entry of the SatNOGS DB transmitters list (https://db.satnogs.org/api/)
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Transmitter {
+    pub uuid: String,
+    pub description: String,
+    pub alive: bool,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub uplink_low: Option<u64>,
+    pub uplink_high: Option<u64>,
+    pub downlink_low: Option<u64>,
+    pub downlink_high: Option<u64>,
+    pub mode: Option<String>,
+    pub baud: Option<f64>,
+    pub norad_cat_id: Option<u64>,
+    pub status: String,
+}
+
+impl Transmitter {
+    pub fn is_active(&self) -> bool {
+        self.alive && self.status == "active"
+    }
+
+    pub fn describe(&self) -> String {
+        let mut description = match self.downlink_low {
+            Some(downlink) => format!("{:.3} MHz", downlink as f64 / 1e6),
+            None => "no downlink".to_string(),
+        };
+        if let Some(mode) = &self.mode {
+            description.push_str(&format!(" {}", mode));
+        }
+        if let Some(baud) = self.baud {
+            description.push_str(&format!(" {}bd", baud));
+        }
+        if !self.description.is_empty() {
+            description.push_str(&format!(" ({})", self.description));
+        }
+        description
+    }
+}
+
+/// Download the transmitters list to the local cache if the source is a URL and the cache is outdated
+pub async fn update_transmitters(config: &Config) {
+    let valid = |body: &str| serde_json::from_str::<Vec<Transmitter>>(body).is_ok();
+    cache::refresh(&config.transmitters_source, "TRANSMITTERS", config.transmitters_update_interval_seconds, valid).await;
+}
+
+/// Active transmitters keyed by NORAD ID, empty if no source is configured or available
+pub fn load_transmitters(config: &Config) -> HashMap<u64, Vec<Transmitter>> {
+    if config.transmitters_source.is_empty() {
+        return HashMap::new();
+    }
+    let path = cache::source_path(&config.transmitters_source, "TRANSMITTERS");
+    let transmitters: Vec<Transmitter> = match fs::read_to_string(&path).map(|contents| serde_json::from_str(&contents)) {
+        Ok(Ok(transmitters)) => transmitters,
+        _ => {
+            eprintln!("Couldn't read transmitters from {}", path.display());
+            return HashMap::new();
+        }
+    };
+    let mut by_norad_id: HashMap<u64, Vec<Transmitter>> = HashMap::new();
+    for transmitter in transmitters {
+        if let (Some(norad_id), true) = (transmitter.norad_cat_id, transmitter.is_active()) {
+            by_norad_id.entry(norad_id).or_default().push(transmitter);
+        }
+    }
+    by_norad_id
+}
+
+/// Downlink frequency used for radio calculations: the configured override if any,
+/// otherwise the first active SatNOGS transmitter with a downlink
+pub fn downlink_frequency_hz(norad_id: u64, transmitters: &[Transmitter], config: &Config) -> Option<u64> {
+    match config.sat_downlink_frequencies.iter().find(|f| f.norad_id == norad_id) {
+        Some(frequency) => Some(frequency.downlink_hz),
+        None => transmitters.iter().find_map(|t| t.downlink_low),
+    }
+}
+
+/// Doppler shift in Hz for a transmitter at `frequency_hz` moving away at `range_rate` m/s
+pub fn doppler_shift_hz(frequency_hz: u64, range_rate: f64) -> f64 {
+    -(frequency_hz as f64) * range_rate / SPEED_OF_LIGHT
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SatFrequency;
+
+    fn transmitter(downlink_low: Option<u64>) -> Transmitter {
+        Transmitter { alive: true, status: "active".to_string(), downlink_low, norad_cat_id: Some(25544), ..Default::default() }
+    }
+
+    #[test]
+    fn receding_satellite_is_heard_lower() {
+        let shift = doppler_shift_hz(437_800_000, 7000.0);
+        assert!((shift + 437_800_000.0 * 7000.0 / SPEED_OF_LIGHT).abs() < 1e-6);
+        assert!((shift + 10_222.4).abs() < 0.1);
+    }
+
+    #[test]
+    fn approaching_satellite_is_heard_higher() {
+        assert!(doppler_shift_hz(145_800_000, -7000.0) > 0.0);
+        assert_eq!(doppler_shift_hz(145_800_000, 0.0), 0.0);
+        assert_eq!(doppler_shift_hz(145_800_000, 3000.0), -doppler_shift_hz(145_800_000, -3000.0));
+    }
+
+    #[test]
+    fn configured_downlink_overrides_satnogs() {
+        let mut config = Config::default();
+        let transmitters = vec![transmitter(None), transmitter(Some(145_800_000)), transmitter(Some(437_800_000))];
+        assert_eq!(downlink_frequency_hz(25544, &transmitters, &config), Some(145_800_000));
+        config.sat_downlink_frequencies.push(SatFrequency { norad_id: 25544, downlink_hz: 437_525_000 });
+        assert_eq!(downlink_frequency_hz(25544, &transmitters, &config), Some(437_525_000));
+        assert_eq!(downlink_frequency_hz(43017, &[], &config), None);
+    }
+
+    #[test]
+    fn parses_satnogs_transmitters() {
+        let json = r#"[{"uuid":"abc","description":"Mode V FM","alive":true,"type":"Transmitter","downlink_low":145800000,"mode":"FM","baud":null,"norad_cat_id":25544,"status":"active","service":"Amateur"}]"#;
+        let transmitters: Vec<Transmitter> = serde_json::from_str(json).unwrap();
+        assert!(transmitters[0].is_active());
+        assert_eq!(transmitters[0].describe(), "145.800 MHz FM (Mode V FM)");
+    }
+}
@@ -0,0 +1,317 @@
+use bevy::{
+    input::mouse::{MouseScrollUnit, MouseWheel},
+    math::vec2,
+    prelude::*,
+    window::PrimaryWindow,
+};
+
+use crate::config::Config;
+use crate::{selection, Satellite, Shape, Star};
+
+/// Cursor travel (pixels) after which a left click becomes a pan
+pub const DRAG_THRESHOLD: f32 = 4.0;
+
+/// What the scene shows, switched with the keymap
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ViewMode {
+    /// The local sky dome
+    #[default]
+    Dome,
+    /// Sub-satellite points and ground tracks on a world map
+    WorldMap,
+    /// Earth and orbits in 3D
+    Globe,
+}
+
+impl ViewMode {
+    pub fn next(&self) -> Self {
+        match self {
+            ViewMode::Dome => ViewMode::WorldMap,
+            ViewMode::WorldMap => ViewMode::Globe,
+            ViewMode::Globe => ViewMode::Dome,
+        }
+    }
+}
+
+/// Run condition for systems that only make sense on the sky dome
+pub fn in_dome(mode: Res<ViewMode>) -> bool {
+    *mode == ViewMode::Dome
+}
+
+/// Run condition for the world map systems
+pub fn in_world_map(mode: Res<ViewMode>) -> bool {
+    *mode == ViewMode::WorldMap
+}
+
+/// Run condition for the 3D globe systems
+pub fn in_globe(mode: Res<ViewMode>) -> bool {
+    *mode == ViewMode::Globe
+}
+
+/// Runtime state of the sky view, changed through the keymap and the mouse
+#[derive(Resource, Debug, Clone)]
+pub struct ViewState {
+    pub azimuth: f32, // degrees, direction shown at the top of the dome
+    pub zoom: f32,
+    pub fit_scale: f32, // logical pixels per scene unit at zoom 1, so the dome fits the window
+    pub target_zoom: f32, // zoom eases towards this
+    pub zoom_anchor: Option<Vec2>, // cursor offset from the window center to zoom around
+    pub pan: Vec2, // scene coordinates at the center of the window
+    pub follow_selected: bool,
+    pub drag_distance: f32, // farthest the cursor moved (pixels) from where the left button went down
+    pub show_stars: bool,
+    pub show_grid: bool,
+    pub show_trails: bool,
+    pub show_labels: bool,
+    pub show_help: bool,
+    pub loaded_config: Config,
+}
+
+impl ViewState {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            azimuth: config.user_azimuth,
+            zoom: 1.0,
+            fit_scale: 1.0,
+            target_zoom: 1.0,
+            zoom_anchor: None,
+            pan: Vec2::ZERO,
+            follow_selected: false,
+            drag_distance: 0.0,
+            show_stars: true,
+            show_grid: true,
+            show_trails: true,
+            show_labels: true,
+            show_help: false,
+            loaded_config: config.clone(),
+        }
+    }
+}
+
+/// A marker component for the azimuth grid lines, storing their unrotated angle (radians)
+#[derive(Component)]
+pub struct AzimuthLine {
+    pub angle: f32,
+}
+
+/// A text label fixed to a sky direction (radians) of the dome: compass letters and grid values
+#[derive(Component)]
+pub struct DomeLabel {
+    pub azimuth: f32,
+    pub elevation: f32,
+    pub scene_radius: f32,
+}
+
+/// Map a sky direction (azimuth, elevation in radians) to scene coordinates with the configured projection
+pub fn sky_to_scene(az: f64, el: f64, scene_radius: f32, view: &ViewState) -> Vec2 {
+    let az = az - (view.azimuth as f64).to_radians();
+    let (x, y) = view.loaded_config.sky_projection.project(az, el, scene_radius);
+    vec2(x, y)
+}
+
+pub fn apply_view_rotation(
+    view: Res<ViewState>,
+    mut line_query: Query<(&mut Transform, &AzimuthLine)>,
+    mut label_query: Query<(&mut Transform, &DomeLabel), Without<AzimuthLine>>,
+) {
+    if !view.is_changed() {
+        return;
+    }
+    let rotation = view.azimuth.to_radians();
+    for (mut transform, line) in &mut line_query {
+        transform.rotation = Quat::from_rotation_z(line.angle + rotation);
+    }
+    for (mut transform, label) in &mut label_query {
+        let position = sky_to_scene(label.azimuth as f64, label.elevation as f64, label.scene_radius, &view);
+        transform.translation = position.extend(transform.translation.z);
+        transform.rotation = Quat::from_rotation_z(rotation);
+    }
+}
+
+pub fn apply_layer_visibility(
+    view: Res<ViewState>,
+    mode: Res<ViewMode>,
+    mut star_query: Query<&mut Visibility, (With<Star>, Without<DomeLabel>, Without<Shape>)>,
+    mut grid_query: Query<&mut Visibility, Or<(With<Shape>, With<DomeLabel>)>>,
+) {
+    if !view.is_changed() && !mode.is_changed() {
+        return;
+    }
+    let dome = *mode == ViewMode::Dome;
+    let visibility = |shown: bool| if shown { Visibility::Inherited } else { Visibility::Hidden };
+    for mut star_visibility in &mut star_query {
+        *star_visibility = visibility(view.show_stars && dome);
+    }
+    for mut grid_visibility in &mut grid_query {
+        *grid_visibility = visibility(view.show_grid && dome);
+    }
+}
+
+impl ViewState {
+    /// Logical pixels per scene unit
+    pub fn pixels_per_unit(&self) -> f32 {
+        self.zoom * self.fit_scale
+    }
+}
+
+/// Scale that keeps something at a constant size on screen whatever the window size and zoom,
+/// sizes in the config are logical pixels (times `ui_scale`)
+pub fn readable_scale(view: &ViewState) -> f32 {
+    view.loaded_config.ui_scale / view.pixels_per_unit()
+}
+
+/// Fit the dome and its grid, plus a margin for labels, in the window when it's resized
+pub fn fit_to_window(windows: Query<&Window, (With<PrimaryWindow>, Changed<Window>)>, mut view: ResMut<ViewState>) {
+    let Ok(window) = windows.get_single() else { return };
+    let loaded_config = &view.loaded_config;
+    let extent = loaded_config.scene_radius.max(loaded_config.azimuth_lines_radius);
+    let available = window.width().min(window.height()) / 2.0 - loaded_config.scene_margin * loaded_config.ui_scale;
+    let fit_scale = (available / extent).max(0.05);
+    if view.fit_scale != fit_scale {
+        view.fit_scale = fit_scale;
+    }
+}
+
+fn clamp_pan(pan: Vec2, scene_radius: f32) -> Vec2 {
+    pan.clamp_length_max(scene_radius)
+}
+
+/// Zoom around the cursor with the mouse wheel
+pub fn zoom_with_mouse_wheel(mut events: EventReader<MouseWheel>, windows: Query<&Window, With<PrimaryWindow>>, mut view: ResMut<ViewState>) {
+    let mut notches = 0.0;
+    for event in events.read() {
+        notches += match event.unit {
+            MouseScrollUnit::Line => event.y,
+            MouseScrollUnit::Pixel => event.y / 40.0,
+        };
+    }
+    if notches == 0.0 {
+        return;
+    }
+    let loaded_config = &view.loaded_config;
+    let target_zoom = (view.target_zoom * loaded_config.zoom_wheel_step.powf(notches)).clamp(loaded_config.min_zoom, loaded_config.max_zoom);
+    let anchor = windows
+        .get_single()
+        .ok()
+        .and_then(|window| Some((window.cursor_position()?, window.size())))
+        .map(|(cursor, size)| vec2(cursor.x - size.x / 2.0, size.y / 2.0 - cursor.y));
+    view.target_zoom = target_zoom;
+    // Following keeps the satellite centered, so zoom around it instead of the cursor
+    view.zoom_anchor = if view.follow_selected { None } else { anchor };
+}
+
+/// Pan by dragging with the left button, leaving short clicks to the satellite picker
+pub fn drag_pan(
+    buttons: Res<ButtonInput<MouseButton>>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    mut view: ResMut<ViewState>,
+    mut press_point: Local<Option<Vec2>>,
+    mut last_cursor: Local<Option<Vec2>>,
+) {
+    let cursor = windows.get_single().ok().and_then(|window| window.cursor_position());
+    if buttons.just_pressed(MouseButton::Left) {
+        view.drag_distance = 0.0;
+        *press_point = cursor;
+        *last_cursor = cursor;
+        return;
+    }
+    if !buttons.pressed(MouseButton::Left) {
+        *press_point = None;
+        *last_cursor = None;
+        return;
+    }
+    let (Some(press), Some(previous), Some(cursor)) = (*press_point, *last_cursor, cursor) else {
+        *press_point = press_point.or(cursor);
+        *last_cursor = cursor;
+        return;
+    };
+    // Farthest the cursor got from where the button went down, so jitter doesn't add up to a drag
+    view.drag_distance = view.drag_distance.max((cursor - press).length());
+    if view.drag_distance <= DRAG_THRESHOLD {
+        return;
+    }
+    // The first panning frame catches up with everything moved since the press
+    let delta = cursor - previous;
+    *last_cursor = Some(cursor);
+    if delta != Vec2::ZERO {
+        let pan = view.pan + vec2(-delta.x, delta.y) / view.pixels_per_unit();
+        view.pan = clamp_pan(pan, view.loaded_config.scene_radius);
+        view.follow_selected = false;
+    }
+}
+
+/// Ease the zoom towards its target, keeping the scene point under the anchor in place
+pub fn smooth_zoom(time: Res<Time>, mut view: ResMut<ViewState>) {
+    if view.zoom == view.target_zoom {
+        return;
+    }
+    let t = 1.0 - (-view.loaded_config.view_smoothing * time.delta_secs()).exp();
+    let mut zoom = view.zoom + (view.target_zoom - view.zoom) * t;
+    if (zoom / view.target_zoom - 1.0).abs() < 0.001 {
+        zoom = view.target_zoom;
+    }
+    if let Some(anchor) = view.zoom_anchor {
+        let pan = view.pan + anchor / view.fit_scale * (1.0 / view.zoom - 1.0 / zoom);
+        view.pan = clamp_pan(pan, view.loaded_config.scene_radius);
+    }
+    view.zoom = zoom;
+    if zoom == view.target_zoom {
+        view.zoom_anchor = None;
+    }
+}
+
+/// Keep the selected satellite centered while following it
+pub fn follow_selected_satellite(
+    time: Res<Time>,
+    selected: Res<selection::SelectedSatellite>,
+    sat_query: Query<&Transform, With<Satellite>>,
+    mut view: ResMut<ViewState>,
+) {
+    if !view.follow_selected {
+        return;
+    }
+    let Some(Ok(transform)) = selected.0.map(|entity| sat_query.get(entity)) else { return };
+    let position = transform.translation.truncate();
+    // Satellites below the horizon are parked far away, stay where we are until AOS
+    if position.length() > view.loaded_config.scene_radius {
+        return;
+    }
+    ease_pan_towards(&mut view, position, time.delta_secs());
+}
+
+/// Move the view center a step towards a scene position
+pub fn ease_pan_towards(view: &mut ViewState, position: Vec2, dt: f32) {
+    if view.pan.distance(position) < 0.01 {
+        return;
+    }
+    let t = 1.0 - (-view.loaded_config.view_smoothing * dt).exp();
+    let pan = view.pan.lerp(position, t);
+    view.pan = clamp_pan(pan, view.loaded_config.scene_radius);
+}
+
+pub fn apply_zoom(view: Res<ViewState>, mut camera_query: Query<(&mut OrthographicProjection, &mut Transform), With<Camera2d>>) {
+    if !view.is_changed() {
+        return;
+    }
+    for (mut projection, mut transform) in &mut camera_query {
+        projection.scale = 1.0 / view.pixels_per_unit();
+        transform.translation.x = view.pan.x;
+        transform.translation.y = view.pan.y;
+    }
+}
+
+/// Counter-scale stars, satellites (and their labels) and the dome labels so they stay readable when zoomed
+pub fn apply_readable_scale(
+    view: Res<ViewState>,
+    mut scaled_query: Query<&mut Transform, Or<(With<Star>, With<Satellite>, With<DomeLabel>)>>,
+) {
+    if !view.is_changed() {
+        return;
+    }
+    let scale = Vec3::splat(readable_scale(&view));
+    for mut transform in &mut scaled_query {
+        if transform.scale != scale {
+            transform.scale = scale;
+        }
+    }
+}
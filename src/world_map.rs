@@ -0,0 +1,323 @@
+use bevy::{prelude::*, window::PrimaryWindow};
+use chrono::{DateTime, Duration, Utc};
+
+use crate::{clock, config, groups, hexstr2color, search, selection, sub_satellite_point, sun, view, Satellite};
+
+/// Coastlines as latitude and longitude (radians), shared by the world map and the globe
+#[derive(Resource, Default)]
+pub struct Coastlines {
+    pub lines: Vec<Vec<(f64, f64)>>,
+}
+
+/// Sub-satellite points and ground tracks in scene coordinates, refreshed as the simulated time moves
+#[derive(Resource, Default)]
+pub struct GroundTracks {
+    pub points_at: Option<DateTime<Utc>>,
+    pub points: Vec<(Entity, Vec2)>,
+    pub tracks_at: Option<DateTime<Utc>>,
+    pub tracks_selected: Option<Entity>,
+    pub tracks: Vec<(Entity, Vec<Vec2>)>,
+}
+
+/// Project a latitude and longitude (radians) on the configured world map
+pub fn geo_to_scene(lat: f64, lon: f64, loaded_config: &config::Config) -> Vec2 {
+    let (x, y) = loaded_config.map_projection.project(lat, lon, loaded_config.scene_radius);
+    Vec2::new(x, y)
+}
+
+fn push_coordinates(lines: &mut Vec<Vec<(f64, f64)>>, coordinates: &serde_json::Value) {
+    let Some(points) = coordinates.as_array() else { return };
+    let line: Vec<(f64, f64)> = points
+        .iter()
+        .filter_map(|point| {
+            let lon = point.get(0)?.as_f64()?;
+            let lat = point.get(1)?.as_f64()?;
+            Some((lat.to_radians(), lon.to_radians()))
+        })
+        .collect();
+    if line.len() > 1 {
+        lines.push(line);
+    }
+}
+
+/// Coastlines built into the binary, from the Natural Earth 1:110m countries without the borders they share
+const BUNDLED_COASTLINES: &str = include_str!("../assets/data/coastlines_110m.geojson");
+
+/// Line strings and polygon rings of a GeoJSON feature collection, None if it doesn't parse
+fn parse_coastlines(contents: &str) -> Option<Vec<Vec<(f64, f64)>>> {
+    let geojson: serde_json::Value = serde_json::from_str(contents).ok()?;
+    let mut lines = Vec::new();
+    for feature in geojson["features"].as_array().into_iter().flatten() {
+        let geometry = &feature["geometry"];
+        let coordinates = &geometry["coordinates"];
+        match geometry["type"].as_str() {
+            Some("LineString") => push_coordinates(&mut lines, coordinates),
+            Some("MultiLineString") | Some("Polygon") => {
+                for line in coordinates.as_array().into_iter().flatten() {
+                    push_coordinates(&mut lines, line);
+                }
+            }
+            Some("MultiPolygon") => {
+                for polygon in coordinates.as_array().into_iter().flatten() {
+                    for ring in polygon.as_array().into_iter().flatten() {
+                        push_coordinates(&mut lines, ring);
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+    Some(lines)
+}
+
+/// The bundled coastlines, or those of map_coastlines_path (e.g. Natural Earth's ne_50m_coastline.geojson) when it's set
+pub fn load_coastlines(loaded_config: &config::Config) -> Vec<Vec<(f64, f64)>> {
+    let bundled = || parse_coastlines(BUNDLED_COASTLINES).expect("couldn't parse the bundled coastlines");
+    if loaded_config.map_coastlines_path.is_empty() {
+        return bundled();
+    }
+    let Some(path) = config::resolve_data_path(&loaded_config.map_coastlines_path) else {
+        eprintln!("Coastlines file {} not found, using the bundled ones", loaded_config.map_coastlines_path);
+        return bundled();
+    };
+    let Ok(contents) = std::fs::read_to_string(&path) else {
+        eprintln!("Couldn't read coastlines from {}, using the bundled ones", path.display());
+        return bundled();
+    };
+    match parse_coastlines(&contents) {
+        Some(lines) => lines,
+        None => {
+            eprintln!("Couldn't parse coastlines from {}, using the bundled ones", path.display());
+            bundled()
+        }
+    }
+}
+
+pub fn setup_world_map(mut commands: Commands, loaded_config: Res<config::Config>) {
+    commands.insert_resource(Coastlines { lines: load_coastlines(&loaded_config) });
+}
+
+/// Split a track where it crosses the antimeridian so it isn't drawn across the whole map
+pub fn split_at_antimeridian(track: &[Vec2], scene_radius: f32) -> Vec<Vec<Vec2>> {
+    let mut segments: Vec<Vec<Vec2>> = vec![Vec::new()];
+    for point in track {
+        if segments.last().and_then(|segment| segment.last()).is_some_and(|last| (last.x - point.x).abs() > scene_radius) {
+            segments.push(Vec::new());
+        }
+        segments.last_mut().unwrap().push(*point);
+    }
+    segments
+}
+
+pub fn update_ground_tracks(
+    clock: Res<clock::SimClock>,
+    sat_query: Query<(Entity, &Satellite)>,
+    visibility: Res<groups::GroupVisibility>,
+    selected: Res<selection::SelectedSatellite>,
+    mut tracks: ResMut<GroundTracks>,
+) {
+    let now = clock.now();
+    let shown: Vec<(Entity, &Satellite)> = sat_query.iter().filter(|(_, sat)| !groups::is_satellite_hidden(&visibility, sat)).collect();
+    let stale = |at: Option<DateTime<Utc>>, seconds: i64| at.is_none_or(|at| (now - at).num_seconds().abs() >= seconds);
+    if stale(tracks.points_at, 1) || visibility.is_changed() {
+        tracks.points = shown
+            .iter()
+            .filter_map(|(entity, sat)| {
+                let (lat, lon, _) = sub_satellite_point(&sat.history, now)?;
+                Some((*entity, geo_to_scene(lat, lon, &sat.loaded_config)))
+            })
+            .collect();
+        tracks.points_at = Some(now);
+    }
+    let Some((_, any_sat)) = shown.first() else {
+        tracks.tracks.clear();
+        return;
+    };
+    let loaded_config = &any_sat.loaded_config;
+    if !stale(tracks.tracks_at, loaded_config.map_track_step_seconds) && tracks.tracks_selected == selected.0 && !visibility.is_changed() {
+        return;
+    }
+    let tracked: Vec<&(Entity, &Satellite)> = if shown.len() <= loaded_config.map_max_tracks {
+        shown.iter().collect()
+    } else {
+        shown.iter().filter(|(entity, _)| selected.0 == Some(*entity)).collect()
+    };
+    let step = loaded_config.map_track_step_seconds.max(1);
+    let start = now - Duration::minutes(loaded_config.map_track_past_minutes);
+    let steps = (loaded_config.map_track_past_minutes + loaded_config.map_track_future_minutes) * 60 / step;
+    tracks.tracks = tracked
+        .iter()
+        .map(|(entity, sat)| {
+            let track = (0..=steps)
+                .filter_map(|i| {
+                    let (lat, lon, _) = sub_satellite_point(&sat.history, start + Duration::seconds(i * step))?;
+                    Some(geo_to_scene(lat, lon, &sat.loaded_config))
+                })
+                .collect();
+            (*entity, track)
+        })
+        .collect();
+    tracks.tracks_at = Some(now);
+    tracks.tracks_selected = selected.0;
+}
+
+/// Hover the sub-satellite point closest to the cursor and select it on click, like `selection::pick_satellites` on the dome
+pub fn pick_sub_points(
+    windows: Query<&Window, With<PrimaryWindow>>,
+    camera_query: Query<(&Camera, &GlobalTransform), With<Camera2d>>,
+    sat_query: Query<&Satellite>,
+    tracks: Res<GroundTracks>,
+    buttons: Res<ButtonInput<MouseButton>>,
+    view: Res<view::ViewState>,
+    mut hovered: ResMut<selection::HoveredSatellite>,
+    mut selected: ResMut<selection::SelectedSatellite>,
+) {
+    let Ok(window) = windows.get_single() else { return };
+    let Ok((camera, camera_transform)) = camera_query.get_single() else { return };
+    let cursor = window
+        .cursor_position()
+        .and_then(|cursor| camera.viewport_to_world_2d(camera_transform, cursor).ok());
+    let mut closest = None;
+    if let Some(cursor) = cursor {
+        let mut closest_distance = f32::MAX;
+        for (entity, point) in &tracks.points {
+            let Ok(sat) = sat_query.get(*entity) else { continue };
+            let distance = point.distance(cursor);
+            if distance < sat.loaded_config.pick_radius.max(sat.style.size) * view::readable_scale(&view) && distance < closest_distance {
+                closest_distance = distance;
+                closest = Some(*entity);
+            }
+        }
+    }
+    if hovered.0 != closest {
+        hovered.0 = closest;
+    }
+    if buttons.just_released(MouseButton::Left) && view.drag_distance <= view::DRAG_THRESHOLD {
+        selected.0 = closest;
+    }
+}
+
+/// Keep the selected sub-satellite point centered while following it
+pub fn follow_selected_sub_point(time: Res<Time>, selected: Res<selection::SelectedSatellite>, tracks: Res<GroundTracks>, mut view: ResMut<view::ViewState>) {
+    if !view.follow_selected {
+        return;
+    }
+    let Some(entity) = selected.0 else { return };
+    if let Some((_, point)) = tracks.points.iter().find(|(e, _)| *e == entity) {
+        view::ease_pan_towards(&mut view, *point, time.delta_secs());
+    }
+}
+
+pub fn draw_world_map(
+    coastlines: Res<Coastlines>,
+    tracks: Res<GroundTracks>,
+    clock: Res<clock::SimClock>,
+    view: Res<view::ViewState>,
+    sat_query: Query<&Satellite>,
+    hovered: Res<selection::HoveredSatellite>,
+    selected: Res<selection::SelectedSatellite>,
+    search: Res<search::SearchState>,
+    mut gizmos: Gizmos,
+) {
+    let loaded_config = &view.loaded_config;
+    let scale = view::readable_scale(&view);
+    if view.show_grid {
+        let graticule_color = hexstr2color(&loaded_config.map_graticule_color);
+        for lon in (-180..=180).step_by(30) {
+            gizmos.linestrip_2d(
+                (-85..=85).step_by(5).map(|lat| geo_to_scene((lat as f64).to_radians(), (lon as f64).to_radians(), loaded_config)),
+                graticule_color,
+            );
+        }
+        for lat in (-60..=60).step_by(30) {
+            gizmos.linestrip_2d(
+                (-180..=180).step_by(5).map(|lon| geo_to_scene((lat as f64).to_radians(), (lon as f64).to_radians(), loaded_config)),
+                graticule_color,
+            );
+        }
+    }
+    let coastline_color = hexstr2color(&loaded_config.map_coastline_color);
+    for line in &coastlines.lines {
+        let line: Vec<Vec2> = line.iter().map(|(lat, lon)| geo_to_scene(*lat, *lon, loaded_config)).collect();
+        for segment in split_at_antimeridian(&line, loaded_config.scene_radius) {
+            gizmos.linestrip_2d(segment, coastline_color);
+        }
+    }
+
+    let now = clock.now();
+    let subsolar = sun::subsolar_point(now);
+    let terminator_color = hexstr2color(&loaded_config.map_terminator_color);
+    gizmos.linestrip_2d(
+        (-180..=180).map(|lon| {
+            let lon = (lon as f64).to_radians();
+            geo_to_scene(sun::terminator_latitude(subsolar, lon), lon, loaded_config)
+        }),
+        terminator_color,
+    );
+    gizmos.circle_2d(Isometry2d::from_translation(geo_to_scene(subsolar.0, subsolar.1, loaded_config)), 8.0 * scale, terminator_color);
+
+    let observer = geo_to_scene(loaded_config.user_latitude.to_radians(), loaded_config.user_longitude.to_radians(), loaded_config);
+    let observer_color = hexstr2color(&loaded_config.map_observer_color);
+    gizmos.line_2d(observer - Vec2::X * 6.0 * scale, observer + Vec2::X * 6.0 * scale, observer_color);
+    gizmos.line_2d(observer - Vec2::Y * 6.0 * scale, observer + Vec2::Y * 6.0 * scale, observer_color);
+
+    if view.show_trails {
+        for (entity, track) in &tracks.tracks {
+            let Ok(sat) = sat_query.get(*entity) else { continue };
+            let color = if selected.0 == Some(*entity) { hexstr2color(&sat.loaded_config.selection_color) } else { hexstr2color(&sat.style.trail_color) };
+            for segment in split_at_antimeridian(track, loaded_config.scene_radius) {
+                gizmos.linestrip_2d(segment, color);
+            }
+        }
+    }
+    for (entity, point) in &tracks.points {
+        let Ok(sat) = sat_query.get(*entity) else { continue };
+        let isometry = Isometry2d::from_translation(*point);
+        gizmos.circle_2d(isometry, sat.style.size * scale, hexstr2color(&sat.style.color)).resolution(8);
+        let ring_scale = if selected.0 == Some(*entity) {
+            Some(3.0)
+        } else if hovered.0 == Some(*entity) {
+            Some(2.0)
+        } else if search.active && search.results.contains(entity) {
+            Some(2.5)
+        } else {
+            None
+        };
+        if let Some(ring_scale) = ring_scale {
+            gizmos.circle_2d(isometry, sat.style.size * ring_scale * scale, hexstr2color(&sat.loaded_config.selection_color));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bundled_coastlines_parse() {
+        let lines = parse_coastlines(BUNDLED_COASTLINES).unwrap();
+        assert!(lines.len() > 100);
+        for &(lat, lon) in lines.iter().flatten() {
+            assert!(lat.abs() <= std::f64::consts::FRAC_PI_2 && lon.abs() <= std::f64::consts::PI);
+        }
+    }
+
+    #[test]
+    fn reads_line_strings_and_polygon_rings() {
+        let geojson = r#"{"type": "FeatureCollection", "features": [
+            {"type": "Feature", "geometry": {"type": "LineString", "coordinates": [[0, 0], [90, 45]]}},
+            {"type": "Feature", "geometry": {"type": "MultiPolygon", "coordinates": [[[[0, 0], [1, 0], [1, 1], [0, 0]]], [[[5, 5], [6, 5], [5, 5]]]]}},
+            {"type": "Feature", "geometry": {"type": "Point", "coordinates": [0, 0]}}
+        ]}"#;
+        let lines = parse_coastlines(geojson).unwrap();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], vec![(0.0, 0.0), (45f64.to_radians(), 90f64.to_radians())]);
+        assert!(parse_coastlines("<html>404</html>").is_none());
+    }
+
+    #[test]
+    fn missing_coastlines_file_falls_back_to_the_bundled_ones() {
+        let config = config::Config { map_coastlines_path: "/nonexistent/coastlines.geojson".to_string(), ..Default::default() };
+        assert_eq!(load_coastlines(&config).len(), parse_coastlines(BUNDLED_COASTLINES).unwrap().len());
+    }
+}